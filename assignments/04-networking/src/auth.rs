@@ -0,0 +1,113 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::error::AspirinEatsError;
+use crate::http::HttpRequest;
+
+/// What a caller is allowed to do. Each role can do everything the roles before it
+/// can, so the kitchen can place orders and an admin can do anything
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    Customer,
+    Kitchen,
+    Admin,
+}
+
+impl Role {
+    /// Whether a caller with this role may use a route that requires `required`
+    pub fn allows(self, required: Role) -> bool {
+        self >= required
+    }
+}
+
+/// Who is making a request, from the credential their token belongs to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Caller {
+    pub name: String,
+    pub role: Role,
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Customer => "customer",
+            Role::Kitchen => "kitchen",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+impl FromStr for Role {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [Role::Customer, Role::Kitchen, Role::Admin]
+            .into_iter()
+            .find(|role| role.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| AspirinEatsError::ParseError(format!("unknown role: {:?}", s)))
+    }
+}
+
+/// The token from a request's `Authorization: Bearer <token>` header
+pub fn bearer_token(request: &HttpRequest) -> Option<&str> {
+    let (scheme, token) = request.header("Authorization")?.trim().split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("Bearer") && !token.is_empty()).then_some(token)
+}
+
+/// A new random token to hand to a caller
+pub fn new_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// How a token is kept in the credentials table, so that reading the database
+/// doesn't give away working tokens
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(authorization: &str) -> HttpRequest {
+        let raw = format!("GET / HTTP/1.1\r\nAuthorization: {}\r\n\r\n", authorization);
+        HttpRequest::from_str(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        assert_eq!(bearer_token(&request("Bearer abc123")), Some("abc123"));
+        assert_eq!(bearer_token(&request("bearer  abc123 ")), Some("abc123"));
+        assert_eq!(bearer_token(&request("Basic YWxhZGRpbjpvcGVu")), None);
+        assert_eq!(bearer_token(&request("Bearer")), None);
+        let anonymous = HttpRequest::from_str("GET / HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(bearer_token(&anonymous), None);
+    }
+
+    #[test]
+    fn test_roles() {
+        assert!(Role::Admin.allows(Role::Kitchen));
+        assert!(Role::Kitchen.allows(Role::Customer));
+        assert!(Role::Customer.allows(Role::Customer));
+        assert!(!Role::Customer.allows(Role::Kitchen));
+        assert!(!Role::Kitchen.allows(Role::Admin));
+        assert_eq!("Admin".parse::<Role>().unwrap(), Role::Admin);
+        assert!("chef".parse::<Role>().is_err());
+    }
+
+    #[test]
+    fn test_hash_token() {
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(new_token(), new_token());
+    }
+}
@@ -0,0 +1,311 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::AspirinEatsError;
+
+/// How the next origin is chosen from the healthy ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Strategy {
+    /// Each origin in turn
+    RoundRobin,
+    /// The origin with the fewest connections open through the proxy
+    LeastConnections,
+    /// Each origin in turn, as many times as its weight, spread out so that a
+    /// heavy origin isn't given all of its turns in a row
+    Weighted,
+}
+
+impl FromStr for Strategy {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Strategy::RoundRobin),
+            "least-connections" => Ok(Strategy::LeastConnections),
+            "weighted" => Ok(Strategy::Weighted),
+            _ => Err(AspirinEatsError::ParseError(format!(
+                "unknown strategy: {:?}",
+                s
+            ))),
+        }
+    }
+}
+
+/// One origin the proxy can send connections to
+#[derive(Debug)]
+pub struct Upstream {
+    pub addr: String,
+    pub weight: u32,
+    healthy: AtomicBool,
+    connections: AtomicUsize,
+}
+
+impl Upstream {
+    /// Whether the origin is in rotation. Origins start out healthy
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    /// How many connections are open to the origin through the proxy
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = AspirinEatsError;
+
+    /// Parse an address with an optional weight, such as `127.0.0.1:8081=3`. The
+    /// weight defaults to 1
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, weight) = match s.rsplit_once('=') {
+            Some((addr, weight)) => {
+                let weight = weight
+                    .parse()
+                    .ok()
+                    .filter(|weight| *weight > 0)
+                    .ok_or_else(|| {
+                        AspirinEatsError::ParseError(format!("invalid weight: {:?}", s))
+                    })?;
+                (addr, weight)
+            }
+            None => (s, 1),
+        };
+        Ok(Upstream {
+            addr: addr.to_string(),
+            weight,
+            healthy: AtomicBool::new(true),
+            connections: AtomicUsize::new(0),
+        })
+    }
+}
+
+/// Spreads connections over a pool of origins, leaving out the ones marked
+/// unhealthy
+#[derive(Debug)]
+pub struct Balancer {
+    upstreams: Vec<Upstream>,
+    strategy: Strategy,
+    /// Where the next round-robin turn starts
+    next: AtomicUsize,
+    /// How far each origin is owed a turn, for `Strategy::Weighted`
+    credits: Mutex<Vec<i64>>,
+}
+
+/// A connection counted against an origin until it is dropped
+#[derive(Debug)]
+pub struct Lease<'a> {
+    pub index: usize,
+    pub upstream: &'a Upstream,
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        self.upstream.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Balancer {
+    pub fn new(upstreams: Vec<Upstream>, strategy: Strategy) -> Self {
+        let credits = Mutex::new(vec![0; upstreams.len()]);
+        Balancer {
+            upstreams,
+            strategy,
+            next: AtomicUsize::new(0),
+            credits,
+        }
+    }
+
+    pub fn upstreams(&self) -> &[Upstream] {
+        &self.upstreams
+    }
+
+    /// Put the origin at `index` in or out of rotation. Returns whether that changed
+    /// anything
+    pub fn set_healthy(&self, index: usize, healthy: bool) -> bool {
+        self.upstreams[index]
+            .healthy
+            .swap(healthy, Ordering::SeqCst)
+            != healthy
+    }
+
+    /// Choose an origin for a new connection, leaving out those in `skip`, or
+    /// `None` if no healthy origin is left
+    pub fn pick(&self, skip: &[usize]) -> Option<Lease<'_>> {
+        let candidates: Vec<usize> = (0..self.upstreams.len())
+            .filter(|index| self.upstreams[*index].is_healthy() && !skip.contains(index))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let index = match self.strategy {
+            Strategy::RoundRobin => {
+                candidates[self.next.fetch_add(1, Ordering::SeqCst) % candidates.len()]
+            }
+            Strategy::LeastConnections => {
+                // Start from a different origin each time, so ties are shared out
+                let start = self.next.fetch_add(1, Ordering::SeqCst) % candidates.len();
+                let rotated = candidates[start..].iter().chain(&candidates[..start]);
+                *rotated.min_by_key(|index| self.upstreams[**index].connections())?
+            }
+            Strategy::Weighted => self.weighted(&candidates),
+        };
+        let upstream = &self.upstreams[index];
+        upstream.connections.fetch_add(1, Ordering::SeqCst);
+        Some(Lease { index, upstream })
+    }
+
+    /// Smooth weighted round-robin: every candidate earns its weight in credit, and
+    /// the one with the most credit goes next and pays back the total
+    fn weighted(&self, candidates: &[usize]) -> usize {
+        let mut credits = match self.credits.lock() {
+            Ok(credits) => credits,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut total = 0;
+        let mut best = candidates[0];
+        for &index in candidates {
+            let weight = i64::from(self.upstreams[index].weight);
+            credits[index] += weight;
+            total += weight;
+            if credits[index] > credits[best] {
+                best = index;
+            }
+        }
+        credits[best] -= total;
+        best
+    }
+}
+
+/// Whether the origin at `addr` answers `GET /` with a 2xx status within `timeout`
+pub fn probe(addr: &str, timeout: Duration) -> bool {
+    let status = || -> std::io::Result<Option<u16>> {
+        let Some(socket_addr) = addr.to_socket_addrs()?.next() else {
+            return Ok(None);
+        };
+        let mut stream = TcpStream::connect_timeout(&socket_addr, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        write!(
+            stream,
+            "GET / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            addr
+        )?;
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        Ok(status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok()))
+    };
+    matches!(status(), Ok(Some(200..=299)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn balancer(upstreams: &[&str], strategy: Strategy) -> Balancer {
+        let upstreams = upstreams.iter().map(|s| s.parse().unwrap()).collect();
+        Balancer::new(upstreams, strategy)
+    }
+
+    /// The origins chosen for `n` connections, each closed before the next opens
+    fn picks(balancer: &Balancer, n: usize) -> Vec<usize> {
+        (0..n).map(|_| balancer.pick(&[]).unwrap().index).collect()
+    }
+
+    #[test]
+    fn test_parse_upstreams() {
+        let upstream: Upstream = "127.0.0.1:8081=3".parse().unwrap();
+        assert_eq!(
+            (upstream.addr.as_str(), upstream.weight),
+            ("127.0.0.1:8081", 3)
+        );
+        let upstream: Upstream = "localhost:8080".parse().unwrap();
+        assert_eq!(upstream.weight, 1);
+        assert!("127.0.0.1:8081=0".parse::<Upstream>().is_err());
+        assert!("127.0.0.1:8081=x".parse::<Upstream>().is_err());
+        assert!("random".parse::<Strategy>().is_err());
+    }
+
+    #[test]
+    fn test_round_robin_skips_unhealthy() {
+        let balancer = balancer(&["a:1", "b:1", "c:1"], Strategy::RoundRobin);
+        assert_eq!(picks(&balancer, 4), vec![0, 1, 2, 0]);
+        assert!(balancer.set_healthy(1, false));
+        assert!(!balancer.set_healthy(1, false));
+        assert!(!picks(&balancer, 4).contains(&1));
+        assert_eq!(balancer.pick(&[0]).unwrap().index, 2);
+
+        balancer.set_healthy(0, false);
+        balancer.set_healthy(2, false);
+        assert!(balancer.pick(&[]).is_none());
+        balancer.set_healthy(1, true);
+        assert_eq!(picks(&balancer, 2), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_least_connections() {
+        let balancer = balancer(&["a:1", "b:1", "c:1"], Strategy::LeastConnections);
+        let first = balancer.pick(&[]).unwrap();
+        let second = balancer.pick(&[]).unwrap();
+        let third = balancer.pick(&[]).unwrap();
+        let mut indexes = vec![first.index, second.index, third.index];
+        indexes.sort_unstable();
+        assert_eq!(indexes, vec![0, 1, 2]);
+
+        let freed = second.index;
+        drop(second);
+        assert_eq!(balancer.pick(&[]).unwrap().index, freed);
+        assert_eq!(balancer.upstreams()[first.index].connections(), 1);
+    }
+
+    #[test]
+    fn test_weighted_spreads_turns() {
+        let balancer = balancer(&["a:1=5", "b:1", "c:1"], Strategy::Weighted);
+        assert_eq!(picks(&balancer, 7), vec![0, 0, 1, 0, 2, 0, 0]);
+        // A heavy origin going down doesn't starve the others
+        balancer.set_healthy(0, false);
+        assert_eq!(picks(&balancer, 4), vec![1, 2, 1, 2]);
+    }
+
+    #[test]
+    fn test_probe() {
+        let answer = |response: &'static str| {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                // Closing with the request still unread would reset the connection
+                // before the probe reads the answer
+                let mut request = String::new();
+                let mut reader = BufReader::new(&stream);
+                while reader.read_line(&mut request).unwrap_or(0) > 2 {
+                    request.clear();
+                }
+                let _ = stream.write_all(response.as_bytes());
+            });
+            addr
+        };
+        let timeout = Duration::from_secs(1);
+        assert!(probe(&answer("HTTP/1.1 200 OK\r\n\r\n"), timeout));
+        assert!(!probe(
+            &answer("HTTP/1.1 500 Internal Server Error\r\n\r\n"),
+            timeout
+        ));
+        assert!(!probe(&answer(""), timeout));
+
+        // Nothing listening
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert!(!probe(&closed.to_string(), timeout));
+    }
+}
@@ -1,20 +1,77 @@
-use aspirin_eats::db::AspirinEatsDb;
+use aspirin_eats::auth::Role;
+use aspirin_eats::db::{AspirinEatsDb, OrderQuery};
 use aspirin_eats::error::AspirinEatsError;
-use aspirin_eats::food::{Order, OrderRequest};
-use aspirin_eats::http::{HttpRequest, HttpResponse};
-use std::io::{Read, Write};
-use std::net::TcpListener;
+use aspirin_eats::food::{MenuEntry, Order, OrderRequest, StatusUpdate};
+use aspirin_eats::http::{HttpRequest, HttpResponse, Limits};
+use aspirin_eats::menu::{Menu, MenuUpdate};
+use aspirin_eats::router::{Params, Router};
+use aspirin_eats::workers::{Load, WorkerPool};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long connections are kept open, and for how many requests
+const KEEP_ALIVE: KeepAlive = KeepAlive {
+    idle_timeout: Duration::from_secs(5),
+    request_timeout: Duration::from_secs(10),
+    max_requests: 100,
+};
+
+/// How many connections are served at once. As many again can wait for a worker
+/// before new connections are left waiting to be accepted
+const WORKERS: usize = 16;
+
+/// How often a connection waiting for its next request checks whether others are
+/// waiting for its worker
+const IDLE_CHECK: Duration = Duration::from_millis(100);
 
 fn main() -> Result<(), AspirinEatsError> {
     const DB_PATH: &str = "aspirin_eats.db";
     let db = AspirinEatsDb::from_path(DB_PATH)?;
 
-    let listener = TcpListener::bind("127.0.0.1:8080")?;
-    println!("Server listening on 127.0.0.1:8080");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let addr = match parse_args(&args) {
+        Ok(Command::Serve(addr)) => addr,
+        Ok(Command::AddToken(name, role)) => {
+            let token = db.add_credential(&name, role)?;
+            println!("{}", token);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!("Usage: origin [<listen-addr> | add-token <name> <customer|kitchen|admin>]");
+            std::process::exit(2);
+        }
+    };
+
+    // Prices come from the menu file the first time an entry is seen; after that the
+    // admin API changes them
+    const MENU_PATH: &str = "menu.json";
+    let menu = match std::fs::read_to_string(MENU_PATH) {
+        Ok(config) => Menu::from_config(&config)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => Menu::default(),
+        Err(e) => return Err(e.into()),
+    };
+    db.seed_menu(&menu)?;
+
+    // A new database has no tokens, which would lock everyone out of the protected
+    // routes, so the first start hands out an admin token to create the rest with. It
+    // goes to stderr, once, so that it stays out of logs collected from stdout
+    if !db.has_credentials()? {
+        let token = db.add_credential("admin", Role::Admin)?;
+        eprintln!("Created the first admin token: {}", token);
+    }
+
+    let router = Arc::new(router());
+    let workers = WorkerPool::new(WORKERS, WORKERS);
+
+    let listener = TcpListener::bind(&addr)?;
+    println!("Server listening on {}", addr);
 
     for stream in listener.incoming() {
-        let mut stream = match stream {
+        let stream = match stream {
             Ok(s) => s,
             Err(e) => {
                 eprintln!("Failed to accept connection: {}", e);
@@ -22,95 +79,488 @@ fn main() -> Result<(), AspirinEatsError> {
             }
         };
 
-        let mut buffer = [0; 1024];
-        let bytes_read = match stream.read(&mut buffer) {
-            Ok(br) => br,
-            Err(e) => {
-                eprintln!("Failed to read from stream: {}", e);
-                continue;
+        let router = Arc::clone(&router);
+        let db = db.clone();
+        let load = workers.load();
+        workers.execute(move || {
+            if let Err(e) = serve_connection(stream, &router, &db, &KEEP_ALIVE, &load) {
+                eprintln!("Connection error: {}", e);
             }
-        };
-        let request_str = String::from_utf8_lossy(&buffer[..bytes_read]).to_string();
+        });
+    }
 
-        let request = match HttpRequest::from_str(&request_str) {
-            Ok(req) => req,
-            Err(_) => {
-                let response: HttpResponse = AspirinEatsError::InvalidRequest.into();
-                stream.write_all(response.to_string().as_bytes())?;
-                continue;
+    Ok(())
+}
+
+/// What the command line asks for
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Serve the API on an address
+    Serve(String),
+    /// Hand out a token instead of serving
+    AddToken(String, Role),
+}
+
+/// `origin add-token <name> <role>` hands out a token; otherwise the only argument
+/// is the address to listen on. Any number of origins can serve from the same
+/// database on different addresses
+fn parse_args(args: &[String]) -> Result<Command, AspirinEatsError> {
+    match args {
+        [command, rest @ ..] if command == "add-token" => match rest {
+            [name, role] => Ok(Command::AddToken(name.clone(), role.parse()?)),
+            _ => Err(AspirinEatsError::ParseError(
+                "add-token takes a name and a role".to_string(),
+            )),
+        },
+        [] => Ok(Command::Serve("127.0.0.1:8080".to_string())),
+        [addr] => Ok(Command::Serve(addr.clone())),
+        _ => Err(AspirinEatsError::ParseError(
+            "too many arguments".to_string(),
+        )),
+    }
+}
+
+/// How long a connection is kept open, and for how many requests
+#[derive(Debug, Clone, Copy)]
+struct KeepAlive {
+    /// How long a connection may wait for its next request to start before it is
+    /// closed
+    idle_timeout: Duration,
+    /// How long a request that has started may go without sending anything more
+    /// before it is answered with a 408
+    request_timeout: Duration,
+    /// How many requests one connection may make before it is closed
+    max_requests: usize,
+}
+
+/// Answer the requests on one connection in the order they arrive, including any
+/// the client sent without waiting for earlier responses. The connection is closed
+/// when the client asks, after `policy.max_requests` requests, after a malformed or stalled
+/// request, once no request has started for `policy.idle_timeout`, or after any response
+/// sent while other connections are waiting for a worker. A connection with no request
+/// in flight is also closed as soon as others are waiting, so that clients holding
+/// connections open cannot keep every worker from new ones
+fn serve_connection(
+    mut stream: TcpStream,
+    router: &Router<AspirinEatsDb>,
+    db: &AspirinEatsDb,
+    policy: &KeepAlive,
+    load: &Load,
+) -> Result<(), AspirinEatsError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    for served in 1..=policy.max_requests {
+        if !request_started(&stream, &mut reader, policy.idle_timeout, load)? {
+            return Ok(());
+        }
+
+        stream.set_read_timeout(Some(policy.request_timeout))?;
+        let (response, keep_alive) = match HttpRequest::read_from(&mut reader, &Limits::default()) {
+            Ok(Some(request)) => {
+                let keep_alive =
+                    request.keep_alive() && served < policy.max_requests && !load.is_saturated();
+                (router.handle(&request, db), keep_alive)
             }
+            // Nothing but blank lines before the client closed the connection
+            Ok(None) => return Ok(()),
+            Err(AspirinEatsError::Io(e)) if timed_out(&e) => (
+                HttpResponse::new(408, "Request Timeout", "Request timed out"),
+                false,
+            ),
+            Err(e) => (e.into(), false),
         };
-
-        let response = handle_request(request, &db);
-        if let Err(e) = stream.write_all(response.to_string().as_bytes()) {
-            eprintln!("Failed to write to stream: {}", e);
+        let response = if keep_alive {
+            let remaining = policy.max_requests - served;
+            response
+                .with_header("Connection", "keep-alive")
+                .with_header(
+                    "Keep-Alive",
+                    &format!(
+                        "timeout={}, max={}",
+                        policy.idle_timeout.as_secs(),
+                        remaining
+                    ),
+                )
+        } else {
+            response.with_header("Connection", "close")
+        };
+        stream.write_all(response.to_string().as_bytes())?;
+        if !keep_alive {
+            break;
         }
     }
-
     Ok(())
 }
 
-fn handle_request(request: HttpRequest, db: &AspirinEatsDb) -> HttpResponse {
-    match (request.method.as_deref(), request.path.as_deref()) {
-        (Some("GET"), Some("/")) | (Some("GET"), None) => {
-            HttpResponse::new(200, "OK", "Welcome to Aspirin Eats!")
+/// Wait for the first byte of the next request. Returns false if the client closed the
+/// connection, let it sit idle for `idle_timeout`, or is sitting idle while other
+/// connections wait for a worker
+fn request_started(
+    stream: &TcpStream,
+    reader: &mut BufReader<TcpStream>,
+    idle_timeout: Duration,
+    load: &Load,
+) -> Result<bool, AspirinEatsError> {
+    let deadline = Instant::now() + idle_timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
         }
-        (Some("GET"), Some("/orders")) => match db.get_all_orders() {
-            Ok(orders) => {
-                let body = serde_json::to_string(&orders).unwrap_or_else(|_| "[]".to_string());
-                HttpResponse::new(200, "OK", &body)
-            }
-            Err(e) => AspirinEatsError::from(e).into(),
-        },
-        (Some("GET"), Some(path)) if path.starts_with("/orders/") => {
-            let id_str = path.trim_start_matches("/orders/");
-            match id_str.parse::<i64>() {
-                Ok(id) => match db.get_order(id) {
-                    Ok(Some(order)) => {
-                        let body = serde_json::to_string(&order).unwrap_or_default();
-                        HttpResponse::new(200, "OK", &body)
-                    }
-                    Ok(None) => HttpResponse::new(404, "Not Found", "Order not found"),
-                    Err(e) => AspirinEatsError::from(e).into(),
-                },
-                Err(_) => HttpResponse::new(400, "Bad Request", "Invalid order ID"),
-            }
+        stream.set_read_timeout(Some(remaining.min(IDLE_CHECK)))?;
+        match reader.fill_buf() {
+            Ok([]) => return Ok(false),
+            Ok(_) => return Ok(true),
+            Err(e) if timed_out(&e) && load.is_saturated() => return Ok(false),
+            Err(e) if timed_out(&e) => {}
+            Err(e) => return Err(e.into()),
         }
-        (Some("POST"), Some("/orders")) => {
-            if let Some(body) = request.body {
-                match serde_json::from_str::<OrderRequest>(&body) {
-                    Ok(order_request) => {
-                        let order = Order::from(order_request);
-                        match db.add_order(order) {
-                            Ok(id) => {
-                                let added_order = db.get_order(id).unwrap().unwrap();
-                                let body = serde_json::to_string(&added_order).unwrap_or_default();
-                                HttpResponse::new(201, "Created", &body)
-                            }
-                            Err(e) => AspirinEatsError::from(e).into(),
-                        }
-                    }
-                    Err(_) => HttpResponse::new(400, "Bad Request", "Invalid order data"),
-                }
-            } else {
-                HttpResponse::new(400, "Bad Request", "Missing request body")
-            }
+    }
+}
+
+/// Whether a read failed because its timeout ran out
+fn timed_out(error: &std::io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Every endpoint of the API, and who may use it. Callers show who they are with
+/// `Authorization: Bearer <token>`, using a token from `origin add-token`
+fn router() -> Router<AspirinEatsDb> {
+    Router::new()
+        .authenticate_with(|request, db: &AspirinEatsDb| db.authenticate(request))
+        .route("GET", "/", welcome)
+        .route("GET", "/menu", get_menu)
+        .route_with_role("PATCH", "/menu/:item", Role::Admin, update_menu_item)
+        .route_with_role("GET", "/orders", Role::Kitchen, list_orders)
+        .route_with_role("POST", "/orders", Role::Customer, add_order)
+        .route_with_role("DELETE", "/orders", Role::Admin, delete_orders)
+        .route_with_role("GET", "/orders/:id", Role::Customer, get_order)
+        .route_with_role("PATCH", "/orders/:id", Role::Kitchen, update_order_status)
+        .route_with_role("DELETE", "/orders/:id", Role::Admin, delete_order)
+}
+
+type HandlerResult = Result<HttpResponse, AspirinEatsError>;
+
+fn welcome(_: &HttpRequest, _: &Params, _: &AspirinEatsDb) -> HandlerResult {
+    Ok(HttpResponse::new(200, "OK", "Welcome to Aspirin Eats!"))
+}
+
+fn get_menu(_: &HttpRequest, _: &Params, db: &AspirinEatsDb) -> HandlerResult {
+    let body = serde_json::to_string(&db.get_menu()?).unwrap_or_default();
+    Ok(HttpResponse::new(200, "OK", &body))
+}
+
+fn update_menu_item(request: &HttpRequest, params: &Params, db: &AspirinEatsDb) -> HandlerResult {
+    let entry: MenuEntry = params.get("item")?;
+    let Some(body) = &request.body else {
+        return Ok(HttpResponse::new(
+            400,
+            "Bad Request",
+            "Missing request body",
+        ));
+    };
+    let Ok(update) = serde_json::from_str::<MenuUpdate>(body) else {
+        return Ok(HttpResponse::new(400, "Bad Request", "Invalid menu update"));
+    };
+    let listing = db.update_menu_item(entry, &update)?;
+    let body = serde_json::to_string(&listing).unwrap_or_default();
+    Ok(HttpResponse::new(200, "OK", &body))
+}
+
+fn list_orders(request: &HttpRequest, _: &Params, db: &AspirinEatsDb) -> HandlerResult {
+    let query = OrderQuery::from_params(&request.query)?;
+    let page = db.get_orders(&query)?;
+    let body = serde_json::to_string(&page).unwrap_or_default();
+    Ok(HttpResponse::new(200, "OK", &body))
+}
+
+fn get_order(_: &HttpRequest, params: &Params, db: &AspirinEatsDb) -> HandlerResult {
+    let caller = params.caller()?;
+    match db.get_order(params.get("id")?)? {
+        // Customers only see their own orders. Anyone else's look like they don't
+        // exist, so order IDs can't be probed
+        Some(order) if caller.role.allows(Role::Kitchen) || order.customer == caller.name => {
+            let body = serde_json::to_string(&order).unwrap_or_default();
+            Ok(HttpResponse::new(200, "OK", &body))
         }
-        (Some("DELETE"), Some("/orders")) => match db.reset_orders() {
-            Ok(_) => HttpResponse::new(200, "OK", "All orders deleted"),
-            Err(e) => AspirinEatsError::from(e).into(),
-        },
-        (Some("DELETE"), Some(path)) if path.starts_with("/orders/") => {
-            let id_str = path.trim_start_matches("/orders/");
-            match id_str.parse::<i64>() {
-                Ok(id) => match db.remove_order(id) {
-                    Ok(_) => HttpResponse::new(200, "OK", "Order deleted"),
-                    Err(e) => AspirinEatsError::from(e).into(),
-                },
-                Err(_) => HttpResponse::new(400, "Bad Request", "Invalid order ID"),
+        _ => Ok(HttpResponse::new(404, "Not Found", "Order not found")),
+    }
+}
+
+fn add_order(request: &HttpRequest, params: &Params, db: &AspirinEatsDb) -> HandlerResult {
+    let Some(body) = &request.body else {
+        return Ok(HttpResponse::new(
+            400,
+            "Bad Request",
+            "Missing request body",
+        ));
+    };
+    let Ok(mut order_request) = serde_json::from_str::<OrderRequest>(body) else {
+        return Ok(HttpResponse::new(400, "Bad Request", "Invalid order data"));
+    };
+    let caller = params.caller()?;
+    if !caller.role.allows(Role::Kitchen) || order_request.customer.is_empty() {
+        order_request.customer = caller.name.clone();
+    }
+    let order = Order::priced(order_request, &db.get_menu()?)?;
+    let id = db.add_order(order)?;
+    let added_order = db.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+    let body = serde_json::to_string(&added_order).unwrap_or_default();
+    Ok(HttpResponse::new(201, "Created", &body))
+}
+
+fn update_order_status(
+    request: &HttpRequest,
+    params: &Params,
+    db: &AspirinEatsDb,
+) -> HandlerResult {
+    let id = params.get("id")?;
+    let Some(body) = &request.body else {
+        return Ok(HttpResponse::new(
+            400,
+            "Bad Request",
+            "Missing request body",
+        ));
+    };
+    let Ok(update) = StatusUpdate::from_str(body) else {
+        return Ok(HttpResponse::new(
+            400,
+            "Bad Request",
+            "Invalid status update",
+        ));
+    };
+    let order = db.update_status(id, update.status)?;
+    let body = serde_json::to_string(&order).unwrap_or_default();
+    Ok(HttpResponse::new(200, "OK", &body))
+}
+
+fn delete_orders(_: &HttpRequest, _: &Params, db: &AspirinEatsDb) -> HandlerResult {
+    db.reset_orders()?;
+    Ok(HttpResponse::new(200, "OK", "All orders deleted"))
+}
+
+fn delete_order(_: &HttpRequest, params: &Params, db: &AspirinEatsDb) -> HandlerResult {
+    db.remove_order(params.get("id")?)?;
+    Ok(HttpResponse::new(200, "OK", "Order deleted"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    const POLICY: KeepAlive = KeepAlive {
+        idle_timeout: Duration::from_millis(100),
+        request_timeout: Duration::from_secs(1),
+        max_requests: 3,
+    };
+
+    /// Open a connection served by `serve_connection` on one of `pool`'s workers
+    fn open(pool: &WorkerPool, policy: KeepAlive) -> BufReader<TcpStream> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.seed_menu(&Menu::default()).unwrap();
+        let load = pool.load();
+        pool.execute(move || {
+            let _ = serve_connection(stream, &router(), &db, &policy, &load);
+        });
+        BufReader::new(client)
+    }
+
+    fn write(connection: &mut BufReader<TcpStream>, data: &str) {
+        connection.get_mut().write_all(data.as_bytes()).unwrap();
+    }
+
+    /// Read one response, returning its status line and headers, and its body
+    fn response(connection: &mut BufReader<TcpStream>) -> (String, String) {
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            assert!(connection.read_line(&mut line).unwrap() > 0, "closed early");
+            if line == "\r\n" {
+                break;
             }
+            head.push_str(&line);
         }
-        (Some(_), Some(_)) => HttpResponse::new(405, "Method Not Allowed", "Method not allowed"),
-        _ => HttpResponse::new(404, "Not Found", "Resource not found"),
+        let length = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .map_or(0, |length| length.parse().unwrap());
+        let mut body = vec![0; length];
+        std::io::Read::read_exact(connection, &mut body).unwrap();
+        (head, String::from_utf8(body).unwrap())
+    }
+
+    fn is_closed(connection: &mut BufReader<TcpStream>) -> bool {
+        connection.read_line(&mut String::new()).unwrap() == 0
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let pool = WorkerPool::new(1, 1);
+        let mut connection = open(&pool, POLICY);
+        write(&mut connection, "GET / HTTP/1.1\r\n\r\n");
+        let (head, body) = response(&mut connection);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Connection: keep-alive"), "{}", head);
+        assert!(head.contains("max=2"), "{}", head);
+        assert_eq!(body, "Welcome to Aspirin Eats!");
+
+        // The idle timeout doesn't cut off a request that has started
+        write(&mut connection, "GET /menu HTTP/1.1\r\n");
+        std::thread::sleep(POLICY.idle_timeout * 3);
+        write(&mut connection, "Connection: close\r\n\r\n");
+        let (head, _) = response(&mut connection);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Connection: close"), "{}", head);
+        assert!(is_closed(&mut connection));
+
+        // Nor does it send anything when no request comes
+        let mut idle = open(&pool, POLICY);
+        assert!(is_closed(&mut idle));
+    }
+
+    #[test]
+    fn test_pipelining_up_to_the_request_cap() {
+        let pool = WorkerPool::new(1, 1);
+        let mut connection = open(&pool, POLICY);
+        write(
+            &mut connection,
+            "GET / HTTP/1.1\r\n\r\nGET /missing HTTP/1.1\r\n\r\n\
+             GET /menu HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+        );
+        let statuses: Vec<String> = (0..3)
+            .map(|_| response(&mut connection).0)
+            .map(|head| head.lines().next().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                "HTTP/1.1 200 OK",
+                "HTTP/1.1 404 Not Found",
+                "HTTP/1.1 200 OK"
+            ]
+        );
+        // The fourth request is over the cap, so the third response closed the
+        // connection
+        assert!(is_closed(&mut connection));
+    }
+
+    #[test]
+    fn test_stalled_request() {
+        let pool = WorkerPool::new(1, 1);
+        let policy = KeepAlive {
+            request_timeout: Duration::from_millis(100),
+            ..POLICY
+        };
+        let mut connection = open(&pool, policy);
+        write(&mut connection, "GET / HTTP/1.1\r\nHost: local");
+        let (head, _) = response(&mut connection);
+        assert!(head.starts_with("HTTP/1.1 408 Request Timeout"), "{}", head);
+        assert!(head.contains("Connection: close"), "{}", head);
+        assert!(is_closed(&mut connection));
+    }
+
+    #[test]
+    fn test_closes_when_connections_are_waiting() {
+        let pool = WorkerPool::new(1, 1);
+        let mut connection = open(&pool, POLICY);
+        // Another job waits for the only worker
+        let (release, wait) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = wait.recv();
+        });
+        write(&mut connection, "GET / HTTP/1.1\r\n\r\n");
+        let (head, _) = response(&mut connection);
+        assert!(head.contains("Connection: close"), "{}", head);
+        assert!(is_closed(&mut connection));
+        drop(release);
+    }
+
+    #[test]
+    fn test_idle_connections_give_up_their_workers() {
+        let pool = WorkerPool::new(WORKERS, WORKERS);
+        // Long enough that the request below would time out on the client if it
+        // waited for any of the idle connections to run out
+        let policy = KeepAlive {
+            idle_timeout: Duration::from_secs(30),
+            ..POLICY
+        };
+        let _idle: Vec<_> = (0..WORKERS).map(|_| open(&pool, policy)).collect();
+        let mut connection = open(&pool, policy);
+        write(&mut connection, "GET / HTTP/1.1\r\n\r\n");
+        let (head, body) = response(&mut connection);
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert_eq!(body, "Welcome to Aspirin Eats!");
+    }
+
+    /// Send `method path` with `token` as its bearer token and `body` after the headers
+    fn send(db: &AspirinEatsDb, method: &str, path: &str, token: &str, body: &str) -> HttpResponse {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nAuthorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            token,
+            body.len(),
+            body
+        );
+        router().handle(&HttpRequest::from_str(&raw).unwrap(), db)
+    }
+
+    #[test]
+    fn test_orders_belong_to_their_customer() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.seed_menu(&Menu::default()).unwrap();
+        let amit = db.add_credential("Amit", Role::Customer).unwrap();
+        let bea = db.add_credential("Bea", Role::Customer).unwrap();
+        let kitchen = db.add_credential("Kitchen", Role::Kitchen).unwrap();
+
+        // The name in the body is ignored for customers
+        let body = r#"{"customer": "Bea", "food": ["Fries"]}"#;
+        let response = send(&db, "POST", "/orders", &amit, body);
+        assert_eq!(response.status_code, 201);
+        let order = Order::from_str(&response.body).unwrap();
+        assert_eq!(order.customer, "Amit");
+        let path = format!("/orders/{}", order.id.unwrap());
+
+        assert_eq!(send(&db, "GET", &path, &amit, "").status_code, 200);
+        assert_eq!(send(&db, "GET", &path, &bea, "").status_code, 404);
+        assert_eq!(send(&db, "GET", &path, &kitchen, "").status_code, 200);
+        assert_eq!(send(&db, "GET", &path, "guess", "").status_code, 401);
+
+        // The kitchen can take an order for someone else
+        let response = send(&db, "POST", "/orders", &kitchen, body);
+        assert_eq!(Order::from_str(&response.body).unwrap().customer, "Bea");
+        let response = send(&db, "POST", "/orders", &kitchen, r#"{"food": ["Drink"]}"#);
+        assert_eq!(Order::from_str(&response.body).unwrap().customer, "Kitchen");
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(
+            parse_args(&[]).unwrap(),
+            Command::Serve("127.0.0.1:8080".to_string())
+        );
+        assert_eq!(
+            parse_args(&args(&["0.0.0.0:8081"])).unwrap(),
+            Command::Serve("0.0.0.0:8081".to_string())
+        );
+        assert_eq!(
+            parse_args(&args(&["add-token", "amit", "kitchen"])).unwrap(),
+            Command::AddToken("amit".to_string(), Role::Kitchen)
+        );
+        // Not an address to listen on
+        assert!(parse_args(&args(&["add-token"])).is_err());
+        assert!(parse_args(&args(&["add-token", "amit"])).is_err());
+        assert!(parse_args(&args(&["add-token", "amit", "chef"])).is_err());
+        assert!(parse_args(&args(&["127.0.0.1:8080", "extra"])).is_err());
     }
 }
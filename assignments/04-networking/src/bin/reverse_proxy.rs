@@ -1,27 +1,46 @@
+use aspirin_eats::balancer::{self, Balancer, Strategy, Upstream};
+use aspirin_eats::error::AspirinEatsError;
+use aspirin_eats::http::HttpResponse;
 use std::env;
 use std::io::{Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
 use std::thread;
+use std::time::Duration;
+
+/// How often every origin is probed with `GET /`
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long an origin has to answer a probe, or to accept a connection
+const ORIGIN_TIMEOUT: Duration = Duration::from_secs(1);
 
 fn main() {
     let args = env::args().collect::<Vec<String>>();
-    if args.len() < 3 {
-        eprintln!("Usage: {} <proxy-from> <proxy-to>", args[0]);
-        std::process::exit(2);
-    }
-
-    let proxy_addr = &args[1];
-    let origin_addr = &args[2];
+    let (balancer, proxy_addr) = match parse_args(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "Usage: {} <proxy-from> <proxy-to>[=weight]... [--strategy round-robin|least-connections|weighted]",
+                args[0]
+            );
+            std::process::exit(2);
+        }
+    };
+    let balancer = Arc::new(balancer);
 
-    let listener = TcpListener::bind(proxy_addr).expect("Failed to bind to proxy address");
+    let listener = TcpListener::bind(&proxy_addr).expect("Failed to bind to proxy address");
     println!("Proxy listening on {}", proxy_addr);
 
+    let checker = Arc::clone(&balancer);
+    thread::spawn(move || check_health(&checker));
+
     for stream in listener.incoming() {
         match stream {
             Ok(client_stream) => {
-                let origin_addr = origin_addr.clone();
+                let balancer = Arc::clone(&balancer);
                 thread::spawn(move || {
-                    if let Err(e) = handle_client(client_stream, &origin_addr) {
+                    if let Err(e) = handle_client(client_stream, &balancer) {
                         eprintln!("Error handling client: {}", e);
                     }
                 });
@@ -31,9 +50,83 @@ fn main() {
     }
 }
 
-fn handle_client(client_stream: TcpStream, origin_addr: &str) -> std::io::Result<()> {
-    // Connect to the origin server
-    let origin_stream = TcpStream::connect(origin_addr)?;
+/// Read the address to listen on, then the origins and the strategy
+fn parse_args(args: &[String]) -> Result<(Balancer, String), AspirinEatsError> {
+    let mut strategy = Strategy::RoundRobin;
+    let mut addrs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--strategy" {
+            let name = args.next().ok_or_else(|| {
+                AspirinEatsError::ParseError("missing --strategy value".to_string())
+            })?;
+            strategy = name.parse()?;
+        } else {
+            addrs.push(arg);
+        }
+    }
+    let Some((proxy_addr, origins)) = addrs.split_first() else {
+        return Err(AspirinEatsError::ParseError(
+            "missing <proxy-from>".to_string(),
+        ));
+    };
+    if origins.is_empty() {
+        return Err(AspirinEatsError::ParseError(
+            "missing <proxy-to>".to_string(),
+        ));
+    }
+    let upstreams = origins
+        .iter()
+        .map(|origin| origin.parse())
+        .collect::<Result<Vec<Upstream>, _>>()?;
+    Ok((Balancer::new(upstreams, strategy), proxy_addr.to_string()))
+}
+
+/// Probe every origin forever, taking the ones that fail out of rotation and
+/// putting them back once they answer again
+fn check_health(balancer: &Balancer) {
+    loop {
+        for (index, upstream) in balancer.upstreams().iter().enumerate() {
+            let healthy = balancer::probe(&upstream.addr, ORIGIN_TIMEOUT);
+            if balancer.set_healthy(index, healthy) {
+                let state = if healthy { "back in" } else { "out of" };
+                eprintln!("Origin {} is {} rotation", upstream.addr, state);
+            }
+        }
+        thread::sleep(HEALTH_CHECK_INTERVAL);
+    }
+}
+
+/// Connect to an origin chosen by the balancer. An origin that can't be reached is
+/// taken out of rotation and the next one is tried
+fn connect(balancer: &Balancer) -> Option<(balancer::Lease<'_>, TcpStream)> {
+    let mut tried = Vec::new();
+    while let Some(lease) = balancer.pick(&tried) {
+        let addr = &lease.upstream.addr;
+        let stream = addr.to_socket_addrs().and_then(|mut addrs| {
+            let addr = addrs.next().ok_or(std::io::ErrorKind::NotFound)?;
+            TcpStream::connect_timeout(&addr, ORIGIN_TIMEOUT)
+        });
+        match stream {
+            Ok(stream) => return Some((lease, stream)),
+            Err(e) => {
+                if balancer.set_healthy(lease.index, false) {
+                    eprintln!("Origin {} is out of rotation: {}", addr, e);
+                }
+                tried.push(lease.index);
+            }
+        }
+    }
+    None
+}
+
+fn handle_client(mut client_stream: TcpStream, balancer: &Balancer) -> std::io::Result<()> {
+    // Connect to an origin server, holding the lease until the connection is done
+    let Some((_lease, origin_stream)) = connect(balancer) else {
+        let response = HttpResponse::new(502, "Bad Gateway", "No origin server is available")
+            .with_header("Connection", "close");
+        return client_stream.write_all(response.to_string().as_bytes());
+    };
 
     // Create threads for bidirectional communication
     let mut client_reader = client_stream.try_clone()?;
@@ -49,6 +142,9 @@ fn handle_client(client_stream: TcpStream, origin_addr: &str) -> std::io::Result
             }
             origin_writer.write_all(&buffer[..bytes_read])?;
         }
+        // Let the origin see the client is done, so it can close a kept-alive
+        // connection instead of waiting for it to time out
+        origin_writer.shutdown(Shutdown::Write)?;
         Ok::<_, std::io::Error>(())
     });
 
@@ -73,3 +169,63 @@ fn handle_client(client_stream: TcpStream, origin_addr: &str) -> std::io::Result
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let (balancer, proxy_addr) = parse_args(&args(&[
+            "127.0.0.1:8080",
+            "127.0.0.1:8081=3",
+            "--strategy",
+            "least-connections",
+            "127.0.0.1:8082",
+        ]))
+        .unwrap();
+        assert_eq!(proxy_addr, "127.0.0.1:8080");
+        let origins: Vec<_> = balancer
+            .upstreams()
+            .iter()
+            .map(|upstream| (upstream.addr.as_str(), upstream.weight))
+            .collect();
+        assert_eq!(origins, vec![("127.0.0.1:8081", 3), ("127.0.0.1:8082", 1)]);
+
+        assert!(parse_args(&[]).is_err());
+        assert!(parse_args(&args(&["127.0.0.1:8080"])).is_err());
+        assert!(parse_args(&args(&["127.0.0.1:8080", "127.0.0.1:8081", "--strategy"])).is_err());
+        assert!(parse_args(&args(&["127.0.0.1:8080", "127.0.0.1:8081=0"])).is_err());
+    }
+
+    #[test]
+    fn test_connect_fails_over() {
+        // Nothing listens on the first origin once its listener is dropped
+        let refused = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let upstreams = vec![
+            refused.to_string().parse().unwrap(),
+            open.to_string().parse().unwrap(),
+        ];
+        let balancer = Balancer::new(upstreams, Strategy::RoundRobin);
+
+        let (lease, stream) = connect(&balancer).unwrap();
+        assert_eq!(lease.index, 1);
+        assert_eq!(stream.peer_addr().unwrap(), open);
+        assert!(!balancer.upstreams()[0].is_healthy());
+        drop(lease);
+
+        // With every origin refusing, there is nowhere left to connect
+        drop(listener);
+        assert!(connect(&balancer).is_none());
+        assert!(!balancer.upstreams()[1].is_healthy());
+    }
+}
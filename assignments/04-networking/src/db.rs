@@ -1,140 +1,664 @@
+use std::collections::HashMap;
 use std::path::Path;
-use std::str::FromStr;
+use std::time::Duration;
 
-use rusqlite::{Connection, Result};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::types::{Type, Value as SqlValue};
+use rusqlite::Error::FromSqlConversionFailure;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row, TransactionBehavior};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
 
+use crate::auth::{self, Caller, Role};
+use crate::error::AspirinEatsError;
 use crate::food::*;
+use crate::http::HttpRequest;
+use crate::menu::{Menu, MenuListing, MenuUpdate};
+use crate::migrations;
+use crate::money::Money;
 
+type Result<T, E = AspirinEatsError> = std::result::Result<T, E>;
+
+/// How many connections a database opened with `from_path` keeps open
+pub const POOL_SIZE: u32 = 8;
+
+/// How long a statement waits for another connection's write to finish before it
+/// fails with "database is locked"
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many orders a page holds when the request doesn't say
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// The most orders a single page may hold
+pub const MAX_PAGE_SIZE: usize = 200;
+
+/// The column a list of orders is sorted by. Ties are broken by ID
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Id,
+    Customer,
+    Total,
+}
+
+impl SortKey {
+    /// Parse a sort such as `total` or `-total`, returning the key and whether it
+    /// is descending
+    fn parse(sort: &str) -> Option<(SortKey, bool)> {
+        let (descending, column) = match sort.strip_prefix('-') {
+            Some(column) => (true, column),
+            None => (false, sort),
+        };
+        let key = match column {
+            "id" => SortKey::Id,
+            "customer" => SortKey::Customer,
+            "total" => SortKey::Total,
+            _ => return None,
+        };
+        Some((key, descending))
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            SortKey::Id => "id",
+            SortKey::Customer => "customer",
+            SortKey::Total => "total",
+        }
+    }
+}
+
+/// Which orders to list, in what order, and where the page starts
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderQuery {
+    /// Only orders with this status
+    pub status: Option<OrderStatus>,
+
+    /// Only orders placed under exactly this customer name
+    pub customer: Option<String>,
+
+    /// Only orders with this on them, either on its own or as part of a burger
+    pub item: Option<MenuEntry>,
+
+    /// The column to sort by
+    pub sort: SortKey,
+
+    /// Whether to sort from largest to smallest
+    pub descending: bool,
+
+    /// The most orders to return
+    pub limit: usize,
+
+    /// Where the previous page, listed with the same sort, left off
+    pub cursor: Option<Cursor>,
+}
+
+impl Default for OrderQuery {
+    fn default() -> Self {
+        OrderQuery {
+            status: None,
+            customer: None,
+            item: None,
+            sort: SortKey::Id,
+            descending: false,
+            limit: DEFAULT_PAGE_SIZE,
+            cursor: None,
+        }
+    }
+}
+
+impl OrderQuery {
+    /// Build a query from request parameters such as
+    /// `?status=Pending&customer=Amit&item=Topping:Bacon&sort=-total&limit=20&cursor=...`. A `-` before
+    /// the sort column sorts in descending order. Unknown parameters are ignored
+    pub fn from_params(params: &HashMap<String, String>) -> Result<Self, AspirinEatsError> {
+        let invalid = |name: &str, value: &str| {
+            AspirinEatsError::ParseError(format!("invalid {}: {:?}", name, value))
+        };
+        let mut query = OrderQuery::default();
+        if let Some(status) = params.get("status") {
+            query.status = Some(
+                serde_json::from_value(JsonValue::String(status.clone()))
+                    .map_err(|_| invalid("status", status))?,
+            );
+        }
+        query.customer = params.get("customer").cloned();
+        if let Some(item) = params.get("item") {
+            query.item = Some(item.parse().map_err(|_| invalid("item", item))?);
+        }
+        if let Some(sort) = params.get("sort") {
+            (query.sort, query.descending) =
+                SortKey::parse(sort).ok_or_else(|| invalid("sort", sort))?;
+        }
+        if let Some(limit) = params.get("limit") {
+            query.limit = limit
+                .parse()
+                .ok()
+                .filter(|limit| (1..=MAX_PAGE_SIZE).contains(limit))
+                .ok_or_else(|| invalid("limit", limit))?;
+        }
+        if let Some(cursor) = params.get("cursor") {
+            let decoded = Cursor::decode(cursor).ok_or_else(|| invalid("cursor", cursor))?;
+            if (decoded.sort, decoded.descending) != (query.sort, query.descending) {
+                return Err(AspirinEatsError::ParseError(
+                    "cursor belongs to a different sort".to_string(),
+                ));
+            }
+            query.cursor = Some(decoded);
+        }
+        Ok(query)
+    }
+}
+
+/// The position of the last order on a page: its sort value and ID, along with the
+/// sort it was listed with. Clients see it as an opaque string
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    sort: SortKey,
+    descending: bool,
+    value: JsonValue,
+    id: i64,
+}
+
+impl Cursor {
+    fn after(query: &OrderQuery, order: &Order) -> Self {
+        let id = order.id.unwrap_or_default();
+        let value = match query.sort {
+            SortKey::Id => JsonValue::from(id),
+            SortKey::Customer => JsonValue::from(order.customer.clone()),
+            SortKey::Total => JsonValue::from(order.total.cents()),
+        };
+        Cursor {
+            sort: query.sort,
+            descending: query.descending,
+            value,
+            id,
+        }
+    }
+
+    /// The cursor as a URL-safe string
+    pub fn encode(&self) -> String {
+        let sign = if self.descending { "-" } else { "" };
+        let json = serde_json::json!([
+            format!("{}{}", sign, self.sort.column()),
+            self.value,
+            self.id
+        ]);
+        json.to_string()
+            .bytes()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Read back a cursor made by `encode`
+    pub fn decode(s: &str) -> Option<Self> {
+        if !s.len().is_multiple_of(2) {
+            return None;
+        }
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        let (sort, value, id): (String, JsonValue, i64) = serde_json::from_slice(&bytes).ok()?;
+        let (sort, descending) = SortKey::parse(&sort)?;
+        let typed = match sort {
+            SortKey::Id | SortKey::Total => value.is_i64(),
+            SortKey::Customer => value.is_string(),
+        };
+        typed.then_some(Cursor {
+            sort,
+            descending,
+            value,
+            id,
+        })
+    }
+
+    fn sql_value(&self) -> SqlValue {
+        match &self.value {
+            JsonValue::String(s) => SqlValue::Text(s.clone()),
+            value => SqlValue::Integer(value.as_i64().unwrap_or_default()),
+        }
+    }
+}
+
+/// One page of orders, and the cursor for the next page if there is one
+#[derive(Serialize, Debug, PartialEq)]
+pub struct OrderPage {
+    pub orders: Vec<Order>,
+    pub next_cursor: Option<String>,
+}
+
+/// A handle to the order database. Clones share one pool of connections, so a
+/// handle can be given to each thread that serves requests
+#[derive(Clone)]
 pub struct AspirinEatsDb {
-    conn: Connection,
+    pool: Pool<SqliteConnectionManager>,
 }
 
 impl AspirinEatsDb {
     /// Create a new AspirinEatsDb instance from a given path
-    /// If the database does not exist, it will be created
+    /// If the database does not exist, it will be created. The database is put in
+    /// WAL mode, so that readers on other connections carry on while one of them
+    /// writes; writers take turns, each waiting up to `BUSY_TIMEOUT` for the last
     pub fn from_path<P>(db_path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
-        let db = Self {
-            conn: Connection::open(db_path)?,
-        };
-        db.create_table()?;
-        Ok(db)
+        let manager = SqliteConnectionManager::file(db_path).with_init(|conn| {
+            conn.busy_timeout(BUSY_TIMEOUT)?;
+            conn.pragma_update(None, "journal_mode", "WAL")?;
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+            conn.pragma_update(None, "foreign_keys", "ON")
+        });
+        Self::with_pool(manager, POOL_SIZE)
     }
 
     /// Create a new AspirinEatsDb instance in memory. Useful for testing
     pub fn in_memory() -> Result<Self> {
-        let db = Self {
-            conn: Connection::open_in_memory()?,
-        };
-        db.create_table()?;
+        // Every connection to `:memory:` opens a database of its own, so there can
+        // only be one
+        let manager = SqliteConnectionManager::memory()
+            .with_init(|conn| conn.pragma_update(None, "foreign_keys", "ON"));
+        Self::with_pool(manager, 1)
+    }
+
+    fn with_pool(manager: SqliteConnectionManager, size: u32) -> Result<Self> {
+        let pool = Pool::builder()
+            .max_size(size)
+            .connection_timeout(BUSY_TIMEOUT)
+            .build(manager)?;
+        let db = Self { pool };
+        migrations::migrate(&mut *db.conn()?)?;
         Ok(db)
     }
 
-    fn create_table(&self) -> Result<()> {
-        self.conn.execute(
-            "CREATE TABLE IF NOT EXISTS orders (
-            id	        INTEGER NOT NULL,
-            customer	TEXT NOT NULL,
-            food        TEXT NOT NULL,
-            status	    TEXT NOT NULL,
-            total       REAL NOT NULL,
-            PRIMARY KEY(id AUTOINCREMENT)
-        )",
-            [], // no params for this query
-        )?;
-        Ok(())
+    /// A connection from the pool, waiting for one to be returned if all are in use
+    fn conn(&self) -> Result<PooledConnection<SqliteConnectionManager>> {
+        Ok(self.pool.get()?)
     }
 }
 
 impl AspirinEatsDb {
     /// Insert a new Order into the database
     pub fn add_order(&self, order: Order) -> Result<i64> {
-        self.conn.execute(
-            "INSERT INTO orders (customer, food, status, total) VALUES (?1, ?2, ?3, ?4)",
-            [
-                order.customer,
-                serde_json::to_string(&order.food).expect("Failed to serialize food"),
-                serde_json::to_string(&order.status).expect("Failed to serialize status"),
-                order.total.to_string(),
-            ],
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute(
+            "INSERT INTO orders (customer, status, total) VALUES (?1, ?2, ?3)",
+            params![order.customer, order.status, order.total],
         )?;
-        Ok(self.conn.last_insert_rowid())
+        let id = tx.last_insert_rowid();
+        for (position, item) in order.food.iter().enumerate() {
+            let (kind, bun, patty) = match item {
+                MenuItem::Burger(burger) => ("Burger", Some(burger.bun()), Some(burger.patty())),
+                MenuItem::Fries => ("Fries", None, None),
+                MenuItem::Drink => ("Drink", None, None),
+            };
+            tx.execute(
+                "INSERT INTO order_items (order_id, position, kind, bun, patty)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![id, position, kind, bun, patty],
+            )?;
+            if let MenuItem::Burger(burger) = item {
+                let item_id = tx.last_insert_rowid();
+                for (position, topping) in burger.toppings().iter().enumerate() {
+                    tx.execute(
+                        "INSERT INTO order_item_toppings (item_id, position, topping)
+                        VALUES (?1, ?2, ?3)",
+                        params![item_id, position, topping],
+                    )?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(id)
     }
 
     /// Get an order by ID from the database
     pub fn get_order(&self, id: i64) -> Result<Option<Order>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT customer, food, status, total FROM orders WHERE id = ?1")?;
-        let mut rows = stmt.query([&id])?;
-
-        if let Some(row) = rows.next()? {
-            Ok(Some(Order {
-                id: Some(id),
-                customer: row.get(0)?,
-                food: {
-                    let food_str: String = row.get(1)?;
-                    serde_json::from_str(&food_str).expect("db should contain valid json")
-                },
-                status: {
-                    let status: String = row.get(2)?;
-                    OrderStatus::from_str(&status).expect("db should contain valid status")
-                },
-                total: row.get(3)?,
-            }))
-        } else {
-            Ok(None)
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT id, customer, status, total FROM orders WHERE id = ?1")?;
+        let mut orders = stmt
+            .query_map([&id], order_from_row)?
+            .collect::<rusqlite::Result<Vec<Order>>>()?;
+        load_food(&conn, &mut orders)?;
+        Ok(orders.pop())
+    }
+
+    /// Change the status of an order, if its current status allows it. Returns the
+    /// updated order
+    pub fn update_status(&self, id: i64, status: OrderStatus) -> Result<Order, AspirinEatsError> {
+        loop {
+            let order = self.get_order(id)?.ok_or(AspirinEatsError::NotFound)?;
+            if !order.status.can_become(&status) {
+                return Err(AspirinEatsError::InvalidTransition {
+                    from: order.status,
+                    to: status,
+                });
+            }
+            // Only update the row if its status is still the one that was checked
+            let changed = self.conn()?.execute(
+                "UPDATE orders SET status = ?1 WHERE id = ?2 AND status = ?3",
+                params![status, id, order.status],
+            )?;
+            if changed > 0 {
+                return Ok(Order { status, ..order });
+            }
         }
     }
 
-    /// Remove an order by ID from the database
+    /// Remove an order by ID from the database. Fails with `NotFound` if there is no
+    /// such order
     pub fn remove_order(&self, id: i64) -> Result<()> {
-        self.conn
+        let removed = self
+            .conn()?
             .execute("DELETE FROM orders WHERE id = ?1", [&id])?;
+        if removed == 0 {
+            return Err(AspirinEatsError::NotFound);
+        }
         Ok(())
     }
 
     /// Remove all orders from the database
     pub fn reset_orders(&self) -> Result<()> {
-        self.conn.execute("DELETE FROM orders", [])?;
-        self.conn.execute(
+        let mut conn = self.conn()?;
+        // Take the write lock up front, so the two statements apply together
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        tx.execute("DELETE FROM orders", [])?;
+        tx.execute(
             "UPDATE SQLITE_SEQUENCE SET SEQ='0' WHERE NAME='orders';",
             [],
         )?;
+        tx.commit()?;
         Ok(())
     }
 
     /// Get all orders from the database
     pub fn get_all_orders(&self) -> Result<Vec<Order>> {
-        let mut stmt = self
-            .conn
-            .prepare("SELECT id, customer, food, status, total FROM orders")?;
-
-        let order_iter = stmt.query_map([], |row| {
-            Ok(Order {
-                id: row.get(0)?,
-                customer: row.get(1)?,
-                food: {
-                    let food_str: String = row.get(2)?;
-                    serde_json::from_str(&food_str).expect("db should contain valid json")
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT id, customer, status, total FROM orders")?;
+
+        let order_iter = stmt.query_map([], order_from_row)?;
+
+        let mut orders = order_iter.collect::<rusqlite::Result<Vec<Order>>>()?;
+        load_food(&conn, &mut orders)?;
+        Ok(orders)
+    }
+
+    /// Get one page of the orders matching `query`. The filters, sort and cursor all
+    /// become parameters of a single statement, so only the page is read
+    pub fn get_orders(&self, query: &OrderQuery) -> Result<OrderPage> {
+        let conn = self.conn()?;
+        let mut sql = String::from("SELECT id, customer, status, total FROM orders WHERE 1 = 1");
+        let mut params: Vec<SqlValue> = Vec::new();
+        if let Some(status) = &query.status {
+            sql.push_str(" AND status = ?");
+            params.push(SqlValue::Text(format!("{:?}", status)));
+        }
+        if let Some(customer) = &query.customer {
+            sql.push_str(" AND customer = ?");
+            params.push(SqlValue::Text(customer.clone()));
+        }
+        if let Some(item) = &query.item {
+            let (column, name) = match item {
+                MenuEntry::Bun(bun) => ("bun", format!("{:?}", bun)),
+                MenuEntry::Patty(patty) => ("patty", format!("{:?}", patty)),
+                MenuEntry::Topping(topping) => ("topping", format!("{:?}", topping)),
+                MenuEntry::Fries | MenuEntry::Drink => ("kind", item.to_string()),
+            };
+            sql.push_str(&format!(
+                " AND id IN (SELECT order_id FROM order_items
+                    LEFT JOIN order_item_toppings ON item_id = order_items.id
+                    WHERE {} = ?)",
+                column
+            ));
+            params.push(SqlValue::Text(name));
+        }
+        let column = query.sort.column();
+        let (after, direction) = if query.descending {
+            ("<", "DESC")
+        } else {
+            (">", "ASC")
+        };
+        if let Some(cursor) = &query.cursor {
+            if query.sort == SortKey::Id {
+                sql.push_str(&format!(" AND id {} ?", after));
+            } else {
+                sql.push_str(&format!(
+                    " AND ({column} {after} ? OR ({column} = ? AND id {after} ?))"
+                ));
+                params.push(cursor.sql_value());
+                params.push(cursor.sql_value());
+            }
+            params.push(SqlValue::Integer(cursor.id));
+        }
+        if query.sort != SortKey::Id {
+            sql.push_str(&format!(" ORDER BY {column} {direction}, id {direction}"));
+        } else {
+            sql.push_str(&format!(" ORDER BY id {direction}"));
+        }
+        // One row past the page tells whether there is a next page
+        sql.push_str(" LIMIT ?");
+        params.push(SqlValue::Integer(query.limit as i64 + 1));
+
+        let mut stmt = conn.prepare(&sql)?;
+        let mut orders = stmt
+            .query_map(params_from_iter(params), order_from_row)?
+            .collect::<rusqlite::Result<Vec<Order>>>()?;
+        let next_cursor = if orders.len() > query.limit {
+            orders.truncate(query.limit);
+            orders
+                .last()
+                .map(|last| Cursor::after(query, last).encode())
+        } else {
+            None
+        };
+        load_food(&conn, &mut orders)?;
+        Ok(OrderPage {
+            orders,
+            next_cursor,
+        })
+    }
+}
+
+impl AspirinEatsDb {
+    /// Add the listings in `menu` that the database doesn't have yet. Listings it
+    /// already has keep the price and availability they were last given
+    pub fn seed_menu(&self, menu: &Menu) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        for listing in &menu.items {
+            tx.execute(
+                "INSERT OR IGNORE INTO menu_items (item, price, available) VALUES (?1, ?2, ?3)",
+                params![listing.item.to_string(), listing.price, listing.available],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Get the current menu, in the order of `MenuEntry::ALL`. Rows for entries this
+    /// version of the server doesn't know are left out
+    pub fn get_menu(&self) -> Result<Menu> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT item, price, available FROM menu_items")?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, Money>(1)?,
+                    row.get::<_, bool>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut items: Vec<MenuListing> = rows
+            .into_iter()
+            .filter_map(|(item, price, available)| {
+                Some(MenuListing {
+                    item: item.parse().ok()?,
+                    price,
+                    available,
+                })
+            })
+            .collect();
+        items.sort_by_key(|listing| {
+            MenuEntry::ALL
+                .iter()
+                .position(|entry| *entry == listing.item)
+        });
+        Ok(Menu { items })
+    }
+
+    /// Change the price or availability of an entry already on the menu
+    pub fn update_menu_item(&self, entry: MenuEntry, update: &MenuUpdate) -> Result<MenuListing> {
+        let conn = self.conn()?;
+        let listing = conn
+            .query_row(
+                "UPDATE menu_items SET price = COALESCE(?2, price), available = COALESCE(?3, available)
+                WHERE item = ?1 RETURNING price, available",
+                params![entry.to_string(), update.price, update.available],
+                |row| {
+                    Ok(MenuListing {
+                        item: entry,
+                        price: row.get(0)?,
+                        available: row.get(1)?,
+                    })
                 },
-                status: {
-                    let status: String = row.get(3)?;
-                    OrderStatus::from_str(&status).expect("db should contain valid status")
+            )
+            .optional()?;
+        listing.ok_or(AspirinEatsError::NotFound)
+    }
+}
+
+impl AspirinEatsDb {
+    /// Give `name` a new token with `role`. Only a hash of the token is kept, so the
+    /// token returned here is the only copy
+    pub fn add_credential(&self, name: &str, role: Role) -> Result<String> {
+        let token = auth::new_token();
+        self.conn()?.execute(
+            "INSERT INTO credentials (token_hash, name, role) VALUES (?1, ?2, ?3)",
+            params![auth::hash_token(&token), name, role],
+        )?;
+        Ok(token)
+    }
+
+    /// Whoever holds `token`, if it is known
+    pub fn caller_for_token(&self, token: &str) -> Result<Option<Caller>> {
+        let caller = self
+            .conn()?
+            .query_row(
+                "SELECT name, role FROM credentials WHERE token_hash = ?1",
+                [auth::hash_token(token)],
+                |row| {
+                    Ok(Caller {
+                        name: row.get(0)?,
+                        role: row.get(1)?,
+                    })
                 },
-                total: row.get(4)?,
-            })
-        })?;
+            )
+            .optional()?;
+        Ok(caller)
+    }
 
-        Ok(order_iter.map(Result::unwrap).collect())
+    /// Whether any token has been handed out yet
+    pub fn has_credentials(&self) -> Result<bool> {
+        let exists =
+            self.conn()?
+                .query_row("SELECT EXISTS (SELECT 1 FROM credentials)", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(exists)
     }
+
+    /// The caller making `request`, from its bearer token
+    pub fn authenticate(&self, request: &HttpRequest) -> Result<Caller> {
+        let token = auth::bearer_token(request).ok_or(AspirinEatsError::Unauthorized)?;
+        self.caller_for_token(token)?
+            .ok_or(AspirinEatsError::Unauthorized)
+    }
+}
+
+/// Build an Order from a row of `SELECT id, customer, status, total`. Its food is
+/// filled in by `load_food`
+fn order_from_row(row: &Row) -> rusqlite::Result<Order> {
+    Ok(Order {
+        id: row.get(0)?,
+        customer: row.get(1)?,
+        food: Vec::new(),
+        status: row.get(2)?,
+        total: row.get(3)?,
+    })
 }
 
+/// Fill in the food of `orders` from `order_items`, with one statement for all of
+/// them
+fn load_food(conn: &Connection, orders: &mut [Order]) -> Result<()> {
+    let ids: Vec<i64> = orders.iter().filter_map(|order| order.id).collect();
+    let mut stmt = conn.prepare(
+        "SELECT order_items.order_id, order_items.id, kind, bun, patty, topping
+        FROM order_items LEFT JOIN order_item_toppings ON item_id = order_items.id
+        WHERE order_id IN (SELECT value FROM json_each(?1))
+        ORDER BY order_items.order_id, order_items.position, order_item_toppings.position",
+    )?;
+    let mut rows = stmt.query([serde_json::to_string(&ids).unwrap_or_default()])?;
+    // Each row is one topping, so a burger spans as many rows as it has toppings
+    let mut food: HashMap<i64, Vec<MenuItem>> = HashMap::new();
+    let mut last_item = None;
+    while let Some(row) = rows.next()? {
+        let (order_id, item_id): (i64, i64) = (row.get(0)?, row.get(1)?);
+        let items = food.entry(order_id).or_default();
+        if last_item != Some(item_id) {
+            last_item = Some(item_id);
+            let kind: String = row.get(2)?;
+            items.push(match kind.as_str() {
+                "Burger" => MenuItem::Burger(Burger::new(row.get(3)?, row.get(4)?, Vec::new())),
+                "Fries" => MenuItem::Fries,
+                "Drink" => MenuItem::Drink,
+                _ => {
+                    let error = format!("unknown kind of order item: {:?}", kind);
+                    return Err(FromSqlConversionFailure(2, Type::Text, error.into()).into());
+                }
+            });
+        }
+        if let (Some(MenuItem::Burger(burger)), Some(topping)) = (items.last_mut(), row.get(5)?) {
+            burger.add_topping(topping);
+        }
+    }
+    for order in orders {
+        if let Some(items) = order.id.and_then(|id| food.remove(&id)) {
+            order.food = items;
+        }
+    }
+    Ok(())
+}
+
+/// Store an enum without data, such as an order status or a topping, as the name
+/// of its variant
+macro_rules! sql_as_name {
+    ($($name:ty),*) => {$(
+        impl ToSql for $name {
+            fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+                Ok(ToSqlOutput::from(format!("{:?}", self)))
+            }
+        }
+
+        impl FromSql for $name {
+            fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+                let name = value.as_str()?;
+                serde_json::from_value(JsonValue::from(name))
+                    .map_err(|e| FromSqlError::Other(Box::new(e)))
+            }
+        }
+    )*};
+}
+
+sql_as_name!(OrderStatus, Bun, Patty, Topping, Role);
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::str::FromStr;
 
     fn get_test_order() -> Order {
         Order {
@@ -142,7 +666,7 @@ mod tests {
             customer: "Amit".to_string(),
             food: vec![MenuItem::Fries, MenuItem::Drink],
             status: OrderStatus::Pending,
-            total: 8.0,
+            total: Money::from_cents(800),
         }
     }
 
@@ -180,6 +704,379 @@ mod tests {
         db.remove_order(id).unwrap();
         let got = db.get_order(id).unwrap();
         assert_eq!(got, None);
+        assert!(matches!(
+            db.remove_order(id),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Every page of `query`, following `next_cursor` to the end
+    fn all_pages(db: &AspirinEatsDb, pairs: &[(&str, &str)]) -> Vec<Vec<i64>> {
+        let mut pages = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut params = params(pairs);
+            if let Some(cursor) = &cursor {
+                params.insert("cursor".to_string(), cursor.clone());
+            }
+            let page = db
+                .get_orders(&OrderQuery::from_params(&params).unwrap())
+                .unwrap();
+            pages.push(page.orders.iter().map(|order| order.id.unwrap()).collect());
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_orders_filters_and_pages() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        for (customer, total, status) in [
+            ("Amit", 800, OrderStatus::Pending),
+            ("Bea", 1250, OrderStatus::Pending),
+            ("Amit", 1250, OrderStatus::Completed),
+            ("Amit", 300, OrderStatus::Pending),
+            ("Amit", 1250, OrderStatus::Pending),
+        ] {
+            let mut order = get_test_order();
+            order.customer = customer.to_string();
+            order.total = Money::from_cents(total);
+            order.status = status;
+            db.add_order(order).unwrap();
+        }
+
+        assert_eq!(
+            all_pages(&db, &[("limit", "2")]),
+            vec![vec![1, 2], vec![3, 4], vec![5]]
+        );
+        assert_eq!(
+            all_pages(&db, &[("sort", "-total"), ("limit", "2")]),
+            vec![vec![5, 3], vec![2, 1], vec![4]]
+        );
+        assert_eq!(
+            all_pages(
+                &db,
+                &[
+                    ("status", "Pending"),
+                    ("customer", "Amit"),
+                    ("sort", "total")
+                ]
+            ),
+            vec![vec![4, 1, 5]]
+        );
+        assert_eq!(
+            all_pages(&db, &[("sort", "-customer"), ("limit", "1")]),
+            vec![vec![2], vec![5], vec![4], vec![3], vec![1]]
+        );
+        // A page that ends exactly at the last order has no next page
+        let page = db
+            .get_orders(&OrderQuery::from_params(&params(&[("limit", "5")])).unwrap())
+            .unwrap();
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn test_burgers_keep_their_toppings() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let mut order = get_test_order();
+        order.food = vec![
+            MenuItem::Drink,
+            MenuItem::Burger(Burger::new(
+                Bun::Sesame,
+                Patty::Beef,
+                vec![Topping::Cheese, Topping::Bacon, Topping::Cheese],
+            )),
+            MenuItem::Burger(Burger::new(Bun::Plain, Patty::Veggie, vec![])),
+            MenuItem::Fries,
+        ];
+        let mut empty = get_test_order();
+        empty.food = vec![];
+        order.id = Some(db.add_order(order.clone()).unwrap());
+        empty.id = Some(db.add_order(empty.clone()).unwrap());
+
+        assert_eq!(db.get_order(order.id.unwrap()).unwrap().unwrap(), order);
+        assert_eq!(db.get_all_orders().unwrap(), vec![order, empty]);
+    }
+
+    #[test]
+    fn test_get_orders_by_item() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        for food in [
+            vec![MenuItem::Fries],
+            vec![MenuItem::Burger(Burger::new(
+                Bun::Sesame,
+                Patty::Beef,
+                vec![Topping::Bacon],
+            ))],
+            vec![
+                MenuItem::Drink,
+                MenuItem::Burger(Burger::new(Bun::Plain, Patty::Beef, vec![])),
+            ],
+        ] {
+            let mut order = get_test_order();
+            order.food = food;
+            db.add_order(order).unwrap();
+        }
+
+        for (item, ids) in [
+            ("Fries", vec![1]),
+            ("Drink", vec![3]),
+            ("Patty:Beef", vec![2, 3]),
+            ("Bun:Plain", vec![3]),
+            ("Topping:Bacon", vec![2]),
+            ("Topping:Onion", vec![]),
+        ] {
+            assert_eq!(all_pages(&db, &[("item", item)]), vec![ids], "{}", item);
+        }
+        assert!(OrderQuery::from_params(&params(&[("item", "Burger")])).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_rows_are_errors() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let lost = db.add_order(get_test_order()).unwrap();
+        let pizza = db.add_order(get_test_order()).unwrap();
+        let conn = db.conn().unwrap();
+        conn.execute("UPDATE orders SET status = 'Lost' WHERE id = ?1", [lost])
+            .unwrap();
+        conn.execute(
+            "UPDATE order_items SET kind = 'Pizza' WHERE order_id = ?1",
+            [pizza],
+        )
+        .unwrap();
+        drop(conn);
+
+        for id in [lost, pizza] {
+            assert!(matches!(
+                db.get_order(id),
+                Err(AspirinEatsError::DatabaseError(_))
+            ));
+        }
+        assert!(db.get_all_orders().is_err());
+        assert!(db.get_orders(&OrderQuery::default()).is_err());
+        assert!(db.update_status(lost, OrderStatus::Preparing).is_err());
+    }
+
+    #[test]
+    fn test_order_query_rejects_bad_params() {
+        for pairs in [
+            [("status", "Lost")],
+            [("sort", "colour")],
+            [("limit", "0")],
+            [("limit", "500")],
+            [("cursor", "zz")],
+        ] {
+            assert!(
+                OrderQuery::from_params(&params(&pairs)).is_err(),
+                "{:?}",
+                pairs
+            );
+        }
+        let query = OrderQuery {
+            limit: 1,
+            ..OrderQuery::default()
+        };
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.add_order(get_test_order()).unwrap();
+        db.add_order(get_test_order()).unwrap();
+        let cursor = db.get_orders(&query).unwrap().next_cursor.unwrap();
+        let other_sort = params(&[("sort", "-id"), ("cursor", &cursor)]);
+        assert!(OrderQuery::from_params(&other_sort).is_err());
+    }
+
+    #[test]
+    fn test_update_status() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        let id = db.add_order(get_test_order()).unwrap();
+
+        let order = db.update_status(id, OrderStatus::Preparing).unwrap();
+        assert_eq!(order.status, OrderStatus::Preparing);
+        assert_eq!(db.get_order(id).unwrap().unwrap(), order);
+
+        db.update_status(id, OrderStatus::Transporting).unwrap();
+        let error = db.update_status(id, OrderStatus::Cancelled).unwrap_err();
+        assert!(matches!(
+            error,
+            AspirinEatsError::InvalidTransition {
+                from: OrderStatus::Transporting,
+                to: OrderStatus::Cancelled
+            }
+        ));
+        let status = db.get_order(id).unwrap().unwrap().status;
+        assert_eq!(status, OrderStatus::Transporting);
+
+        assert!(matches!(
+            db.update_status(999, OrderStatus::Preparing),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_readers() {
+        let path = std::env::temp_dir().join(format!("aspirin-eats-{}.db", uuid::Uuid::new_v4()));
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..25 {
+                        let id = db.add_order(get_test_order()).unwrap();
+                        assert!(db.get_order(id).unwrap().is_some());
+                        db.get_orders(&OrderQuery::default()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let orders = db.get_all_orders().unwrap();
+        let mut ids: Vec<i64> = orders.iter().map(|order| order.id.unwrap()).collect();
+        ids.dedup();
+        assert_eq!(ids.len(), 200);
+        let journal_mode: String = db
+            .conn()
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_migrates_real_totals_to_cents() {
+        let path = std::env::temp_dir().join(format!("aspirin-eats-{}.db", uuid::Uuid::new_v4()));
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE orders (
+                    id	        INTEGER NOT NULL,
+                    customer	TEXT NOT NULL,
+                    food        TEXT NOT NULL,
+                    status	    TEXT NOT NULL,
+                    total       REAL NOT NULL,
+                    PRIMARY KEY(id AUTOINCREMENT)
+                );
+                INSERT INTO orders (customer, food, status, total)
+                    VALUES ('Amit', '[\"Fries\",\"Drink\"]', '\"Pending\"', 8.0),
+                           ('Bea', '[\"Fries\"]', '\"Pending\"', 0.1 + 0.2),
+                           ('Cy', '[\"Drink\"]', '\"Pending\"', 3.0);
+                DELETE FROM orders WHERE id = 3;",
+            )
+            .unwrap();
+        }
+
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        let totals: Vec<Money> = db
+            .get_all_orders()
+            .unwrap()
+            .iter()
+            .map(|order| order.total)
+            .collect();
+        assert_eq!(totals, vec![Money::from_cents(800), Money::from_cents(30)]);
+        // The ID of the deleted order is not handed out again
+        assert_eq!(db.add_order(get_test_order()).unwrap(), 4);
+        drop(db);
+        // Opening it again leaves it alone
+        let db = AspirinEatsDb::from_path(&path).unwrap();
+        assert_eq!(db.get_all_orders().unwrap().len(), 3);
+
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_menu_seed_and_update() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        db.seed_menu(&Menu::default()).unwrap();
+        assert_eq!(db.get_menu().unwrap(), Menu::default());
+
+        let bacon = MenuEntry::Topping(Topping::Bacon);
+        let update = MenuUpdate {
+            price: Some(Money::from_cents(250)),
+            available: None,
+        };
+        let listing = db.update_menu_item(bacon, &update).unwrap();
+        assert_eq!(
+            (listing.price, listing.available),
+            (Money::from_cents(250), true)
+        );
+        let update = MenuUpdate {
+            price: None,
+            available: Some(false),
+        };
+        db.update_menu_item(MenuEntry::Fries, &update).unwrap();
+
+        // Seeding again doesn't undo the changes
+        db.seed_menu(&Menu::default()).unwrap();
+        let menu = db.get_menu().unwrap();
+        assert_eq!(menu.listing(bacon).unwrap().price, Money::from_cents(250));
+        assert!(!menu.listing(MenuEntry::Fries).unwrap().available);
+        assert_eq!(menu.items.len(), MenuEntry::ALL.len());
+
+        let db = AspirinEatsDb::in_memory().unwrap();
+        assert!(matches!(
+            db.update_menu_item(bacon, &MenuUpdate::default()),
+            Err(AspirinEatsError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn test_credentials() {
+        let db = AspirinEatsDb::in_memory().unwrap();
+        assert!(!db.has_credentials().unwrap());
+        let admin = db.add_credential("Amit", Role::Admin).unwrap();
+        let kitchen = db.add_credential("Bea", Role::Kitchen).unwrap();
+        assert!(db.has_credentials().unwrap());
+        assert_eq!(
+            db.caller_for_token(&admin).unwrap(),
+            Some(Caller {
+                name: "Amit".to_string(),
+                role: Role::Admin
+            })
+        );
+        assert_eq!(db.caller_for_token("guess").unwrap(), None);
+
+        let stored: String = db
+            .conn()
+            .unwrap()
+            .query_row(
+                "SELECT token_hash FROM credentials WHERE name = 'Amit'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_ne!(stored, admin);
+
+        let request = |authorization: &str| {
+            let raw = format!("GET / HTTP/1.1\r\nAuthorization: {}\r\n\r\n", authorization);
+            HttpRequest::from_str(&raw).unwrap()
+        };
+        let with_token = request(&format!("Bearer {}", kitchen));
+        let caller = db.authenticate(&with_token).unwrap();
+        assert_eq!((caller.name.as_str(), caller.role), ("Bea", Role::Kitchen));
+        for authorization in ["Bearer guess", "Basic abc"] {
+            assert!(matches!(
+                db.authenticate(&request(authorization)),
+                Err(AspirinEatsError::Unauthorized)
+            ));
+        }
     }
 
     #[test]
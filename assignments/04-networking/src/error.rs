@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::food::{MenuEntry, OrderStatus};
+
 #[derive(Error, Debug)]
 pub enum AspirinEatsError {
     /// Error when trying to parse a JSON string
@@ -11,13 +13,42 @@ pub enum AspirinEatsError {
     /// Error when a resource is not found
     #[error("Not found")]
     NotFound,
+    /// Error when a request needs credentials and has none, or has ones that aren't
+    /// known
+    #[error("Unauthorized")]
+    Unauthorized,
+    /// Error when a request's credentials don't carry the role it needs
+    #[error("Forbidden")]
+    Forbidden,
     /// Error when an unsupported HTTP method is used
     #[error("Method not allowed")]
     MethodNotAllowed,
+    /// Error when an order's status cannot change to the one requested
+    #[error("Cannot change order status from {from:?} to {to:?}")]
+    InvalidTransition { from: OrderStatus, to: OrderStatus },
+    /// Error when an order asks for something that is not on the menu right now
+    #[error("{0} is not available")]
+    Unavailable(MenuEntry),
+    /// Error when a request body is larger than the server accepts
+    #[error("Payload too large")]
+    PayloadTooLarge,
+    /// Error when a request line is longer than the server accepts
+    #[error("URI too long")]
+    UriTooLong,
+    /// Error when a request's headers are larger than the server accepts
+    #[error("Request header fields too large")]
+    HeadersTooLarge,
+    /// Error when the database was last used by a newer server, with a schema
+    /// version this one doesn't know
+    #[error("Unsupported database schema version {0}")]
+    UnsupportedSchema(usize),
     /// IO Error
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     /// Database Error (from rusqlite)
     #[error("Database error: {0}")]
     DatabaseError(#[from] rusqlite::Error),
+    /// Error when no database connection could be had from the pool
+    #[error("Connection pool error: {0}")]
+    PoolError(#[from] r2d2::Error),
 }
@@ -1,6 +1,13 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
 use display_json::{DisplayAsJson, FromStrAsJson};
 use serde::{Deserialize, Serialize};
 
+use crate::error::AspirinEatsError;
+use crate::menu::Menu;
+use crate::money::Money;
+
 /// Struct that represents an order
 #[derive(Serialize, Deserialize, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone)]
 pub struct Order {
@@ -17,33 +24,47 @@ pub struct Order {
     pub status: OrderStatus,
 
     /// Total price of the order
-    pub total: f64,
+    pub total: Money,
 }
 
 /// Struct that represents an incoming order request to be added to the database. Separate from the
 /// Order struct because many of the fields will be generated for new orders
 #[derive(Deserialize, FromStrAsJson)]
 pub struct OrderRequest {
-    /// Customer Name
+    /// Customer Name. Customers always order under the name their token was given
+    /// to, so this is only read when the kitchen places an order for someone else
+    #[serde(default)]
     pub customer: String,
 
     /// Vec of all the food items in the order
     pub food: Vec<MenuItem>,
 }
 
-impl From<OrderRequest> for Order {
-    /// Create an Order from an OrderRequest by filling in the ID, status, and total fields
-    fn from(order_request: OrderRequest) -> Self {
-        Order {
+impl Order {
+    /// Create an Order from an OrderRequest by filling in the ID, status, and total
+    /// fields, with the total priced from `menu`. Fails if anything ordered is
+    /// missing from the menu or unavailable
+    pub fn priced(order_request: OrderRequest, menu: &Menu) -> Result<Self, AspirinEatsError> {
+        Ok(Order {
             id: None,
             customer: order_request.customer,
             status: OrderStatus::Pending,
-            total: order_request.food.iter().map(|item| item.price()).sum(),
+            total: order_request
+                .food
+                .iter()
+                .try_fold(Money::ZERO, |total, item| total + menu.price(item)?)?,
             food: order_request.food,
-        }
+        })
     }
 }
 
+/// Struct that represents a request to change an order's status
+#[derive(Deserialize, FromStrAsJson)]
+pub struct StatusUpdate {
+    /// The status the order should have
+    pub status: OrderStatus,
+}
+
 /// Enum that represents the status of an order
 #[derive(Serialize, Deserialize, DisplayAsJson, FromStrAsJson, Debug, PartialEq, Clone)]
 pub enum OrderStatus {
@@ -54,6 +75,26 @@ pub enum OrderStatus {
     Cancelled,
 }
 
+impl OrderStatus {
+    /// The statuses an order with this status can move to. Orders move forward
+    /// from Pending to Completed one step at a time, and can only be cancelled
+    /// before they leave the kitchen
+    pub fn next_statuses(&self) -> &'static [OrderStatus] {
+        match self {
+            OrderStatus::Pending => &[OrderStatus::Preparing, OrderStatus::Cancelled],
+            OrderStatus::Preparing => &[OrderStatus::Transporting, OrderStatus::Cancelled],
+            OrderStatus::Transporting => &[OrderStatus::Completed],
+            OrderStatus::Completed | OrderStatus::Cancelled => &[],
+        }
+    }
+
+    /// Whether an order with this status can be given `next`. Keeping the same
+    /// status is always allowed, so repeating an update is harmless
+    pub fn can_become(&self, next: &OrderStatus) -> bool {
+        self == next || self.next_statuses().contains(next)
+    }
+}
+
 /// Enum that represents a particular menu item
 #[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
 pub enum MenuItem {
@@ -63,15 +104,89 @@ pub enum MenuItem {
 }
 
 impl MenuItem {
-    fn price(&self) -> f64 {
+    /// The things on the menu that make up this item, each priced separately
+    pub fn entries(&self) -> Vec<MenuEntry> {
         match self {
-            MenuItem::Burger(burger) => burger.price(),
-            MenuItem::Fries => 5.0,
-            MenuItem::Drink => 3.0,
+            MenuItem::Burger(burger) => {
+                let mut entries = vec![MenuEntry::Bun(burger.bun), MenuEntry::Patty(burger.patty)];
+                entries.extend(burger.toppings.iter().copied().map(MenuEntry::Topping));
+                entries
+            }
+            MenuItem::Fries => vec![MenuEntry::Fries],
+            MenuItem::Drink => vec![MenuEntry::Drink],
         }
     }
 }
 
+/// Enum that identifies something on the menu with a price of its own. A burger's
+/// price is the sum of its bun, patty and toppings. Written as `Fries` or
+/// `Topping:Bacon`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MenuEntry {
+    Bun(Bun),
+    Patty(Patty),
+    Topping(Topping),
+    Fries,
+    Drink,
+}
+
+impl MenuEntry {
+    /// Every menu entry, in the order the menu lists them
+    pub const ALL: [MenuEntry; 14] = [
+        MenuEntry::Bun(Bun::Sesame),
+        MenuEntry::Bun(Bun::Plain),
+        MenuEntry::Bun(Bun::GlutenFree),
+        MenuEntry::Patty(Patty::Beef),
+        MenuEntry::Patty(Patty::Chicken),
+        MenuEntry::Patty(Patty::Veggie),
+        MenuEntry::Topping(Topping::Lettuce),
+        MenuEntry::Topping(Topping::Tomato),
+        MenuEntry::Topping(Topping::Onion),
+        MenuEntry::Topping(Topping::Pickle),
+        MenuEntry::Topping(Topping::Cheese),
+        MenuEntry::Topping(Topping::Bacon),
+        MenuEntry::Fries,
+        MenuEntry::Drink,
+    ];
+}
+
+impl Display for MenuEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MenuEntry::Bun(bun) => write!(f, "Bun:{:?}", bun),
+            MenuEntry::Patty(patty) => write!(f, "Patty:{:?}", patty),
+            MenuEntry::Topping(topping) => write!(f, "Topping:{:?}", topping),
+            MenuEntry::Fries => f.write_str("Fries"),
+            MenuEntry::Drink => f.write_str("Drink"),
+        }
+    }
+}
+
+impl FromStr for MenuEntry {
+    type Err = AspirinEatsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MenuEntry::ALL
+            .into_iter()
+            .find(|entry| entry.to_string() == s)
+            .ok_or_else(|| AspirinEatsError::ParseError(format!("unknown menu entry: {:?}", s)))
+    }
+}
+
+impl Serialize for MenuEntry {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MenuEntry {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
 /// Struct that represents a burger
 #[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
 pub struct Burger {
@@ -89,55 +204,42 @@ impl Burger {
         }
     }
 
-    fn price(&self) -> f64 {
-        self.bun.price()
-            + self.patty.price()
-            + self
-                .toppings
-                .iter()
-                .map(|topping| topping.price())
-                .sum::<f64>()
+    pub fn bun(&self) -> Bun {
+        self.bun
+    }
+
+    pub fn patty(&self) -> Patty {
+        self.patty
+    }
+
+    pub fn toppings(&self) -> &[Topping] {
+        &self.toppings
+    }
+
+    /// Put `topping` on top of the others
+    pub fn add_topping(&mut self, topping: Topping) {
+        self.toppings.push(topping);
     }
 }
 
 /// Enum that represents a type of bun
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Bun {
     Sesame,
     Plain,
     GlutenFree,
 }
 
-impl Bun {
-    fn price(&self) -> f64 {
-        match self {
-            Bun::Sesame => 1.0,
-            Bun::Plain => 0.0,
-            Bun::GlutenFree => 2.0,
-        }
-    }
-}
-
 /// Enum that represents a type of patty
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Patty {
     Beef,
     Chicken,
     Veggie,
 }
 
-impl Patty {
-    fn price(&self) -> f64 {
-        match self {
-            Patty::Beef => 8.0,
-            Patty::Chicken => 7.0,
-            Patty::Veggie => 6.0,
-        }
-    }
-}
-
 /// Enum that represents a type of topping
-#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, DisplayAsJson, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Topping {
     Lettuce,
     Tomato,
@@ -147,19 +249,6 @@ pub enum Topping {
     Bacon,
 }
 
-impl Topping {
-    fn price(&self) -> f64 {
-        match self {
-            Topping::Lettuce => 0.0,
-            Topping::Tomato => 0.0,
-            Topping::Onion => 0.0,
-            Topping::Pickle => 0.0,
-            Topping::Cheese => 1.0,
-            Topping::Bacon => 2.0,
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,16 +277,30 @@ mod tests {
                 MenuItem::Drink,
             ],
         };
-        let order = Order::from(order_request);
+        let order = Order::priced(order_request, &Menu::default()).unwrap();
         assert_eq!(
             order,
             Order {
                 id: None,
                 customer: "Alice".to_string(),
                 status: OrderStatus::Pending,
-                total: 20.0,
+                total: Money::from_cents(2000),
                 food,
             }
         );
     }
+
+    #[test]
+    fn test_order_status_transitions() {
+        use OrderStatus::*;
+        assert!(Pending.can_become(&Preparing));
+        assert!(Pending.can_become(&Cancelled));
+        assert!(Preparing.can_become(&Cancelled));
+        assert!(Transporting.can_become(&Completed));
+        assert!(Completed.can_become(&Completed));
+        assert!(!Pending.can_become(&Completed));
+        assert!(!Transporting.can_become(&Cancelled));
+        assert!(!Completed.can_become(&Pending));
+        assert!(!Cancelled.can_become(&Preparing));
+    }
 }
@@ -1,62 +1,327 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Read};
 use std::{fmt::Display, str::FromStr};
 
 use crate::error::AspirinEatsError;
-// use crate::food::{OrderRequest, Order};
-// use serde_json;
+
+/// Largest request line plus headers accepted by default, in bytes
+pub const MAX_HEADER_BYTES: usize = 8 * 1024;
+
+/// Largest request body accepted by default, in bytes
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Size limits applied while reading a request
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// Most bytes the request line and headers may take up together
+    pub max_header_bytes: usize,
+
+    /// Most bytes the body may take up, after any chunked encoding is removed
+    pub max_body_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_header_bytes: MAX_HEADER_BYTES,
+            max_body_bytes: MAX_BODY_BYTES,
+        }
+    }
+}
+
 /// Simple wrapper for an HTTP Request
 #[derive(Debug)]
 pub struct HttpRequest {
     /// The HTTP method used in the request (GET, POST, etc)
     pub method: Option<String>,
 
-    /// The path requested by the client
+    /// The path requested by the client, without the query string
     pub path: Option<String>,
 
+    /// The decoded parameters from the query string. When a name is repeated the
+    /// last value wins
+    pub query: HashMap<String, String>,
+
+    /// The protocol version from the request line, such as `HTTP/1.1`
+    pub version: Option<String>,
+
+    /// The request headers, keyed by lowercase name. Repeated headers are joined
+    /// with `, `
+    pub headers: HashMap<String, String>,
+
     /// The body of the request
     pub body: Option<String>,
 }
 
-impl FromStr for HttpRequest {
-    type Err = AspirinEatsError;
+impl HttpRequest {
+    /// Read one request from `reader`, taking exactly the bytes that belong to it so
+    /// that the next request on the same connection can be read after it. Returns
+    /// `Ok(None)` if the reader is at end of input before the request line starts
+    pub fn read_from<R: BufRead>(
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<Option<Self>, AspirinEatsError> {
+        let mut budget = limits.max_header_bytes;
+        let request_line = loop {
+            match read_line(reader, &mut budget, AspirinEatsError::UriTooLong)? {
+                None => return Ok(None),
+                // Clients may send blank lines between requests
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(path), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AspirinEatsError::InvalidRequest);
+        };
+        if method.is_empty() || path.is_empty() || !matches!(version, "HTTP/1.0" | "HTTP/1.1") {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
 
-    // Parse a string into an HTTP Request
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut lines = s.lines();
-        let request_line = lines.next().ok_or("Invalid request")?;
-        let mut parts = request_line.split_whitespace();
-        let method = parts.next().map(|s| s.to_string());
-        let path = parts.next().map(|s| s.to_string());
-
-        // Skip headers for simplicity
-        let mut headers_done = false;
-        let mut body: Option<String> = None;
-        for line in lines {
+        let mut headers: HashMap<String, String> = HashMap::new();
+        loop {
+            let line = read_line(reader, &mut budget, AspirinEatsError::HeadersTooLarge)?
+                .ok_or(AspirinEatsError::InvalidRequest)?;
             if line.is_empty() {
-                headers_done = true;
-                continue;
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(AspirinEatsError::InvalidRequest)?;
+            // Whitespace before the colon, or a continuation line, is not allowed
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(AspirinEatsError::InvalidRequest);
+            }
+            let value = value.trim();
+            headers
+                .entry(name.to_ascii_lowercase())
+                .and_modify(|existing| {
+                    existing.push_str(", ");
+                    existing.push_str(value);
+                })
+                .or_insert_with(|| value.to_string());
+        }
+
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path, parse_query(query)?),
+            None => (path, HashMap::new()),
+        };
+        let mut request = HttpRequest {
+            method: Some(method.to_string()),
+            path: Some(path.to_string()),
+            query,
+            version: Some(version.to_string()),
+            headers,
+            body: None,
+        };
+        let body = match request.body_length()? {
+            BodyLength::Chunked => read_chunked(reader, limits)?,
+            BodyLength::Exactly(0) => return Ok(Some(request)),
+            BodyLength::Exactly(length) => {
+                if length > limits.max_body_bytes {
+                    return Err(AspirinEatsError::PayloadTooLarge);
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).map_err(truncated)?;
+                body
+            }
+        };
+        request.body = Some(String::from_utf8(body).map_err(|_| {
+            AspirinEatsError::ParseError("request body is not valid UTF-8".to_string())
+        })?);
+        Ok(Some(request))
+    }
+
+    /// Whether the client wants the connection kept open after this request. That
+    /// is the default in HTTP/1.1 unless it sends `Connection: close`, while an
+    /// HTTP/1.0 client has to ask with `Connection: keep-alive`
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.header("connection").is_some_and(|connection| {
+                connection
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(option))
+            })
+        };
+        match self.version.as_deref() {
+            Some("HTTP/1.1") => !has_option("close"),
+            _ => has_option("keep-alive"),
+        }
+    }
+
+    /// The value of the header `name`, which is matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// How the body is delimited, from the `Content-Length` and `Transfer-Encoding`
+    /// headers
+    fn body_length(&self) -> Result<BodyLength, AspirinEatsError> {
+        match (
+            self.header("transfer-encoding"),
+            self.header("content-length"),
+        ) {
+            (None, None) => Ok(BodyLength::Exactly(0)),
+            // A request that says both could be read two ways by two servers
+            (Some(_), Some(_)) => Err(AspirinEatsError::InvalidRequest),
+            (Some(encoding), None) if encoding.eq_ignore_ascii_case("chunked") => {
+                Ok(BodyLength::Chunked)
             }
-            if headers_done {
-                if let Some(ref mut b) = body {
-                    b.push_str(line);
-                    b.push('\n');
-                } else {
-                    body = Some(line.to_string() + "\n");
+            (Some(_), None) => Err(AspirinEatsError::InvalidRequest),
+            (None, Some(length)) => {
+                // Repeated headers were joined, and are fine as long as they agree
+                let mut lengths = length.split(',').map(|n| n.trim().parse::<usize>());
+                let first = lengths.next();
+                match first {
+                    Some(Ok(n)) if lengths.all(|other| other.as_ref() == Ok(&n)) => {
+                        Ok(BodyLength::Exactly(n))
+                    }
+                    _ => Err(AspirinEatsError::InvalidRequest),
                 }
             }
         }
-        if body.as_ref().map_or(true, |b| b.is_empty()) {
-            body = None;
-        } else {
-            body = body.map(|b| b.trim_end().to_string());
+    }
+}
+
+/// How a request body is delimited
+enum BodyLength {
+    Exactly(usize),
+    Chunked,
+}
+
+/// Read one line ending in `\n` (with or without `\r`), charging it against
+/// `budget` and failing with `too_long` if it would go over. Returns `Ok(None)` at
+/// end of input
+fn read_line<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+    too_long: AspirinEatsError,
+) -> Result<Option<String>, AspirinEatsError> {
+    let mut line = Vec::new();
+    // One byte more than the budget tells a line that fits apart from one that doesn't
+    reader
+        .take(*budget as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.len() > *budget {
+        return Err(too_long);
+    }
+    *budget -= line.len();
+    if line.pop() != Some(b'\n') {
+        return Err(truncated(std::io::ErrorKind::UnexpectedEof.into()));
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| AspirinEatsError::InvalidRequest)
+}
+
+/// Read a body sent with `Transfer-Encoding: chunked`, discarding any trailers
+fn read_chunked<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Vec<u8>, AspirinEatsError> {
+    let mut body = Vec::new();
+    // Chunk size lines and trailers count against the header limit
+    let mut budget = limits.max_header_bytes;
+    loop {
+        let line = read_line(reader, &mut budget, AspirinEatsError::HeadersTooLarge)?
+            .ok_or(AspirinEatsError::InvalidRequest)?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| AspirinEatsError::InvalidRequest)?;
+        if size == 0 {
+            break;
+        }
+        if size > limits.max_body_bytes - body.len() {
+            return Err(AspirinEatsError::PayloadTooLarge);
         }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).map_err(truncated)?;
+        let end = read_line(reader, &mut budget, AspirinEatsError::HeadersTooLarge)?;
+        if end.as_deref() != Some("") {
+            return Err(AspirinEatsError::InvalidRequest);
+        }
+    }
+    while let Some(trailer) = read_line(reader, &mut budget, AspirinEatsError::HeadersTooLarge)? {
+        if trailer.is_empty() {
+            return Ok(body);
+        }
+    }
+    Err(AspirinEatsError::InvalidRequest)
+}
+
+/// Parse an `application/x-www-form-urlencoded` query string
+fn parse_query(query: &str) -> Result<HashMap<String, String>, AspirinEatsError> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(name)?, percent_decode(value)?))
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes, and `+` as a space
+fn percent_decode(s: &str) -> Result<String, AspirinEatsError> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or(AspirinEatsError::InvalidRequest)?;
+                bytes.push(hex);
+                rest = &rest[2..];
+            }
+            other => bytes.push(other),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| AspirinEatsError::InvalidRequest)
+}
 
-        Ok(HttpRequest { method, path, body })
+/// A request that ends early is malformed rather than an I/O failure
+fn truncated(error: std::io::Error) -> AspirinEatsError {
+    match error.kind() {
+        std::io::ErrorKind::UnexpectedEof => AspirinEatsError::InvalidRequest,
+        _ => AspirinEatsError::Io(error),
+    }
+}
+
+impl FromStr for HttpRequest {
+    type Err = AspirinEatsError;
+
+    // Parse a string into an HTTP Request. The body is delimited as it would be on
+    // a connection, except that without `Content-Length` whatever follows the
+    // headers is taken as the body
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut reader = s.as_bytes();
+        let mut request = Self::read_from(&mut reader, &Limits::default())?
+            .ok_or(AspirinEatsError::InvalidRequest)?;
+        if request.body.is_none() && !reader.is_empty() {
+            let rest = String::from_utf8_lossy(reader).trim_end().to_string();
+            request.body = Some(rest).filter(|rest| !rest.is_empty());
+        }
+        Ok(request)
     }
 }
 
 pub struct HttpResponse {
     pub status_code: u16,
     pub status_text: String,
+    /// Headers sent after `Content-Length` and `Content-Type`, in order
+    pub headers: Vec<(String, String)>,
     pub body: String,
 }
 
@@ -65,21 +330,31 @@ impl HttpResponse {
         HttpResponse {
             status_code,
             status_text: status_text.to_string(),
+            headers: Vec::new(),
             body: body.to_string(),
         }
     }
+
+    /// Add a header to the response
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
 }
 
 impl Display for HttpResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nContent-Type: application/json\r\n\r\n{}",
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nContent-Type: application/json\r\n",
             self.status_code,
             self.status_text,
             self.body.len(),
-            self.body
-        )
+        )?;
+        for (name, value) in &self.headers {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        write!(f, "\r\n{}", self.body)
     }
 }
 
@@ -94,14 +369,74 @@ impl From<AspirinEatsError> for HttpResponse {
             AspirinEatsError::MethodNotAllowed => {
                 HttpResponse::new(405, "Method Not Allowed", "Method not allowed")
             }
+            AspirinEatsError::Unauthorized => {
+                HttpResponse::new(401, "Unauthorized", "Missing or unknown bearer token")
+                    .with_header("WWW-Authenticate", "Bearer")
+            }
+            AspirinEatsError::Forbidden => {
+                HttpResponse::new(403, "Forbidden", "Not allowed for this role")
+            }
+            AspirinEatsError::InvalidTransition { from, to } => {
+                let body = serde_json::json!({
+                    "error": format!("Cannot change order status from {:?} to {:?}", from, to),
+                    "from": from,
+                    "to": to,
+                    "allowed": from.next_statuses(),
+                });
+                HttpResponse::new(409, "Conflict", &body.to_string())
+            }
+            AspirinEatsError::Unavailable(entry) => HttpResponse::new(
+                422,
+                "Unprocessable Entity",
+                &format!("{} is not available", entry),
+            ),
+            AspirinEatsError::PayloadTooLarge => {
+                HttpResponse::new(413, "Payload Too Large", "Request body too large")
+            }
+            AspirinEatsError::UriTooLong => {
+                HttpResponse::new(414, "URI Too Long", "Request line too long")
+            }
+            AspirinEatsError::HeadersTooLarge => HttpResponse::new(
+                431,
+                "Request Header Fields Too Large",
+                "Request headers too large",
+            ),
             _ => HttpResponse::new(500, "Internal Server Error", "Internal Server Error"),
         }
     }
 }
 
 #[cfg(test)]
+// The error test builds its I/O error the long way, which predates this lint
+#[allow(clippy::io_other_error)]
 mod tests {
     use super::*;
+    use crate::food::OrderStatus;
+    use std::io::BufReader;
+
+    /// A reader that hands out at most `step` bytes per read, like a socket whose
+    /// data arrives in several segments
+    struct Trickle<'a> {
+        data: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn read(data: &str, limits: &Limits) -> Result<Option<HttpRequest>, AspirinEatsError> {
+        let mut reader = BufReader::new(Trickle {
+            data: data.as_bytes(),
+            step: 3,
+        });
+        HttpRequest::read_from(&mut reader, limits)
+    }
 
     #[test]
     fn test_http_request_from_str() {
@@ -121,6 +456,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_http_response_headers() {
+        let response = HttpResponse::new(200, "OK", "{}").with_header("Connection", "close");
+        assert_eq!(
+            response.to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Type: application/json\r\nConnection: close\r\n\r\n{}"
+        );
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let keep_alive = |request: &str| HttpRequest::from_str(request).unwrap().keep_alive();
+        assert!(keep_alive("GET / HTTP/1.1\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.1\r\nConnection: Close\r\n\r\n"));
+        assert!(!keep_alive(
+            "GET / HTTP/1.1\r\nConnection: upgrade, close\r\n\r\n"
+        ));
+        assert!(!keep_alive("GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"
+        ));
+    }
+
     #[test]
     fn test_http_response_from_aspirin_eats_error() {
         let error = AspirinEatsError::InvalidRequest;
@@ -141,10 +499,163 @@ mod tests {
         assert_eq!(response.status_text, "Method Not Allowed");
         assert_eq!(response.body, "Method not allowed");
 
+        let response: HttpResponse = AspirinEatsError::Unauthorized.into();
+        assert_eq!(response.status_code, 401);
+        assert_eq!(
+            response.headers,
+            vec![("WWW-Authenticate".to_string(), "Bearer".to_string())]
+        );
+        let response: HttpResponse = AspirinEatsError::Forbidden.into();
+        assert_eq!(response.status_code, 403);
+
+        let error = AspirinEatsError::InvalidTransition {
+            from: OrderStatus::Completed,
+            to: OrderStatus::Cancelled,
+        };
+        let response: HttpResponse = error.into();
+        assert_eq!(response.status_code, 409);
+        assert_eq!(response.status_text, "Conflict");
+        assert_eq!(
+            response.body,
+            r#"{"allowed":[],"error":"Cannot change order status from Completed to Cancelled","from":"Completed","to":"Cancelled"}"#
+        );
+
         let error = AspirinEatsError::Io(std::io::Error::new(std::io::ErrorKind::Other, "test"));
         let response: HttpResponse = error.into();
         assert_eq!(response.status_code, 500);
         assert_eq!(response.status_text, "Internal Server Error");
         assert_eq!(response.body, "Internal Server Error");
     }
+
+    #[test]
+    fn test_read_headers_and_version() {
+        let request = read(
+            "POST /orders HTTP/1.1\r\nHost: localhost\r\nX-Tag: a\r\nx-tag:  b \r\n\r\n",
+            &Limits::default(),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.method.as_deref(), Some("POST"));
+        assert_eq!(request.version.as_deref(), Some("HTTP/1.1"));
+        assert_eq!(request.header("HOST"), Some("localhost"));
+        assert_eq!(request.header("x-tag"), Some("a, b"));
+        assert_eq!(request.body, None);
+    }
+
+    #[test]
+    fn test_read_query_string() {
+        let request = HttpRequest::from_str(
+            "GET /orders?status=Pending&customer=Amit+K%C3%B6nig&flag&sort=-total HTTP/1.1\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(request.path.as_deref(), Some("/orders"));
+        assert_eq!(
+            request.query.get("status").map(String::as_str),
+            Some("Pending")
+        );
+        assert_eq!(
+            request.query.get("customer").map(String::as_str),
+            Some("Amit König")
+        );
+        assert_eq!(request.query.get("flag").map(String::as_str), Some(""));
+        assert_eq!(
+            request.query.get("sort").map(String::as_str),
+            Some("-total")
+        );
+        assert!(HttpRequest::from_str("GET /?a=%zz HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_read_body_to_content_length() {
+        let body = "x".repeat(5000);
+        let data = format!(
+            "POST /orders HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}GET / HTTP/1.1\r\n\r\n",
+            body.len(),
+            body
+        );
+        let mut reader = BufReader::new(Trickle {
+            data: data.as_bytes(),
+            step: 700,
+        });
+        let first = HttpRequest::read_from(&mut reader, &Limits::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.body, Some(body));
+        let second = HttpRequest::read_from(&mut reader, &Limits::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.path.as_deref(), Some("/"));
+        assert!(HttpRequest::read_from(&mut reader, &Limits::default())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_read_chunked_body() {
+        let data = "POST /orders HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\nTrailer: x\r\n\r\n";
+        let request = read(data, &Limits::default()).unwrap().unwrap();
+        assert_eq!(request.body.as_deref(), Some("hello, world"));
+    }
+
+    #[test]
+    fn test_read_limits() {
+        let limits = Limits {
+            max_header_bytes: 64,
+            max_body_bytes: 10,
+        };
+        let long_header = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "c".repeat(100));
+        assert!(matches!(
+            read(&long_header, &limits),
+            Err(AspirinEatsError::HeadersTooLarge)
+        ));
+        let long_line = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100));
+        assert!(matches!(
+            read(&long_line, &limits),
+            Err(AspirinEatsError::UriTooLong)
+        ));
+        let large = "POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\nhello world";
+        assert!(matches!(
+            read(large, &limits),
+            Err(AspirinEatsError::PayloadTooLarge)
+        ));
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nhello \r\n5\r\nworld\r\n0\r\n\r\n";
+        assert!(matches!(
+            read(chunked, &limits),
+            Err(AspirinEatsError::PayloadTooLarge)
+        ));
+
+        let response: HttpResponse = AspirinEatsError::PayloadTooLarge.into();
+        assert_eq!(response.status_code, 413);
+        let response: HttpResponse = AspirinEatsError::UriTooLong.into();
+        assert_eq!(response.status_code, 414);
+        let response: HttpResponse = AspirinEatsError::HeadersTooLarge.into();
+        assert_eq!(response.status_code, 431);
+    }
+
+    #[test]
+    fn test_read_malformed_requests() {
+        for data in [
+            "GET /\r\n\r\n",
+            "GET / HTTP/2\r\n\r\n",
+            "GET / HTTP/1.1\r\nNo colon here\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost : x\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: x\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: 4\r\nContent-Length: 5\r\n\r\nbody!",
+            "POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort",
+            "POST / HTTP/1.1\r\nContent-Length: 4\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n",
+        ] {
+            assert!(
+                matches!(
+                    read(data, &Limits::default()),
+                    Err(AspirinEatsError::InvalidRequest)
+                ),
+                "{:?}",
+                data
+            );
+        }
+    }
 }
@@ -1,4 +1,11 @@
+pub mod auth;
+pub mod balancer;
 pub mod db;
 pub mod error;
 pub mod food;
 pub mod http;
+pub mod menu;
+mod migrations;
+pub mod money;
+pub mod router;
+pub mod workers;
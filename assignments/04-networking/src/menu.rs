@@ -0,0 +1,166 @@
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::error::AspirinEatsError;
+use crate::food::{MenuEntry, MenuItem};
+use crate::money::Money;
+
+/// The menu the server starts with when it isn't given a menu file
+pub const DEFAULT_MENU: &str = include_str!("../menu.json");
+
+/// The price of one menu entry, and whether it can be ordered right now
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MenuListing {
+    pub item: MenuEntry,
+    #[serde(deserialize_with = "price")]
+    pub price: Money,
+    #[serde(default = "available")]
+    pub available: bool,
+}
+
+fn available() -> bool {
+    true
+}
+
+/// Read a price, refusing one below zero
+fn price<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+    non_negative(Money::deserialize(deserializer)?)
+}
+
+/// Read a price that may be left out or `null`, refusing one below zero
+fn optional_price<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Money>, D::Error> {
+    Option::<Money>::deserialize(deserializer)?
+        .map(non_negative)
+        .transpose()
+}
+
+fn non_negative<E: de::Error>(price: Money) -> Result<Money, E> {
+    if price < Money::ZERO {
+        return Err(E::custom(format!("price {} is negative", price)));
+    }
+    Ok(price)
+}
+
+/// Struct that represents a request to change a menu listing. Fields left out keep
+/// their current value
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MenuUpdate {
+    #[serde(default, deserialize_with = "optional_price")]
+    pub price: Option<Money>,
+    pub available: Option<bool>,
+}
+
+/// The current menu, with a listing for each entry that can be priced
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Menu {
+    pub items: Vec<MenuListing>,
+}
+
+impl Menu {
+    /// Read a menu file: a JSON list of listings such as
+    /// `{"item": "Topping:Bacon", "price": "2.00", "available": true}`
+    pub fn from_config(json: &str) -> Result<Self, AspirinEatsError> {
+        let items: Vec<MenuListing> = serde_json::from_str(json)
+            .map_err(|e| AspirinEatsError::ParseError(format!("invalid menu: {}", e)))?;
+        for (i, listing) in items.iter().enumerate() {
+            if items[..i].iter().any(|other| other.item == listing.item) {
+                return Err(AspirinEatsError::ParseError(format!(
+                    "invalid menu: {} is listed twice",
+                    listing.item
+                )));
+            }
+        }
+        Ok(Menu { items })
+    }
+
+    /// The listing for `entry`, if the menu has one
+    pub fn listing(&self, entry: MenuEntry) -> Option<&MenuListing> {
+        self.items.iter().find(|listing| listing.item == entry)
+    }
+
+    /// The price of `item`, adding up each of its entries
+    pub fn price(&self, item: &MenuItem) -> Result<Money, AspirinEatsError> {
+        item.entries()
+            .into_iter()
+            .try_fold(Money::ZERO, |total, entry| match self.listing(entry) {
+                Some(listing) if listing.available => total + listing.price,
+                _ => Err(AspirinEatsError::Unavailable(entry)),
+            })
+    }
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Menu::from_config(DEFAULT_MENU).expect("the default menu should be valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::food::{Bun, Burger, Patty, Topping};
+
+    #[test]
+    fn test_default_menu_lists_everything() {
+        let menu = Menu::default();
+        for entry in MenuEntry::ALL {
+            assert!(menu.listing(entry).is_some(), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn test_price_from_menu() {
+        let mut menu = Menu::from_config(
+            r#"[
+                {"item": "Bun:Plain", "price": "0.50"},
+                {"item": "Patty:Veggie", "price": "6.25"},
+                {"item": "Topping:Cheese", "price": "1.10"},
+                {"item": "Topping:Bacon", "price": "2.00", "available": false}
+            ]"#,
+        )
+        .unwrap();
+        let burger = |toppings| MenuItem::Burger(Burger::new(Bun::Plain, Patty::Veggie, toppings));
+
+        let cheese = vec![Topping::Cheese, Topping::Cheese];
+        assert_eq!(menu.price(&burger(cheese)).unwrap(), Money::from_cents(895));
+        assert!(matches!(
+            menu.price(&burger(vec![Topping::Bacon])),
+            Err(AspirinEatsError::Unavailable(MenuEntry::Topping(
+                Topping::Bacon
+            )))
+        ));
+        assert!(matches!(
+            menu.price(&MenuItem::Fries),
+            Err(AspirinEatsError::Unavailable(MenuEntry::Fries))
+        ));
+
+        menu.items[3].available = true;
+        let bacon = menu.price(&burger(vec![Topping::Bacon])).unwrap();
+        assert_eq!(bacon, Money::from_cents(875));
+    }
+
+    #[test]
+    fn test_negative_prices() {
+        let config = r#"[{"item": "Fries", "price": "-1.00"}]"#;
+        assert!(Menu::from_config(config).is_err());
+        assert!(serde_json::from_str::<MenuUpdate>(r#"{"price": "-0.01"}"#).is_err());
+        assert!(serde_json::from_str::<MenuUpdate>(r#"{"price": -2}"#).is_err());
+
+        let free: MenuUpdate = serde_json::from_str(r#"{"price": "0.00"}"#).unwrap();
+        assert_eq!(free.price, Some(Money::ZERO));
+        let unchanged: MenuUpdate = serde_json::from_str(r#"{"available": false}"#).unwrap();
+        assert_eq!(unchanged.price, None);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        for config in [
+            "{}",
+            r#"[{"item": "Caviar", "price": "99.00"}]"#,
+            r#"[{"item": "Fries", "price": 1.005}]"#,
+            r#"[{"item": "Fries", "price": "1.00"}, {"item": "Fries", "price": "2.00"}]"#,
+        ] {
+            assert!(Menu::from_config(config).is_err(), "{}", config);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use rusqlite::{Connection, OptionalExtension, Transaction, TransactionBehavior};
+
+use crate::error::AspirinEatsError;
+
+type Result<T, E = AspirinEatsError> = std::result::Result<T, E>;
+
+/// A change to the schema, made inside the transaction `migrate` holds
+type Migration = fn(&Transaction) -> Result<()>;
+
+/// Every change made to the schema, oldest first. A database's `user_version` is
+/// the number of these it has had applied, so new steps only ever go on the end
+const MIGRATIONS: &[Migration] = &[
+    create_orders,
+    create_menu_items,
+    normalize_order_items,
+    create_credentials,
+];
+
+/// The schema version this build of the server expects
+pub const SCHEMA_VERSION: usize = MIGRATIONS.len();
+
+/// Bring the database up to `SCHEMA_VERSION`, applying the migrations it hasn't had
+/// yet. They run in one transaction that holds the write lock, so a failed step
+/// changes nothing and two servers starting at once don't both apply a step
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let version: usize = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > SCHEMA_VERSION {
+        return Err(AspirinEatsError::UnsupportedSchema(version));
+    }
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        migration(&tx)?;
+        tx.pragma_update(None, "user_version", applied + 1)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Databases from before migrations were tracked are at version 0 but may already
+/// have an orders table. Those with a `REAL` column of dollars are copied into a
+/// table of cents, since SQLite can't change a column's type in place
+fn create_orders(tx: &Transaction) -> Result<()> {
+    tx.execute(&create_orders_sql("IF NOT EXISTS orders"), [])?;
+    let total_type: String = tx.query_row(
+        "SELECT type FROM pragma_table_info('orders') WHERE name = 'total'",
+        [],
+        |row| row.get(0),
+    )?;
+    if total_type.eq_ignore_ascii_case("REAL") {
+        migrate_totals_to_cents(tx)?;
+    }
+    // Listing orders filters on these
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS orders_status ON orders (status, id);
+        CREATE INDEX IF NOT EXISTS orders_customer ON orders (customer, id);",
+    )?;
+    Ok(())
+}
+
+/// The statement that created the orders table under `name`, with food kept as a
+/// JSON array
+fn create_orders_sql(name: &str) -> String {
+    format!(
+        "CREATE TABLE {} (
+            id	        INTEGER NOT NULL,
+            customer	TEXT NOT NULL,
+            food        TEXT NOT NULL,
+            status	    TEXT NOT NULL,
+            total       INTEGER NOT NULL,
+            PRIMARY KEY(id AUTOINCREMENT)
+        )",
+        name
+    )
+}
+
+/// Copy the orders into a table with an `INTEGER` total, keeping their IDs and the
+/// next ID to be handed out
+fn migrate_totals_to_cents(tx: &Transaction) -> Result<()> {
+    let next_id: i64 = tx
+        .query_row(
+            "SELECT seq FROM sqlite_sequence WHERE name = 'orders'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+    tx.execute(&create_orders_sql("orders_cents"), [])?;
+    tx.execute_batch(
+        "INSERT INTO orders_cents (id, customer, food, status, total)
+            SELECT id, customer, food, status, CAST(ROUND(total * 100) AS INTEGER) FROM orders;
+        DROP TABLE orders;
+        ALTER TABLE orders_cents RENAME TO orders;",
+    )?;
+    tx.execute(
+        "UPDATE sqlite_sequence SET seq = MAX(seq, ?1) WHERE name = 'orders'",
+        [next_id],
+    )?;
+    Ok(())
+}
+
+/// Databases from before migrations were tracked may already have this table
+fn create_menu_items(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS menu_items (
+            item        TEXT NOT NULL PRIMARY KEY,
+            price       INTEGER NOT NULL,
+            available   INTEGER NOT NULL DEFAULT 1
+        );",
+    )?;
+    Ok(())
+}
+
+/// Give each thing ordered a row of its own, with a burger's toppings in a table
+/// beside it, instead of a JSON array on the order. Statuses go from JSON strings
+/// such as `"Pending"` to plain names
+fn normalize_order_items(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE order_items (
+            id          INTEGER NOT NULL PRIMARY KEY,
+            order_id    INTEGER NOT NULL REFERENCES orders (id) ON DELETE CASCADE,
+            position    INTEGER NOT NULL,
+            kind        TEXT NOT NULL,
+            bun         TEXT,
+            patty       TEXT,
+            UNIQUE (order_id, position)
+        );
+        CREATE TABLE order_item_toppings (
+            item_id     INTEGER NOT NULL REFERENCES order_items (id) ON DELETE CASCADE,
+            position    INTEGER NOT NULL,
+            topping     TEXT NOT NULL,
+            PRIMARY KEY (item_id, position)
+        );
+        CREATE INDEX order_items_bun ON order_items (bun);
+        CREATE INDEX order_items_patty ON order_items (patty);
+        CREATE INDEX order_items_kind ON order_items (kind);
+        CREATE INDEX order_item_toppings_topping ON order_item_toppings (topping);
+
+        -- Fries and drinks are JSON strings, burgers are {\"Burger\": {...}}
+        INSERT INTO order_items (order_id, position, kind, bun, patty)
+            SELECT orders.id, food.key,
+                CASE food.type WHEN 'text' THEN food.value ELSE 'Burger' END,
+                CASE food.type WHEN 'object' THEN json_extract(food.value, '$.Burger.bun') END,
+                CASE food.type WHEN 'object' THEN json_extract(food.value, '$.Burger.patty') END
+            FROM orders, json_each(orders.food) AS food;
+        INSERT INTO order_item_toppings (item_id, position, topping)
+            SELECT order_items.id, topping.key, topping.value
+            FROM orders, json_each(orders.food) AS food
+            JOIN order_items
+                ON order_items.order_id = orders.id AND order_items.position = food.key,
+            json_each(food.value, '$.Burger.toppings') AS topping
+            WHERE food.type = 'object';
+
+        ALTER TABLE orders DROP COLUMN food;
+        UPDATE orders SET status = json_extract(status, '$') WHERE json_valid(status);",
+    )?;
+    Ok(())
+}
+
+/// Tokens are kept as their hashes, with the name of who they were given to
+fn create_credentials(tx: &Transaction) -> Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE credentials (
+            token_hash  TEXT NOT NULL PRIMARY KEY,
+            name        TEXT NOT NULL,
+            role        TEXT NOT NULL
+        );",
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(conn: &Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    /// The first column of every row `sql` returns
+    fn strings(conn: &Connection, sql: &str) -> Vec<String> {
+        let mut stmt = conn.prepare(sql).unwrap();
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn test_migrates_new_database_once() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn), SCHEMA_VERSION);
+        // Nothing is left to apply the second time
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn), SCHEMA_VERSION);
+
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        assert!(matches!(
+            migrate(&mut conn),
+            Err(AspirinEatsError::UnsupportedSchema(version)) if version == SCHEMA_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn test_moves_food_into_order_items() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"CREATE TABLE orders (
+                id	        INTEGER NOT NULL,
+                customer	TEXT NOT NULL,
+                food        TEXT NOT NULL,
+                status	    TEXT NOT NULL,
+                total       INTEGER NOT NULL,
+                PRIMARY KEY(id AUTOINCREMENT)
+            );
+            INSERT INTO orders (customer, food, status, total) VALUES
+                ('Amit', '[{"Burger":{"bun":"Sesame","patty":"Beef","toppings":["Cheese","Bacon"]}},"Fries"]', '"Pending"', 1800),
+                ('Bea', '["Drink"]', '"Completed"', 300);"#,
+        )
+        .unwrap();
+        migrate(&mut conn).unwrap();
+
+        let items = strings(
+            &conn,
+            "SELECT order_id || ' ' || position || ' ' || kind || ' '
+                || ifnull(bun, '-') || ' ' || ifnull(patty, '-')
+            FROM order_items ORDER BY order_id, position",
+        );
+        assert_eq!(
+            items,
+            vec!["1 0 Burger Sesame Beef", "1 1 Fries - -", "2 0 Drink - -"]
+        );
+        let toppings = strings(
+            &conn,
+            "SELECT topping FROM order_item_toppings ORDER BY position",
+        );
+        assert_eq!(toppings, vec!["Cheese", "Bacon"]);
+        let statuses = strings(&conn, "SELECT status FROM orders ORDER BY id");
+        assert_eq!(statuses, vec!["Pending", "Completed"]);
+        let food_columns: i64 = conn
+            .query_row(
+                "SELECT count(*) FROM pragma_table_info('orders') WHERE name = 'food'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(food_columns, 0);
+    }
+}
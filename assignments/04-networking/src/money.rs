@@ -0,0 +1,221 @@
+use std::fmt::Display;
+use std::ops::Add;
+use std::str::FromStr;
+
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::AspirinEatsError;
+
+/// An amount of money, held exactly as a whole number of cents. It is stored in
+/// SQLite as an `INTEGER` of cents and written in JSON as a decimal string such as
+/// `"12.50"`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_cents(cents: i64) -> Self {
+        Money(cents)
+    }
+
+    pub const fn cents(self) -> i64 {
+        self.0
+    }
+}
+
+impl Add for Money {
+    type Output = Result<Money, AspirinEatsError>;
+
+    /// Fails rather than wrapping when the total is too large to hold, which a
+    /// client can reach by ordering enough of an expensive item
+    fn add(self, other: Money) -> Self::Output {
+        self.0
+            .checked_add(other.0)
+            .map(Money)
+            .ok_or_else(|| AspirinEatsError::ParseError("amount out of range".to_string()))
+    }
+}
+
+impl Display for Money {
+    /// Format as dollars and cents, such as `12.50` or `-0.05`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, cents / 100, cents % 100)
+    }
+}
+
+impl FromStr for Money {
+    type Err = AspirinEatsError;
+
+    /// Parse a decimal amount with at most two places after the point, such as
+    /// `12`, `12.5` or `-0.05`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AspirinEatsError::ParseError(format!("invalid amount: {:?}", s));
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        let all_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || fraction.len() > 2 || !all_digits(whole) || !all_digits(fraction) {
+            return Err(invalid());
+        }
+        let whole: i64 = whole.parse().map_err(|_| invalid())?;
+        let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+        let cents = whole
+            .checked_mul(100)
+            .and_then(|cents| cents.checked_add(fraction))
+            .ok_or_else(invalid)?;
+        Ok(Money(if negative { -cents } else { cents }))
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    /// Read a decimal string. Plain JSON numbers are accepted too, as long as they
+    /// are a whole number of cents, so totals written before amounts were strings
+    /// still load
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MoneyVisitor;
+
+        impl Visitor<'_> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("an amount such as \"12.50\"")
+            }
+
+            fn visit_str<E: de::Error>(self, s: &str) -> Result<Money, E> {
+                s.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, n: i64) -> Result<Money, E> {
+                n.checked_mul(100)
+                    .map(Money)
+                    .ok_or_else(|| E::custom("amount out of range"))
+            }
+
+            fn visit_u64<E: de::Error>(self, n: u64) -> Result<Money, E> {
+                i64::try_from(n)
+                    .map_err(|_| E::custom("amount out of range"))
+                    .and_then(|n| self.visit_i64(n))
+            }
+
+            fn visit_f64<E: de::Error>(self, n: f64) -> Result<Money, E> {
+                let cents = (n * 100.0).round();
+                if (n * 100.0 - cents).abs() > 1e-6 || cents.abs() > i64::MAX as f64 {
+                    return Err(E::custom(format!("{} is not a whole number of cents", n)));
+                }
+                Ok(Money(cents as i64))
+            }
+        }
+
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+impl ToSql for Money {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0))
+    }
+}
+
+impl FromSql for Money {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Integer(cents) => Ok(Money(cents)),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_and_parse() {
+        for (cents, text) in [(0, "0.00"), (5, "0.05"), (1250, "12.50"), (-1999, "-19.99")] {
+            assert_eq!(Money::from_cents(cents).to_string(), text);
+            assert_eq!(text.parse::<Money>().unwrap(), Money::from_cents(cents));
+        }
+        assert_eq!("12.5".parse::<Money>().unwrap(), Money::from_cents(1250));
+        assert_eq!("7".parse::<Money>().unwrap(), Money::from_cents(700));
+        for bad in [
+            "",
+            ".5",
+            "1.234",
+            "1,50",
+            "$1",
+            "1e3",
+            "--1",
+            "99999999999999999999",
+        ] {
+            assert!(bad.parse::<Money>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_sum_is_exact() {
+        // Adding 0.10 ten times as f64 gives 0.9999999999999999
+        let total = std::iter::repeat_n(Money::from_cents(10), 10)
+            .try_fold(Money::ZERO, Add::add)
+            .unwrap();
+        assert_eq!(total, Money::from_cents(100));
+    }
+
+    #[test]
+    fn test_add_overflow() {
+        let max = Money::from_cents(i64::MAX);
+        assert!(matches!(
+            max + Money::from_cents(1),
+            Err(AspirinEatsError::ParseError(_))
+        ));
+        assert!((Money::from_cents(i64::MIN) + Money::from_cents(-1)).is_err());
+        assert_eq!(
+            (max + Money::from_cents(-1)).unwrap(),
+            Money::from_cents(i64::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        assert_eq!(
+            serde_json::to_string(&Money::from_cents(2050)).unwrap(),
+            "\"20.50\""
+        );
+        for json in ["\"20.50\"", "20.5", "20.50"] {
+            assert_eq!(
+                serde_json::from_str::<Money>(json).unwrap(),
+                Money::from_cents(2050)
+            );
+        }
+        assert_eq!(
+            serde_json::from_str::<Money>("3").unwrap(),
+            Money::from_cents(300)
+        );
+        assert!(serde_json::from_str::<Money>("20.505").is_err());
+        assert!(serde_json::from_str::<Money>("\"abc\"").is_err());
+    }
+
+    #[test]
+    fn test_sqlite_integer() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        let (stored, kind): (Money, String) = conn
+            .query_row("SELECT ?1, typeof(?1)", [Money::from_cents(899)], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(stored, Money::from_cents(899));
+        assert_eq!(kind, "integer");
+    }
+}
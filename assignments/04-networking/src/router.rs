@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::auth::{Caller, Role};
+use crate::error::AspirinEatsError;
+use crate::http::{HttpRequest, HttpResponse};
+
+/// A function that answers a request, given the parameters captured from its path
+/// and the state shared by every handler (such as the database)
+pub type Handler<S> =
+    Box<dyn Fn(&HttpRequest, &Params, &S) -> Result<HttpResponse, AspirinEatsError> + Send + Sync>;
+
+/// A function that works out who is making a request, failing with `Unauthorized` if
+/// it can't
+pub type Authenticator<S> =
+    Box<dyn Fn(&HttpRequest, &S) -> Result<Caller, AspirinEatsError> + Send + Sync>;
+
+/// Parameters captured from a request path by a pattern like `/orders/:id`, and the
+/// caller the router authenticated for routes that need a role
+#[derive(Debug, Default, PartialEq)]
+pub struct Params {
+    path: HashMap<String, String>,
+    caller: Option<Caller>,
+}
+
+impl Params {
+    /// The parameter `name`, parsed as a `T`
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<T, AspirinEatsError> {
+        let value = self.path.get(name).ok_or(AspirinEatsError::NotFound)?;
+        value
+            .parse()
+            .map_err(|_| AspirinEatsError::ParseError(format!("invalid {}: {:?}", name, value)))
+    }
+
+    /// Who is making the request. Only routes registered with a role have one, so
+    /// handlers don't need to authenticate the request a second time
+    pub fn caller(&self) -> Result<&Caller, AspirinEatsError> {
+        self.caller.as_ref().ok_or(AspirinEatsError::Unauthorized)
+    }
+}
+
+/// One piece of a route pattern between slashes
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+struct Route<S> {
+    method: String,
+    pattern: Vec<Segment>,
+    /// The role a caller needs, or `None` if anyone may use the route
+    role: Option<Role>,
+    handler: Handler<S>,
+}
+
+/// Dispatches requests to handlers registered by method and path pattern
+pub struct Router<S> {
+    routes: Vec<Route<S>>,
+    authenticator: Option<Authenticator<S>>,
+}
+
+impl<S> Default for Router<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> Router<S> {
+    /// Create a router with no routes
+    pub fn new() -> Self {
+        Router {
+            routes: Vec::new(),
+            authenticator: None,
+        }
+    }
+
+    /// Use `authenticator` to find the callers of routes that need a role. Without
+    /// it, those routes turn every caller away
+    pub fn authenticate_with<F>(mut self, authenticator: F) -> Self
+    where
+        F: Fn(&HttpRequest, &S) -> Result<Caller, AspirinEatsError> + Send + Sync + 'static,
+    {
+        self.authenticator = Some(Box::new(authenticator));
+        self
+    }
+
+    /// Register `handler` for requests with `method` whose path matches `pattern`.
+    /// A pattern segment starting with `:` captures that segment of the path as a
+    /// parameter, so `/orders/:id` matches `/orders/15` with `id` set to `15`
+    pub fn route<F>(self, method: &str, pattern: &str, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest, &Params, &S) -> Result<HttpResponse, AspirinEatsError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.add_route(method, pattern, None, handler)
+    }
+
+    /// Register `handler` like `route`, for callers with `role` or a role above it.
+    /// Callers without credentials get a 401, and those with too low a role a 403
+    pub fn route_with_role<F>(self, method: &str, pattern: &str, role: Role, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest, &Params, &S) -> Result<HttpResponse, AspirinEatsError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.add_route(method, pattern, Some(role), handler)
+    }
+
+    fn add_route<F>(mut self, method: &str, pattern: &str, role: Option<Role>, handler: F) -> Self
+    where
+        F: Fn(&HttpRequest, &Params, &S) -> Result<HttpResponse, AspirinEatsError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let pattern = segments(pattern)
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(name) => Segment::Param(name.to_string()),
+                None => Segment::Literal(segment.to_string()),
+            })
+            .collect();
+        self.routes.push(Route {
+            method: method.to_ascii_uppercase(),
+            pattern,
+            role,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Answer `request` with the first route that matches its method and path. A
+    /// path that no route matches gets a 404, and a path that only matches routes
+    /// for other methods gets a 405 listing them in `Allow`
+    pub fn handle(&self, request: &HttpRequest, state: &S) -> HttpResponse {
+        let (Some(method), Some(path)) = (request.method.as_deref(), request.path.as_deref())
+        else {
+            return AspirinEatsError::InvalidRequest.into();
+        };
+        let mut allowed: Vec<&str> = Vec::new();
+        for route in &self.routes {
+            let Some(mut params) = route.matches(path) else {
+                continue;
+            };
+            if route.method == method {
+                return self
+                    .authorize(route, request, state)
+                    .and_then(|caller| {
+                        params.caller = caller;
+                        (route.handler)(request, &params, state)
+                    })
+                    .unwrap_or_else(HttpResponse::from);
+            }
+            if !allowed.contains(&route.method.as_str()) {
+                allowed.push(&route.method);
+            }
+        }
+        if allowed.is_empty() {
+            return AspirinEatsError::NotFound.into();
+        }
+        allowed.sort_unstable();
+        HttpResponse::from(AspirinEatsError::MethodNotAllowed)
+            .with_header("Allow", &allowed.join(", "))
+    }
+
+    /// Check that the caller making `request` may use `route`, returning who they are
+    /// if the route needs a role
+    fn authorize(
+        &self,
+        route: &Route<S>,
+        request: &HttpRequest,
+        state: &S,
+    ) -> Result<Option<Caller>, AspirinEatsError> {
+        let Some(required) = route.role else {
+            return Ok(None);
+        };
+        let authenticator = self
+            .authenticator
+            .as_ref()
+            .ok_or(AspirinEatsError::Unauthorized)?;
+        let caller = authenticator(request, state)?;
+        if caller.role.allows(required) {
+            Ok(Some(caller))
+        } else {
+            Err(AspirinEatsError::Forbidden)
+        }
+    }
+}
+
+impl<S> Route<S> {
+    /// The parameters captured from `path`, if it matches this route's pattern
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut params = HashMap::new();
+        let mut parts = segments(path);
+        for segment in &self.pattern {
+            let part = parts.next()?;
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    params.insert(name.clone(), part.to_string());
+                }
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Params {
+                path: params,
+                caller: None,
+            }),
+        }
+    }
+}
+
+/// The non-empty segments of a path, so that `/orders/` and `/orders` are the same
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> Router<String> {
+        Router::new()
+            .route("GET", "/", |_, _, state: &String| {
+                Ok(HttpResponse::new(200, "OK", state))
+            })
+            .route("GET", "/orders/:id", |_, params, _| {
+                let id: i64 = params.get("id")?;
+                Ok(HttpResponse::new(200, "OK", &id.to_string()))
+            })
+            .route_with_role("DELETE", "/orders/:id", Role::Kitchen, |_, params, _| {
+                let caller = params.caller()?;
+                Ok(HttpResponse::new(
+                    200,
+                    "OK",
+                    &format!("deleted by {}", caller.name),
+                ))
+            })
+            .route("GET", "/orders/:id/items/:item", |_, params, _| {
+                let item: String = params.get("item")?;
+                Ok(HttpResponse::new(200, "OK", &item))
+            })
+    }
+
+    fn request(method: &str, path: &str) -> HttpResponse {
+        request_as(method, path, "")
+    }
+
+    /// Send a request with `Authorization: Bearer <token>`, where the token is the
+    /// name of the caller's role
+    fn request_as(method: &str, path: &str, token: &str) -> HttpResponse {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nAuthorization: Bearer {}\r\n\r\n",
+            method, path, token
+        );
+        let request = HttpRequest::from_str(&raw).unwrap();
+        let router = router().authenticate_with(|request, _| {
+            let token = crate::auth::bearer_token(request).ok_or(AspirinEatsError::Unauthorized)?;
+            Ok(Caller {
+                name: token.to_string(),
+                role: token.parse().map_err(|_| AspirinEatsError::Unauthorized)?,
+            })
+        });
+        router.handle(&request, &"welcome".to_string())
+    }
+
+    #[test]
+    fn test_route_dispatch() {
+        assert_eq!(request("GET", "/").body, "welcome");
+        assert_eq!(request("GET", "/orders/15").body, "15");
+        assert_eq!(request("GET", "/orders/15/").body, "15");
+        assert_eq!(
+            request_as("DELETE", "/orders/15", "kitchen").body,
+            "deleted by kitchen"
+        );
+        assert_eq!(request("GET", "/orders/15/items/fries").body, "fries");
+        assert_eq!(request("GET", "/orders/15?verbose=1").body, "15");
+    }
+
+    #[test]
+    fn test_route_roles() {
+        assert_eq!(request_as("DELETE", "/orders/15", "admin").status_code, 200);
+        assert_eq!(
+            request_as("DELETE", "/orders/15", "kitchen").status_code,
+            200
+        );
+        assert_eq!(
+            request_as("DELETE", "/orders/15", "customer").status_code,
+            403
+        );
+        assert_eq!(request_as("DELETE", "/orders/15", "guess").status_code, 401);
+        assert_eq!(request("DELETE", "/orders/15").status_code, 401);
+        // Routes without a role don't look at credentials
+        assert_eq!(request_as("GET", "/orders/15", "guess").status_code, 200);
+
+        // Without an authenticator, nobody gets through
+        let request = HttpRequest::from_str("DELETE /orders/15 HTTP/1.1\r\n\r\n").unwrap();
+        let response = router().handle(&request, &String::new());
+        assert_eq!(response.status_code, 401);
+    }
+
+    #[test]
+    fn test_typed_params() {
+        assert_eq!(request("GET", "/orders/abc").status_code, 400);
+        assert_eq!(
+            Params::default().get::<i64>("id").unwrap_err().to_string(),
+            "Not found"
+        );
+    }
+
+    #[test]
+    fn test_unknown_path_and_method() {
+        assert_eq!(request("GET", "/menu").status_code, 404);
+        assert_eq!(request("GET", "/orders/15/items").status_code, 404);
+
+        let response = request("PUT", "/orders/15");
+        assert_eq!(response.status_code, 405);
+        assert_eq!(
+            response.headers,
+            vec![("Allow".to_string(), "DELETE, GET".to_string())]
+        );
+    }
+}
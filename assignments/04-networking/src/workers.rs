@@ -0,0 +1,180 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of threads that run jobs from a bounded queue
+pub struct WorkerPool {
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    load: Load,
+}
+
+/// How busy a pool is. Jobs can hold on to this to see whether others are waiting
+/// for them to finish
+#[derive(Debug, Clone)]
+pub struct Load {
+    /// Jobs handed to the pool that haven't finished, running or queued
+    jobs: Arc<AtomicUsize>,
+    size: usize,
+}
+
+impl Load {
+    /// Whether every worker is busy and at least one job is waiting for a turn
+    pub fn is_saturated(&self) -> bool {
+        self.jobs.load(Ordering::SeqCst) > self.size
+    }
+}
+
+/// Counts a job as finished when dropped, even if the job panicked
+struct Finished(Arc<AtomicUsize>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl WorkerPool {
+    /// Start `size` worker threads. Up to `queue` jobs can wait for a free worker
+    /// before `execute` blocks
+    pub fn new(size: usize, queue: usize) -> Self {
+        assert!(size > 0, "a worker pool needs at least one thread");
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || work(&receiver))
+            })
+            .collect();
+        WorkerPool {
+            sender: Some(sender),
+            workers,
+            load: Load {
+                jobs: Arc::new(AtomicUsize::new(0)),
+                size,
+            },
+        }
+    }
+
+    /// A handle to how busy the pool is, which stays up to date
+    pub fn load(&self) -> Load {
+        self.load.clone()
+    }
+
+    /// Run `job` on the next free worker. When every worker is busy and the queue
+    /// is full this waits, which holds back the caller instead of letting work pile
+    /// up without bound
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Some(sender) = &self.sender {
+            let jobs = Arc::clone(&self.load.jobs);
+            jobs.fetch_add(1, Ordering::SeqCst);
+            let finished = Finished(jobs);
+            // Workers only stop once the sender is dropped, so this cannot fail
+            let _ = sender.send(Box::new(move || {
+                let _finished = finished;
+                job();
+            }));
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    /// Let the queued jobs finish, then stop the workers
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Take jobs off the queue until it is closed. A job that panics takes down only
+/// itself, not the worker
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(poisoned) => poisoned.into_inner().recv(),
+        };
+        match job {
+            Ok(job) => {
+                let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn test_runs_every_job() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let pool = WorkerPool::new(4, 2);
+        for _ in 0..100 {
+            let count = Arc::clone(&count);
+            pool.execute(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn test_jobs_run_at_the_same_time() {
+        // Each job waits for the others, so this only finishes if all four run at once
+        let barrier = Arc::new(Barrier::new(4));
+        let pool = WorkerPool::new(4, 0);
+        for _ in 0..4 {
+            let barrier = Arc::clone(&barrier);
+            pool.execute(move || {
+                barrier.wait();
+            });
+        }
+    }
+
+    #[test]
+    fn test_survives_a_panicking_job() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let pool = WorkerPool::new(1, 4);
+        pool.execute(|| panic!("job failed"));
+        let counter = Arc::clone(&count);
+        pool.execute(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        drop(pool);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_load() {
+        let pool = WorkerPool::new(1, 2);
+        let load = pool.load();
+        let (release, wait) = mpsc::channel::<()>();
+        let (started, running) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = wait.recv();
+        });
+        running.recv().unwrap();
+        // The only worker is busy, but nothing is waiting for it yet
+        assert!(!load.is_saturated());
+        pool.execute(|| {});
+        assert!(load.is_saturated());
+
+        drop(release);
+        drop(pool);
+        assert!(!load.is_saturated());
+    }
+}
@@ -0,0 +1,76 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use jq_rs::value::Value;
+use serde_json::json;
+use std::fs;
+use std::rc::Rc;
+
+fn sample(name: &str) -> Value {
+    let path = format!("{}/sample_data/{}", env!("CARGO_MANIFEST_DIR"), name);
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+/// The players from `football.json`, repeated until the document is a few megabytes
+fn large_document() -> Value {
+    let players = sample("football.json");
+    let players = players.as_array().unwrap();
+    let repeated: Vec<Value> = players.iter().cycle().take(20_000).cloned().collect();
+    Value::from(json!({ "players": repeated, "students": sample("students.json") }))
+}
+
+/// Times the filter alone: the document is parsed once and shared by every iteration
+fn bench_filter(c: &mut Criterion, name: &str, program: &str, input: &Value) {
+    let input = Rc::new(input.clone());
+    c.bench_function(name, |b| {
+        b.iter(|| jq_rs::run_value(program, input.clone()).unwrap())
+    });
+}
+
+fn sample_data(c: &mut Criterion) {
+    let all_types = sample("all_types.json");
+    bench_filter(c, "sample/identity", ".", &all_types);
+    bench_filter(c, "sample/keys", "keys", &all_types);
+    let football = sample("football.json");
+    bench_filter(
+        c,
+        "sample/select",
+        "[.[] | select(.goals > 50) | .name]",
+        &football,
+    );
+}
+
+/// `cargo bench --bench eval -- --quick`, before and after arrays and objects were shared
+/// rather than copied whenever a filter picked them out:
+///
+/// | bench              | copied  | shared  |
+/// |--------------------|---------|---------|
+/// | large/index        | 4.85µs  | 4.13µs  |
+/// | large/nested_index | 4.72µs  | 3.36µs  |
+/// | large/object       | 40.8ms  | 6.14µs  |
+/// | large/map          | 49.4ms  | 4.36ms  |
+/// | large/select       | 67.5ms  | 37.3ms  |
+fn large_document_filters(c: &mut Criterion) {
+    let document = large_document();
+    bench_filter(c, "large/index", ".players[0].name", &document);
+    bench_filter(
+        c,
+        "large/nested_index",
+        ".students.metadata[1].Name",
+        &document,
+    );
+    bench_filter(
+        c,
+        "large/object",
+        "{count: (.players | length), first: .players[0].name}",
+        &document,
+    );
+    bench_filter(c, "large/map", ".players | map(.goals) | add", &document);
+    bench_filter(
+        c,
+        "large/select",
+        "[.players[] | select(.favorite_move.success_rate > 80) | .team] | length",
+        &document,
+    );
+}
+
+criterion_group!(benches, sample_data, large_document_filters);
+criterion_main!(benches);
@@ -0,0 +1,164 @@
+use crate::value::Value;
+
+/// A parsed jq program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// `.`
+    Identity,
+    /// A constant such as `1`, `"foo"` or `null`
+    Literal(Value),
+    /// `"text \(expr) text"`
+    Interpolate(Vec<StringPart>),
+    /// `term[index]`, including `.foo` which is sugar for `.["foo"]`
+    Index(Box<Filter>, Box<Filter>),
+    /// `term[start:end]`; a missing bound is `null`
+    Slice(Box<Filter>, Box<Filter>, Box<Filter>),
+    /// `term[]`
+    Iterate(Box<Filter>),
+    /// `[body]`, or `[]` when there is no body
+    Array(Option<Box<Filter>>),
+    /// `{key: value, ...}`
+    Object(Vec<(Filter, Filter)>),
+    /// `lhs | rhs`
+    Pipe(Box<Filter>, Box<Filter>),
+    /// `lhs, rhs`
+    Comma(Box<Filter>, Box<Filter>),
+    /// `-term`
+    Neg(Box<Filter>),
+    /// Arithmetic and comparison operators
+    Binary(BinaryOp, Box<Filter>, Box<Filter>),
+    /// `lhs and rhs`
+    And(Box<Filter>, Box<Filter>),
+    /// `lhs or rhs`
+    Or(Box<Filter>, Box<Filter>),
+    /// `lhs // rhs`
+    Alternative(Box<Filter>, Box<Filter>),
+    /// `path = value`, `path |= update` and the arithmetic update operators
+    Assign(AssignOp, Box<Filter>, Box<Filter>),
+    /// `if cond then a else b end`; `elif` chains nest in the else branch
+    If(Box<Filter>, Box<Filter>, Option<Box<Filter>>),
+    /// `try body catch handler`, and the postfix `?` operator
+    Try(Box<Filter>, Option<Box<Filter>>),
+    /// `reduce source as $name (init; update)`
+    Reduce(Box<Filter>, String, Box<Filter>, Box<Filter>),
+    /// `foreach source as $name (init; update; extract)`
+    Foreach(
+        Box<Filter>,
+        String,
+        Box<Filter>,
+        Box<Filter>,
+        Option<Box<Filter>>,
+    ),
+    /// `source as $name | body`
+    Bind(Box<Filter>, String, Box<Filter>),
+    /// `$name`
+    Var(String),
+    /// `name` or `name(arg; ...)`
+    Call(String, Vec<Filter>),
+    /// `def name(params): body; rest`
+    Def(Box<FuncDef>, Box<Filter>),
+}
+
+impl Filter {
+    /// The filters directly inside this one, including the bodies of definitions
+    pub fn children(&self) -> Vec<&Filter> {
+        match self {
+            Filter::Identity | Filter::Literal(_) | Filter::Var(_) => Vec::new(),
+            Filter::Interpolate(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    StringPart::Expr(expr) => Some(expr),
+                    StringPart::Literal(_) => None,
+                })
+                .collect(),
+            Filter::Iterate(a) | Filter::Neg(a) => vec![a],
+            Filter::Array(body) => body.iter().map(|body| &**body).collect(),
+            Filter::Object(entries) => entries.iter().flat_map(|(k, v)| [k, v]).collect(),
+            Filter::Index(a, b)
+            | Filter::Pipe(a, b)
+            | Filter::Comma(a, b)
+            | Filter::Binary(_, a, b)
+            | Filter::And(a, b)
+            | Filter::Or(a, b)
+            | Filter::Alternative(a, b)
+            | Filter::Assign(_, a, b)
+            | Filter::Bind(a, _, b) => vec![a, b],
+            Filter::Slice(a, b, c) | Filter::Reduce(a, _, b, c) => vec![a, b, c],
+            Filter::If(a, b, c) => {
+                let mut children: Vec<&Filter> = vec![a, b];
+                children.extend(c.iter().map(|c| &**c));
+                children
+            }
+            Filter::Foreach(a, _, b, c, d) => {
+                let mut children: Vec<&Filter> = vec![a, b, c];
+                children.extend(d.iter().map(|d| &**d));
+                children
+            }
+            Filter::Try(body, handler) => {
+                let mut children: Vec<&Filter> = vec![body];
+                children.extend(handler.iter().map(|h| &**h));
+                children
+            }
+            Filter::Call(_, args) => args.iter().collect(),
+            Filter::Def(def, rest) => vec![&def.body, rest],
+        }
+    }
+}
+
+/// A piece of an interpolated string literal
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Literal(String),
+    Expr(Filter),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssignOp {
+    /// `=`
+    Set,
+    /// `|=`
+    Update,
+    /// `+=`, `-=`, `*=`, `/=` and `%=`
+    Arithmetic(BinaryOp),
+    /// `//=`
+    Alternative,
+}
+
+/// A function defined with `def`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub body: Filter,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    /// `def f(g): ...` receives `g` as a filter
+    Filter(String),
+    /// `def f($g): ...` receives each output of `g` as the variable `$g`
+    Value(String),
+}
+
+impl Param {
+    pub fn name(&self) -> &str {
+        match self {
+            Param::Filter(name) | Param::Value(name) => name,
+        }
+    }
+}
@@ -0,0 +1,1034 @@
+use std::rc::Rc;
+use std::sync::OnceLock;
+
+use crate::ast::Filter;
+use crate::error::JqError;
+use crate::eval::{
+    self, eval, eval_paths, flat_map_ok, single, Env, Path, PathStream, Shared, Stream,
+};
+use crate::filters;
+use crate::parser;
+use crate::value::Value;
+
+/// Builtins that are easiest to express in jq itself
+const PRELUDE: &str = r#"
+def select(f): if f then . else empty end;
+def recurse(f): def r: ., (f | r); r;
+def recurse: recurse(.[]?);
+def map(f): [.[] | f];
+def del(f): delpaths([path(f)]);
+def values: select(. != null);
+def nulls: select(. == null);
+def booleans: select(type == "boolean");
+def numbers: select(type == "number");
+def strings: select(type == "string");
+def arrays: select(type == "array");
+def objects: select(type == "object");
+def iterables: select(type | . == "array" or . == "object");
+def scalars: select(type | . != "array" and . != "object");
+def finites: select(isinfinite or isnan | not);
+def normals: select(isnormal);
+def first(f): limit(1; f);
+def first: .[0];
+def last: .[-1];
+def nth($n): .[$n];
+def nth($n; f): if $n < 0 then error("Out of bounds negative array index") else last(limit($n + 1; f)) end;
+def isempty(g): first((g | false), true);
+def any(generator; condition): isempty(first(generator | condition or empty)) | not;
+def all(generator; condition): isempty(first(generator | condition and empty));
+def any(condition): any(.[]; condition);
+def all(condition): all(.[]; condition);
+def any: any(.);
+def all: all(.);
+def flatten: flatten(1e9);
+def combinations: if length == 0 then [] else .[0][] as $x | (.[1:] | combinations) as $w | [$x] + $w end;
+def combinations(n): . as $dot | [range(n)] | map($dot) | combinations;
+def index($i): indices($i) | .[0];
+def rindex($i): indices($i) | .[-1:][0];
+def inside(xs): . as $x | xs | contains($x);
+def IN(s): any(s == .; .);
+def IN(source; s): any(source == s; .);
+def INDEX(stream; idx_expr): reduce stream as $row ({}; .[$row | idx_expr | tostring] |= $row);
+def INDEX(idx_expr): INDEX(.[]; idx_expr);
+def splits($re; flags): split($re; flags) | .[];
+def splits($re): splits($re; null);
+.
+"#;
+
+/// Extend `env` with the definitions from the prelude
+pub(crate) fn prelude(mut env: Env<'_>) -> Env<'_> {
+    static PARSED: OnceLock<Filter> = OnceLock::new();
+    let mut filter = PARSED.get_or_init(|| parser::parse(PRELUDE).expect("prelude should parse"));
+    while let Filter::Def(def, rest) = filter {
+        env = env.bind_func(def);
+        filter = rest;
+    }
+    env
+}
+
+type StreamFn = for<'a> fn(&'a [Filter], &Env<'a>, Shared) -> Stream<'a>;
+type PathFn = for<'a> fn(&'a [Filter], &Env<'a>, Path) -> PathStream<'a>;
+
+/// How a builtin implemented in Rust is evaluated
+enum Native {
+    /// Maps each input to exactly one output
+    Unary(fn(&Value) -> Result<Value, JqError>),
+    /// Called once per combination of its arguments' outputs
+    Values(fn(&Value, &[Shared]) -> Result<Value, JqError>),
+    /// Controls evaluation of its arguments itself. Builtins that can appear in path
+    /// expressions, such as `empty`, also provide a path implementation
+    Stream(StreamFn, Option<PathFn>),
+}
+
+const NATIVES: &[(&str, usize, Native)] = &[
+    ("empty", 0, Native::Stream(empty, Some(empty_paths))),
+    ("error", 0, Native::Unary(|v| Err(JqError::User(v.clone())))),
+    (
+        "error",
+        1,
+        Native::Values(|_, args| Err(JqError::User((*args[0]).clone()))),
+    ),
+    (
+        "not",
+        0,
+        Native::Unary(|v| Ok(Value::Bool(!filters::is_truthy(v)))),
+    ),
+    ("path", 1, Native::Stream(path, None)),
+    ("getpath", 1, Native::Stream(getpath, Some(getpath_paths))),
+    // Arrays and strings
+    (
+        "flatten",
+        1,
+        Native::Values(|v, a| filters::flatten(v, &a[0])),
+    ),
+    ("transpose", 0, Native::Unary(filters::transpose)),
+    (
+        "indices",
+        1,
+        Native::Values(|v, a| filters::indices(v, &a[0])),
+    ),
+    (
+        "contains",
+        1,
+        Native::Values(|v, a| filters::contains(v, &a[0]).map(Value::Bool)),
+    ),
+    (
+        "bsearch",
+        1,
+        Native::Values(|v, a| filters::bsearch(v, &a[0])),
+    ),
+    ("split", 1, Native::Values(|v, a| filters::split(v, &a[0]))),
+    (
+        "split",
+        2,
+        Native::Values(|v, a| filters::split_regex(v, &a[0], &a[1])),
+    ),
+    // Generators. These stay lazy so `first(range(1e9))` stops after one output
+    ("range", 1, Native::Stream(range, None)),
+    ("range", 2, Native::Stream(range, None)),
+    ("range", 3, Native::Stream(range, None)),
+    ("limit", 2, Native::Stream(limit, Some(limit_paths))),
+    ("last", 1, Native::Stream(last, Some(last_paths))),
+    ("repeat", 1, Native::Stream(repeat, None)),
+    ("while", 2, Native::Stream(while_, None)),
+    ("until", 2, Native::Stream(until, None)),
+    ("input", 0, Native::Stream(input, None)),
+    ("inputs", 0, Native::Stream(inputs, None)),
+    ("delpaths", 1, Native::Values(delpaths)),
+    ("length", 0, Native::Unary(filters::length)),
+    ("add", 0, Native::Unary(filters::add)),
+    ("keys", 0, Native::Unary(|v| filters::keys(v, true))),
+    (
+        "keys_unsorted",
+        0,
+        Native::Unary(|v| filters::keys(v, false)),
+    ),
+    (
+        "has",
+        1,
+        Native::Values(|v, args| filters::has(v, &args[0])),
+    ),
+    // Types and conversions
+    (
+        "type",
+        0,
+        Native::Unary(|v| Ok(Value::from(filters::type_name(v)))),
+    ),
+    ("tostring", 0, Native::Unary(|v| Ok(filters::tostring(v)))),
+    ("tonumber", 0, Native::Unary(filters::tonumber)),
+    ("tojson", 0, Native::Unary(|v| Ok(filters::tojson(v)))),
+    ("fromjson", 0, Native::Unary(filters::fromjson)),
+    (
+        "infinite",
+        0,
+        Native::Unary(|_| Ok(filters::number(f64::INFINITY))),
+    ),
+    ("nan", 0, Native::Unary(|_| Ok(filters::number(f64::NAN)))),
+    ("isinfinite", 0, Native::Unary(filters::is_infinite)),
+    ("isnan", 0, Native::Unary(filters::is_nan)),
+    ("isnormal", 0, Native::Unary(filters::is_normal)),
+    // Math, backed by libm like jq's own
+    ("acos", 0, Native::Unary(|v| filters::math(v, libm::acos))),
+    ("acosh", 0, Native::Unary(|v| filters::math(v, libm::acosh))),
+    ("asin", 0, Native::Unary(|v| filters::math(v, libm::asin))),
+    ("asinh", 0, Native::Unary(|v| filters::math(v, libm::asinh))),
+    ("atan", 0, Native::Unary(|v| filters::math(v, libm::atan))),
+    ("atanh", 0, Native::Unary(|v| filters::math(v, libm::atanh))),
+    ("cbrt", 0, Native::Unary(|v| filters::math(v, libm::cbrt))),
+    ("ceil", 0, Native::Unary(|v| filters::math(v, libm::ceil))),
+    ("cos", 0, Native::Unary(|v| filters::math(v, libm::cos))),
+    ("cosh", 0, Native::Unary(|v| filters::math(v, libm::cosh))),
+    ("exp", 0, Native::Unary(|v| filters::math(v, libm::exp))),
+    ("exp10", 0, Native::Unary(|v| filters::math(v, libm::exp10))),
+    ("exp2", 0, Native::Unary(|v| filters::math(v, libm::exp2))),
+    ("expm1", 0, Native::Unary(|v| filters::math(v, libm::expm1))),
+    ("fabs", 0, Native::Unary(|v| filters::math(v, libm::fabs))),
+    ("floor", 0, Native::Unary(|v| filters::math(v, libm::floor))),
+    (
+        "gamma",
+        0,
+        Native::Unary(|v| filters::math(v, libm::lgamma)),
+    ),
+    ("j0", 0, Native::Unary(|v| filters::math(v, libm::j0))),
+    ("j1", 0, Native::Unary(|v| filters::math(v, libm::j1))),
+    (
+        "lgamma",
+        0,
+        Native::Unary(|v| filters::math(v, libm::lgamma)),
+    ),
+    ("log", 0, Native::Unary(|v| filters::math(v, libm::log))),
+    ("log10", 0, Native::Unary(|v| filters::math(v, libm::log10))),
+    ("log1p", 0, Native::Unary(|v| filters::math(v, libm::log1p))),
+    ("log2", 0, Native::Unary(|v| filters::math(v, libm::log2))),
+    ("logb", 0, Native::Unary(|v| filters::math(v, logb))),
+    (
+        "nearbyint",
+        0,
+        Native::Unary(|v| filters::math(v, libm::rint)),
+    ),
+    ("pow10", 0, Native::Unary(|v| filters::math(v, libm::exp10))),
+    ("rint", 0, Native::Unary(|v| filters::math(v, libm::rint))),
+    ("round", 0, Native::Unary(|v| filters::math(v, libm::round))),
+    (
+        "significand",
+        0,
+        Native::Unary(|v| filters::math(v, significand)),
+    ),
+    ("sin", 0, Native::Unary(|v| filters::math(v, libm::sin))),
+    ("sinh", 0, Native::Unary(|v| filters::math(v, libm::sinh))),
+    ("sqrt", 0, Native::Unary(|v| filters::math(v, libm::sqrt))),
+    ("tan", 0, Native::Unary(|v| filters::math(v, libm::tan))),
+    ("tanh", 0, Native::Unary(|v| filters::math(v, libm::tanh))),
+    (
+        "tgamma",
+        0,
+        Native::Unary(|v| filters::math(v, libm::tgamma)),
+    ),
+    ("trunc", 0, Native::Unary(|v| filters::math(v, libm::trunc))),
+    ("y0", 0, Native::Unary(|v| filters::math(v, libm::y0))),
+    ("y1", 0, Native::Unary(|v| filters::math(v, libm::y1))),
+    ("frexp", 0, Native::Unary(frexp)),
+    ("modf", 0, Native::Unary(modf)),
+    ("lgamma_r", 0, Native::Unary(lgamma_r)),
+    (
+        "atan2",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::atan2)),
+    ),
+    (
+        "copysign",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::copysign)),
+    ),
+    (
+        "drem",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::remainder)),
+    ),
+    (
+        "fdim",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::fdim)),
+    ),
+    (
+        "fmax",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::fmax)),
+    ),
+    (
+        "fmin",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::fmin)),
+    ),
+    (
+        "fmod",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::fmod)),
+    ),
+    (
+        "hypot",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::hypot)),
+    ),
+    (
+        "ldexp",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], scalb)),
+    ),
+    (
+        "nextafter",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::nextafter)),
+    ),
+    (
+        "nexttoward",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::nextafter)),
+    ),
+    (
+        "pow",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], libm::pow)),
+    ),
+    (
+        "scalb",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], scalb)),
+    ),
+    (
+        "scalbln",
+        2,
+        Native::Values(|_, a| filters::math2(&a[0], &a[1], scalb)),
+    ),
+    (
+        "fma",
+        3,
+        Native::Values(|_, a| filters::math3(&a[0], &a[1], &a[2], libm::fma)),
+    ),
+];
+
+fn logb(x: f64) -> f64 {
+    if x == 0.0 {
+        f64::NEG_INFINITY
+    } else if !x.is_finite() {
+        x.abs()
+    } else {
+        libm::ilogb(x) as f64
+    }
+}
+
+fn significand(x: f64) -> f64 {
+    if x == 0.0 || !x.is_finite() {
+        x
+    } else {
+        libm::scalbn(x, -libm::ilogb(x))
+    }
+}
+
+fn scalb(x: f64, exp: f64) -> f64 {
+    libm::scalbn(x, exp.clamp(i32::MIN as f64, i32::MAX as f64) as i32)
+}
+
+fn frexp(v: &Value) -> Result<Value, JqError> {
+    let (mantissa, exp) = libm::frexp(filters::number_arg(v)?);
+    Ok(Value::from(vec![
+        filters::number(mantissa),
+        Value::from(exp),
+    ]))
+}
+
+fn modf(v: &Value) -> Result<Value, JqError> {
+    let (fraction, integer) = libm::modf(filters::number_arg(v)?);
+    Ok(Value::from(vec![
+        filters::number(fraction),
+        filters::number(integer),
+    ]))
+}
+
+fn lgamma_r(v: &Value) -> Result<Value, JqError> {
+    let (value, sign) = libm::lgamma_r(filters::number_arg(v)?);
+    Ok(Value::from(vec![filters::number(value), Value::from(sign)]))
+}
+
+fn empty<'a>(_: &'a [Filter], _: &Env<'a>, _: Shared) -> Stream<'a> {
+    Box::new(std::iter::empty())
+}
+
+fn empty_paths<'a>(_: &'a [Filter], _: &Env<'a>, _: Path) -> PathStream<'a> {
+    Box::new(std::iter::empty())
+}
+
+fn path<'a>(args: &'a [Filter], env: &Env<'a>, input: Shared) -> Stream<'a> {
+    Box::new(
+        eval_paths(&args[0], env, (Vec::new(), input))
+            .map(|found| found.map(|(path, _)| Rc::new(Value::from(path)))),
+    )
+}
+
+/// `range($upto)`, `range($from; $upto)` and `range($from; $upto; $by)`
+fn range<'a>(args: &'a [Filter], env: &Env<'a>, input: Shared) -> Stream<'a> {
+    flat_map_ok(cartesian(args, env, &input), |bounds| {
+        let numbers = bounds
+            .iter()
+            .map(|bound| filters::number_arg(bound))
+            .collect::<Result<Vec<_>, _>>();
+        let (from, upto, by) = match numbers.as_deref() {
+            Ok([upto]) => (0.0, *upto, 1.0),
+            Ok([from, upto]) => (*from, *upto, 1.0),
+            Ok([from, upto, by]) => (*from, *upto, *by),
+            Ok(_) => unreachable!("range is only registered with 1 to 3 arguments"),
+            Err(e) => return single(Err(e.clone())),
+        };
+        if by == 0.0 {
+            return Box::new(std::iter::empty());
+        }
+        Box::new(
+            std::iter::successors(Some(from), move |n| Some(n + by))
+                .take_while(move |n| if by > 0.0 { *n < upto } else { *n > upto })
+                .map(|n| Ok(Rc::new(filters::number(n)))),
+        )
+    })
+}
+
+/// How many outputs `limit` may take, given its first argument. As in jq, a negative
+/// count puts no limit on them
+fn limit_count(n: &Value) -> Result<usize, JqError> {
+    let n = filters::number_arg(n)?;
+    Ok(if n < 0.0 {
+        usize::MAX
+    } else {
+        n.ceil() as usize
+    })
+}
+
+fn limit<'a>(args: &'a [Filter], env: &Env<'a>, input: Shared) -> Stream<'a> {
+    let env = env.clone();
+    flat_map_ok(
+        eval(&args[0], &env, input.clone()),
+        move |n| match limit_count(&n) {
+            Ok(n) => Box::new(eval(&args[1], &env, input.clone()).take(n)),
+            Err(e) => single(Err(e)),
+        },
+    )
+}
+
+fn limit_paths<'a>(args: &'a [Filter], env: &Env<'a>, current: Path) -> PathStream<'a> {
+    let env = env.clone();
+    flat_map_ok(
+        eval(&args[0], &env, current.1.clone()),
+        move |n| match limit_count(&n) {
+            Ok(n) => Box::new(eval_paths(&args[1], &env, current.clone()).take(n)),
+            Err(e) => single(Err(e)),
+        },
+    )
+}
+
+/// The final output of `stream`, or nothing if it is empty
+fn last_of<'a, T: 'a>(
+    stream: impl Iterator<Item = Result<T, JqError>> + 'a,
+) -> Box<dyn Iterator<Item = Result<T, JqError>> + 'a> {
+    eval::defer(move || {
+        let mut last = None;
+        for item in stream {
+            match item {
+                Ok(item) => last = Some(item),
+                Err(e) => return single(Err(e)),
+            }
+        }
+        Box::new(last.map(Ok).into_iter())
+    })
+}
+
+fn last<'a>(args: &'a [Filter], env: &Env<'a>, input: Shared) -> Stream<'a> {
+    last_of(eval(&args[0], env, input))
+}
+
+fn last_paths<'a>(args: &'a [Filter], env: &Env<'a>, current: Path) -> PathStream<'a> {
+    last_of(eval_paths(&args[0], env, current))
+}
+
+/// Walk the values reachable from `input`, depth first. For each value `step` says
+/// whether to output it and which values to visit next. The pending streams live on an
+/// explicit stack, so long chains such as `repeat` do not grow the call stack
+fn unfold<'a>(
+    input: Shared,
+    mut step: impl FnMut(&Shared) -> Result<(bool, Option<Stream<'a>>), JqError> + 'a,
+) -> Stream<'a> {
+    let mut stack = vec![single(Ok(input))];
+    Box::new(std::iter::from_fn(move || loop {
+        match stack.last_mut()?.next() {
+            None => {
+                stack.pop();
+            }
+            Some(Ok(value)) => match step(&value) {
+                Ok((emit, next)) => {
+                    stack.extend(next);
+                    if emit {
+                        return Some(Ok(value));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            },
+            Some(Err(e)) => return Some(Err(e)),
+        }
+    }))
+}
+
+/// Whether the first output of `cond` is truthy; an empty condition counts as false
+fn holds<'a>(cond: &'a Filter, env: &Env<'a>, input: &Shared) -> Result<bool, JqError> {
+    match eval(cond, env, input.clone()).next() {
+        Some(value) => Ok(filters::is_truthy(&*value?)),
+        None => Ok(false),
+    }
+}
+
+/// `def repeat(f): def _r: ., (f | _r); _r;`
+fn repeat<'a>(args: &'a [Filter], env: &Env<'a>, input: Shared) -> Stream<'a> {
+    let env = env.clone();
+    unfold(input, move |value| {
+        Ok((true, Some(eval(&args[0], &env, value.clone()))))
+    })
+}
+
+/// `def while(cond; update): def _w: if cond then ., (update | _w) else empty end; _w;`
+fn while_<'a>(args: &'a [Filter], env: &Env<'a>, input: Shared) -> Stream<'a> {
+    let env = env.clone();
+    unfold(input, move |value| {
+        if holds(&args[0], &env, value)? {
+            Ok((true, Some(eval(&args[1], &env, value.clone()))))
+        } else {
+            Ok((false, None))
+        }
+    })
+}
+
+/// `def until(cond; update): def _u: if cond then . else (update | _u) end; _u;`
+fn until<'a>(args: &'a [Filter], env: &Env<'a>, input: Shared) -> Stream<'a> {
+    let env = env.clone();
+    unfold(input, move |value| {
+        if holds(&args[0], &env, value)? {
+            Ok((true, None))
+        } else {
+            Ok((false, Some(eval(&args[1], &env, value.clone()))))
+        }
+    })
+}
+
+fn no_more_inputs() -> JqError {
+    JqError::Input("No more inputs".into())
+}
+
+fn input<'a>(_: &'a [Filter], env: &Env<'a>, _: Shared) -> Stream<'a> {
+    let next = env.inputs().and_then(|inputs| inputs.borrow_mut().next());
+    single(next.unwrap_or_else(|| Err(no_more_inputs())).map(Rc::new))
+}
+
+fn inputs<'a>(_: &'a [Filter], env: &Env<'a>, _: Shared) -> Stream<'a> {
+    match env.inputs() {
+        Some(inputs) => {
+            let inputs = inputs.clone();
+            Box::new(std::iter::from_fn(move || {
+                inputs.borrow_mut().next().map(|next| next.map(Rc::new))
+            }))
+        }
+        None => Box::new(std::iter::empty()),
+    }
+}
+
+/// The path given as the argument of `getpath` and friends
+fn path_arg(path: &Value) -> Result<&Vec<Value>, JqError> {
+    path.as_array()
+        .ok_or_else(|| JqError::Type("Path must be specified as an array".into()))
+}
+
+fn getpath<'a>(args: &'a [Filter], env: &Env<'a>, input: Shared) -> Stream<'a> {
+    Box::new(
+        eval(&args[0], env, input.clone())
+            .map(move |path| filters::getpath(&input, path_arg(&*path?)?).map(Rc::new)),
+    )
+}
+
+fn getpath_paths<'a>(args: &'a [Filter], env: &Env<'a>, current: Path) -> PathStream<'a> {
+    Box::new(eval(&args[0], env, current.1.clone()).map(move |path| {
+        let path = path?;
+        let path = path_arg(&path)?;
+        let value = filters::getpath(&current.1, path)?;
+        let mut full = current.0.clone();
+        full.extend(path.iter().cloned());
+        Ok((full, Rc::new(value)))
+    }))
+}
+
+fn delpaths(input: &Value, args: &[Shared]) -> Result<Value, JqError> {
+    match &*args[0] {
+        Value::Array(paths) => filters::delpaths(input.clone(), paths),
+        _ => Err(JqError::Type("Paths must be specified as an array".into())),
+    }
+}
+
+fn find(name: &str, arity: usize) -> Option<&'static Native> {
+    NATIVES
+        .iter()
+        .find(|(native, native_arity, _)| *native == name && *native_arity == arity)
+        .map(|(_, _, native)| native)
+}
+
+fn undefined(name: &str, arity: usize) -> JqError {
+    JqError::Undefined(format!("{}/{}", name, arity))
+}
+
+/// Every combination of the outputs of `args`, each evaluated against `input`
+fn cartesian<'a>(
+    args: &'a [Filter],
+    env: &Env<'a>,
+    input: &Shared,
+) -> Box<dyn Iterator<Item = Result<Vec<Shared>, JqError>> + 'a> {
+    let (first, rest) = match args.split_first() {
+        Some(split) => split,
+        None => return single(Ok(Vec::new())),
+    };
+    let env = env.clone();
+    let input = input.clone();
+    flat_map_ok(eval(first, &env, input.clone()), move |value| {
+        Box::new(cartesian(rest, &env, &input).map(move |values| {
+            let mut values = values?;
+            values.insert(0, value.clone());
+            Ok(values)
+        }))
+    })
+}
+
+/// Call the builtin `name`
+pub(crate) fn call<'a>(
+    name: &'a str,
+    args: &'a [Filter],
+    env: &Env<'a>,
+    input: Shared,
+) -> Stream<'a> {
+    match find(name, args.len()) {
+        Some(Native::Unary(f)) => single(f(&input).map(Rc::new)),
+        Some(Native::Values(f)) => {
+            let f = *f;
+            Box::new(
+                cartesian(args, env, &input).map(move |values| f(&input, &values?).map(Rc::new)),
+            )
+        }
+        Some(Native::Stream(f, _)) => f(args, env, input),
+        None => single(Err(undefined(name, args.len()))),
+    }
+}
+
+/// Call the builtin `name` as part of a path expression
+pub(crate) fn call_paths<'a>(
+    name: &'a str,
+    args: &'a [Filter],
+    env: &Env<'a>,
+    current: Path,
+) -> PathStream<'a> {
+    match find(name, args.len()) {
+        Some(Native::Stream(_, Some(paths))) => paths(args, env, current),
+        Some(_) => eval::invalid_paths(call(name, args, env, current.1)),
+        None => single(Err(undefined(name, args.len()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run;
+    use serde_json::json;
+
+    #[test]
+    fn test_math_functions() {
+        assert_eq!(run("floor", json!(3.7)).unwrap(), vec![json!(3)]);
+        assert_eq!(run("sqrt", json!(9)).unwrap(), vec![json!(3)]);
+        assert_eq!(run("pow(.; 2)", json!(3)).unwrap(), vec![json!(9)]);
+        assert_eq!(run("log | exp | round", json!(5)).unwrap(), vec![json!(5)]);
+        assert_eq!(
+            run("[.[] | ceil]", json!([1.2, -1.2])).unwrap(),
+            vec![json!([2, -1])]
+        );
+        assert_eq!(run("frexp", json!(8)).unwrap(), vec![json!([0.5, 4])]);
+        assert!(run("floor", json!("1")).is_err());
+    }
+
+    #[test]
+    fn test_infinite_and_nan() {
+        assert_eq!(run("infinite", json!(null)).unwrap(), vec![json!(f64::MAX)]);
+        assert_eq!(
+            run("-infinite", json!(null)).unwrap(),
+            vec![json!(-f64::MAX)]
+        );
+        assert_eq!(run("nan", json!(null)).unwrap(), vec![json!(null)]);
+        assert_eq!(
+            run(
+                "[nan | isnan, (1 | isnan), (infinite | isinfinite)]",
+                json!(null)
+            )
+            .unwrap(),
+            vec![json!([true, false, true])]
+        );
+        assert_eq!(
+            run("infinite + 1 | isinfinite", json!(null)).unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(
+            run("[.[] | sqrt]", json!([-1])).unwrap(),
+            vec![json!([null])]
+        );
+        assert_eq!(
+            run(
+                "[nan | type, tostring], [.[] | isinfinite, isnormal], ([nan] | .[0] | isnan)",
+                json!([1.7976931348623157e308])
+            )
+            .unwrap(),
+            vec![json!(["number", "null"]), json!([false, true]), json!(true)]
+        );
+        assert_eq!(
+            run("\"nan\" | tonumber | isnan", json!(null)).unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(run(".[nan]", json!([1])).unwrap(), vec![json!(null)]);
+        assert!(run("isnan", json!(null)).is_err());
+        assert!(run("isinfinite", json!("1")).is_err());
+        assert!(run("isnormal", json!([])).is_err());
+    }
+
+    #[test]
+    fn test_nan_ordering() {
+        assert_eq!(
+            run(
+                "[nan < 1, nan < -infinite, nan == nan, nan > nan, null < nan]",
+                json!(null)
+            )
+            .unwrap(),
+            vec![json!([true, true, false, false, true])]
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(
+            run("tostring", json!({"a": 1})).unwrap(),
+            vec![json!("{\"a\":1}")]
+        );
+        assert_eq!(run("tonumber", json!("42")).unwrap(), vec![json!(42)]);
+        assert_eq!(
+            run("tojson | fromjson", json!([1, "a"])).unwrap(),
+            vec![json!([1, "a"])]
+        );
+        assert!(run("tonumber", json!("forty-two")).is_err());
+        assert!(run("fromjson", json!("{")).is_err());
+    }
+
+    #[test]
+    fn test_type_selectors() {
+        let input = json!([null, true, 1, "a", [2], {"b": 3}]);
+        assert_eq!(
+            run("[.[] | type]", input.clone()).unwrap(),
+            vec![json!([
+                "null", "boolean", "number", "string", "array", "object"
+            ])]
+        );
+        assert_eq!(
+            run("[.[] | arrays]", input.clone()).unwrap(),
+            vec![json!([[2]])]
+        );
+        assert_eq!(
+            run("[.[] | objects]", input.clone()).unwrap(),
+            vec![json!([{"b": 3}])]
+        );
+        assert_eq!(
+            run("[.[] | iterables]", input.clone()).unwrap(),
+            vec![json!([[2], {"b": 3}])]
+        );
+        assert_eq!(
+            run("[.[] | booleans]", input.clone()).unwrap(),
+            vec![json!([true])]
+        );
+        assert_eq!(
+            run("[.[] | numbers]", input.clone()).unwrap(),
+            vec![json!([1])]
+        );
+        assert_eq!(
+            run("[.[] | strings]", input.clone()).unwrap(),
+            vec![json!(["a"])]
+        );
+        assert_eq!(
+            run("[.[] | nulls]", input.clone()).unwrap(),
+            vec![json!([null])]
+        );
+        assert_eq!(
+            run("[.[] | values] | length", input.clone()).unwrap(),
+            vec![json!(5)]
+        );
+        assert_eq!(
+            run("[.[] | scalars]", input).unwrap(),
+            vec![json!([null, true, 1, "a"])]
+        );
+    }
+
+    #[test]
+    fn test_undefined_function() {
+        assert_eq!(
+            run("nope(1)", json!(null)).unwrap_err(),
+            crate::JqError::Undefined("nope/1".into())
+        );
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(
+            run("[range(3)]", json!(null)).unwrap(),
+            vec![json!([0, 1, 2])]
+        );
+        assert_eq!(
+            run("[range(2; 4)]", json!(null)).unwrap(),
+            vec![json!([2, 3])]
+        );
+        assert_eq!(
+            run("[range(0; 1; 0.25)]", json!(null)).unwrap(),
+            vec![json!([0, 0.25, 0.5, 0.75])]
+        );
+        assert_eq!(
+            run("[range(5; 0; -2)]", json!(null)).unwrap(),
+            vec![json!([5, 3, 1])]
+        );
+        assert_eq!(
+            run("[range(0; 10; 0)]", json!(null)).unwrap(),
+            vec![json!([])]
+        );
+        assert_eq!(
+            run("[range(0, 1; 3, 4)]", json!(null)).unwrap(),
+            vec![json!([0, 1, 2, 0, 1, 2, 3, 1, 2, 1, 2, 3])]
+        );
+    }
+
+    #[test]
+    fn test_generators_are_lazy() {
+        assert_eq!(
+            run("first(range(1e9))", json!(null)).unwrap(),
+            vec![json!(0)]
+        );
+        assert_eq!(
+            run("[limit(3; range(1e9))]", json!(null)).unwrap(),
+            vec![json!([0, 1, 2])]
+        );
+        assert_eq!(
+            run("nth(5; range(1e9))", json!(null)).unwrap(),
+            vec![json!(5)]
+        );
+        assert_eq!(
+            run("[limit(5; repeat(. * 2))]", json!(1)).unwrap(),
+            vec![json!([1, 2, 4, 8, 16])]
+        );
+        assert_eq!(
+            run("isempty(range(1e9))", json!(null)).unwrap(),
+            vec![json!(false)]
+        );
+    }
+
+    #[test]
+    fn test_first_last_nth() {
+        assert_eq!(
+            run("[first(empty), last(empty)]", json!(null)).unwrap(),
+            vec![json!([])]
+        );
+        assert_eq!(
+            run("[last(range(4)), first, last, nth(1)]", json!([5, 6, 7])).unwrap(),
+            vec![json!([3, 5, 7, 6])]
+        );
+        assert_eq!(
+            run("[limit(0; 1, 2)], [limit(-1; 1, 2)]", json!(null)).unwrap(),
+            vec![json!([]), json!([1, 2])]
+        );
+        assert_eq!(
+            run("[limit(-1; .[])], [path(limit(-2; .[]))]", json!([3, 4])).unwrap(),
+            vec![json!([3, 4]), json!([[0], [1]])]
+        );
+        assert!(run("nth(-1; 1, 2)", json!(null)).is_err());
+        assert_eq!(
+            run("path(first(.a, .b))", json!({})).unwrap(),
+            vec![json!(["a"])]
+        );
+        assert_eq!(
+            run("del(last(.[]))", json!([1, 2, 3])).unwrap(),
+            vec![json!([1, 2])]
+        );
+    }
+
+    #[test]
+    fn test_while_until() {
+        assert_eq!(
+            run("[while(. < 100; . * 2)]", json!(1)).unwrap(),
+            vec![json!([1, 2, 4, 8, 16, 32, 64])]
+        );
+        assert_eq!(
+            run("until(. > 100; . * 2)", json!(1)).unwrap(),
+            vec![json!(128)]
+        );
+        assert_eq!(
+            run("[.[] | until(. >= 3; . + 1)]", json!([0, 5])).unwrap(),
+            vec![json!([3, 5])]
+        );
+        // Deep iteration must not exhaust the call stack
+        assert_eq!(
+            run("until(. == 100000; . + 1)", json!(0)).unwrap(),
+            vec![json!(100000)]
+        );
+        assert_eq!(
+            run("last(limit(100000; repeat(. + 1)))", json!(0)).unwrap(),
+            vec![json!(99999)]
+        );
+    }
+
+    #[test]
+    fn test_input() {
+        use crate::eval::{eval, Env, Inputs};
+        use crate::parser;
+        use crate::value::json as value;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let filter = parser::parse("[., input], [inputs]").unwrap();
+        let inputs: Inputs = Rc::new(RefCell::new(
+            vec![Ok(value!(2)), Ok(value!(3)), Ok(value!(4))].into_iter(),
+        ));
+        let env = Env::root().with_inputs(inputs);
+        let outputs: Result<Vec<_>, _> = eval(&filter, &env, Rc::new(value!(1))).collect();
+        assert_eq!(
+            outputs.unwrap(),
+            vec![Rc::new(value!([1, 2])), Rc::new(value!([3, 4]))]
+        );
+
+        assert_eq!(
+            run("input", json!(null)).unwrap_err().to_string(),
+            "No more inputs"
+        );
+        assert_eq!(run("[inputs]", json!(null)).unwrap(), vec![json!([])]);
+    }
+
+    #[test]
+    fn test_array_utilities() {
+        assert_eq!(
+            run("flatten, flatten(1)", json!([1, [2], [[3]]])).unwrap(),
+            vec![json!([1, 2, 3]), json!([1, 2, [3]])]
+        );
+        assert_eq!(
+            run("transpose", json!([[1, 2], [3, 4]])).unwrap(),
+            vec![json!([[1, 3], [2, 4]])]
+        );
+        assert_eq!(
+            run("[combinations]", json!([[1, 2], [3, 4]])).unwrap(),
+            vec![json!([[1, 3], [1, 4], [2, 3], [2, 4]])]
+        );
+        assert_eq!(
+            run("[combinations(2)]", json!([0, 1])).unwrap(),
+            vec![json!([[0, 0], [0, 1], [1, 0], [1, 1]])]
+        );
+        assert_eq!(
+            run("bsearch(3), bsearch(0)", json!([1, 2, 3])).unwrap(),
+            vec![json!(2), json!(-1)]
+        );
+    }
+
+    #[test]
+    fn test_index_and_rindex() {
+        let input = json!("a,b, cd, efg, hijk");
+        assert_eq!(
+            run(r#"indices(", "), index(", "), rindex(", ")"#, input).unwrap(),
+            vec![json!([3, 7, 12]), json!(3), json!(12)]
+        );
+        assert_eq!(
+            run("indices(1), indices([1, 2])", json!([0, 1, 2, 1, 3, 1, 2])).unwrap(),
+            vec![json!([1, 3, 5]), json!([1, 5])]
+        );
+        assert_eq!(
+            run(".[[1, 2]]", json!([0, 1, 2])).unwrap(),
+            vec![json!([1])]
+        );
+        assert_eq!(
+            run(r#"index("z"), rindex("z")"#, json!("abc")).unwrap(),
+            vec![json!(null), json!(null)]
+        );
+        assert_eq!(
+            run("index(1.0), rindex(1), indices(1)", json!([1, 2, 1.0])).unwrap(),
+            vec![json!(0), json!(2), json!([0, 2])]
+        );
+    }
+
+    #[test]
+    fn test_containment() {
+        assert_eq!(
+            run(
+                r#"contains(["baz", "bar"])"#,
+                json!(["foobar", "foobaz", "blarp"])
+            )
+            .unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(
+            run(
+                r#"contains({foo: 12, bar: [{barp: 12}]})"#,
+                json!({"foo": 12, "bar": [1, 2, {"barp": 12, "blip": 13}]})
+            )
+            .unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(
+            run(r#"inside("foobar")"#, json!("bar")).unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(
+            run(r#"inside({"foo": 12, "bar": 1})"#, json!({"foo": 12})).unwrap(),
+            vec![json!(true)]
+        );
+    }
+
+    #[test]
+    fn test_in_and_index() {
+        assert_eq!(
+            run("[.[] | IN(2, 3)]", json!([1, 2, 3])).unwrap(),
+            vec![json!([false, true, true])]
+        );
+        assert_eq!(
+            run("IN(.[]; 5, 1)", json!([1, 2])).unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(
+            run(
+                "INDEX(.id)",
+                json!([{"id": 1, "v": "a"}, {"id": 2, "v": "b"}])
+            )
+            .unwrap(),
+            vec![json!({"1": {"id": 1, "v": "a"}, "2": {"id": 2, "v": "b"}})]
+        );
+        assert_eq!(
+            run("any, all, any(. > 2), all(. > 0)", json!([1, 2])).unwrap(),
+            vec![json!(true), json!(true), json!(false), json!(true)]
+        );
+    }
+
+    #[test]
+    fn test_getpath_and_splits() {
+        assert_eq!(
+            run(
+                r#"getpath(["a", "b"]), getpath(["x", 0])"#,
+                json!({"a": {"b": 1}})
+            )
+            .unwrap(),
+            vec![json!(1), json!(null)]
+        );
+        assert_eq!(
+            run(r#"path(getpath(["a", "b"]))"#, json!(null)).unwrap(),
+            vec![json!(["a", "b"])]
+        );
+        assert_eq!(
+            run(r#"[splits(", *")], split(",")"#, json!("a, b,c")).unwrap(),
+            vec![json!(["a", "b", "c"]), json!(["a", " b", "c"])]
+        );
+        assert_eq!(
+            run("[1, [2]] | tojson", json!(null)).unwrap(),
+            vec![json!("[1,[2]]")]
+        );
+    }
+}
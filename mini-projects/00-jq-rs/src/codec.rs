@@ -0,0 +1,348 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::error::JqError;
+use crate::filters;
+use crate::value::{Map, Value};
+
+/// The values decoded from a document
+pub type Values<'a> = Box<dyn Iterator<Item = Result<Value, JqError>> + 'a>;
+
+/// A structured text format that can be converted to and from JSON values, so that
+/// filters can query it like any other input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    /// A table with a header row, read as an array of objects keyed by the header
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = JqError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "toml" => Ok(Format::Toml),
+            "csv" => Ok(Format::Csv),
+            _ => Err(JqError::Input(format!(
+                "unknown format '{}', expected json, yaml, toml or csv",
+                name
+            ))),
+        }
+    }
+}
+
+impl Format {
+    /// Guess the format of a file from its extension, falling back to JSON
+    pub fn from_path(path: &str) -> Format {
+        std::path::Path::new(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(|ext| ext.parse().ok())
+            .unwrap_or(Format::Json)
+    }
+
+    /// The values in `text`. JSON and YAML files may hold several values, which are
+    /// decoded lazily; TOML and CSV files are always a single value
+    pub fn decode<'a>(self, text: &'a str) -> Values<'a> {
+        match self {
+            Format::Json => Box::new(
+                serde_json::Deserializer::from_str(text)
+                    .into_iter::<Value>()
+                    .map(|value| value.map_err(|e| JqError::Input(e.to_string()))),
+            ),
+            Format::Yaml => Box::new(serde_yaml::Deserializer::from_str(text).map(|document| {
+                serde_yaml::Value::deserialize(document)
+                    .map(from_yaml)
+                    .map_err(|e| JqError::Input(e.to_string()))
+            })),
+            Format::Toml => Box::new(std::iter::once(
+                toml::from_str::<toml::Table>(text)
+                    .map(|table| from_toml(toml::Value::Table(table)))
+                    .map_err(|e| JqError::Input(e.to_string())),
+            )),
+            Format::Csv => Box::new(std::iter::once(decode_csv(text))),
+        }
+    }
+}
+
+/// Writes filter outputs in a format other than JSON, which goes through `format` so that
+/// it keeps its indentation and color options
+pub struct Encoder {
+    format: Format,
+    /// How many values have been written so far
+    written: usize,
+    /// The columns of the CSV table, fixed by the first row
+    header: Option<Vec<String>>,
+}
+
+impl Encoder {
+    pub fn new(format: Format) -> Self {
+        Encoder {
+            format,
+            written: 0,
+            header: None,
+        }
+    }
+
+    /// The text for one output value, without a trailing newline
+    pub fn encode(&mut self, value: &Value) -> Result<String, JqError> {
+        let text = match self.format {
+            Format::Json => Ok(value.to_string()),
+            // Every value after the first starts a new YAML document
+            Format::Yaml => serde_yaml::to_string(value)
+                .map(|text| {
+                    let separator = if self.written > 0 { "---\n" } else { "" };
+                    format!("{}{}", separator, text.trim_end())
+                })
+                .map_err(|e| JqError::Output(e.to_string())),
+            Format::Toml => match to_toml(value)? {
+                toml::Value::Table(table) => toml::to_string(&table)
+                    .map(|text| text.trim_end().to_string())
+                    .map_err(|e| JqError::Output(e.to_string())),
+                _ => Err(JqError::Output(format!(
+                    "{} cannot be written as a TOML document, which must be an object",
+                    filters::describe(value)
+                ))),
+            },
+            Format::Csv => self.encode_csv(value),
+        }?;
+        self.written += 1;
+        Ok(text)
+    }
+
+    /// An object is one row, and an array of objects is several. The first row decides
+    /// the header. Arrays of arrays, or of plain values, are written as bare rows
+    fn encode_csv(&mut self, value: &Value) -> Result<String, JqError> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let rows: Vec<&Value> = match value {
+            Value::Array(items) if items.iter().all(|item| item.is_object() || item.is_array()) => {
+                items.iter().collect()
+            }
+            Value::Array(_) | Value::Object(_) => vec![value],
+            other => {
+                return Err(JqError::Output(format!(
+                    "{} cannot be written as a CSV row",
+                    filters::describe(other)
+                )))
+            }
+        };
+        for row in rows {
+            let fields: Vec<String> = match row {
+                Value::Object(obj) => {
+                    let header = match &self.header {
+                        Some(header) => header,
+                        None => {
+                            let header: Vec<String> = obj.keys().cloned().collect();
+                            write_record(&mut writer, &header)?;
+                            self.header.insert(header)
+                        }
+                    };
+                    header
+                        .iter()
+                        .map(|key| obj.get(key).map_or_else(String::new, csv_field))
+                        .collect()
+                }
+                Value::Array(cells) => cells.iter().map(csv_field).collect(),
+                other => vec![csv_field(other)],
+            };
+            write_record(&mut writer, &fields)?;
+        }
+        let bytes = writer
+            .into_inner()
+            .map_err(|e| JqError::Output(e.to_string()))?;
+        Ok(String::from_utf8_lossy(&bytes).trim_end().to_string())
+    }
+}
+
+fn write_record(writer: &mut csv::Writer<Vec<u8>>, fields: &[String]) -> Result<(), JqError> {
+    writer
+        .write_record(fields)
+        .map_err(|e| JqError::Output(e.to_string()))
+}
+
+/// A CSV cell: strings as they are, `null` as an empty cell and anything else as JSON
+fn csv_field(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Every row after the header becomes an object. Cells stay strings, since CSV does not
+/// say whether `007` is a number; use `tonumber` where one is wanted
+fn decode_csv(text: &str) -> Result<Value, JqError> {
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let header = reader
+        .headers()
+        .map_err(|e| JqError::Input(e.to_string()))?
+        .clone();
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| JqError::Input(e.to_string()))?;
+        let row: Map<String, Value> = header
+            .iter()
+            .zip(record.iter())
+            .map(|(key, cell)| (key.to_string(), Value::from(cell)))
+            .collect();
+        rows.push(Value::from(row));
+    }
+    Ok(Value::from(rows))
+}
+
+/// YAML allows keys of any type, which become their JSON text; tags are dropped
+fn from_yaml(value: serde_yaml::Value) -> Value {
+    match value {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => Value::from(i),
+            (_, Some(u)) => Value::from(u),
+            _ => filters::number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(items) => {
+            Value::from(items.into_iter().map(from_yaml).collect::<Vec<_>>())
+        }
+        serde_yaml::Value::Mapping(mapping) => Value::from(
+            mapping
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = match from_yaml(key) {
+                        Value::String(key) => key,
+                        other => other.to_string(),
+                    };
+                    (key, from_yaml(value))
+                })
+                .collect::<Map<_, _>>(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => from_yaml(tagged.value),
+    }
+}
+
+/// TOML dates and times have no JSON equivalent, so they become strings
+fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => filters::number(f),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(items) => {
+            Value::from(items.into_iter().map(from_toml).collect::<Vec<_>>())
+        }
+        toml::Value::Table(table) => Value::from(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, from_toml(value)))
+                .collect::<Map<_, _>>(),
+        ),
+    }
+}
+
+fn to_toml(value: &Value) -> Result<toml::Value, JqError> {
+    Ok(match value {
+        Value::Null => return Err(JqError::Output("TOML has no null value".into())),
+        Value::Bool(b) => toml::Value::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None => toml::Value::Float(n.as_f64()),
+        },
+        Value::String(s) => toml::Value::String(s.clone()),
+        Value::Array(items) => {
+            toml::Value::Array(items.iter().map(to_toml).collect::<Result<_, _>>()?)
+        }
+        Value::Object(obj) => toml::Value::Table(
+            obj.iter()
+                .map(|(key, value)| Ok((key.clone(), to_toml(value)?)))
+                .collect::<Result<_, JqError>>()?,
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::json;
+
+    fn decode_all(format: Format, text: &str) -> Vec<Value> {
+        format.decode(text).collect::<Result<_, _>>().unwrap()
+    }
+
+    #[test]
+    fn test_format_names() {
+        assert_eq!("YAML".parse::<Format>().unwrap(), Format::Yaml);
+        assert!("xml".parse::<Format>().is_err());
+        assert_eq!(Format::from_path("config/app.yml"), Format::Yaml);
+        assert_eq!(Format::from_path("report.csv"), Format::Csv);
+        assert_eq!(Format::from_path("data"), Format::Json);
+    }
+
+    #[test]
+    fn test_yaml_round_trip() {
+        let text = "name: web\nports: [80, 443]\n1: one\n---\nname: db\n";
+        let values = decode_all(Format::Yaml, text);
+        assert_eq!(
+            values,
+            vec![
+                json!({"name": "web", "ports": [80, 443], "1": "one"}),
+                json!({"name": "db"})
+            ]
+        );
+        let mut encoder = Encoder::new(Format::Yaml);
+        assert_eq!(encoder.encode(&json!({"a": [1]})).unwrap(), "a:\n- 1");
+        assert_eq!(encoder.encode(&json!("b")).unwrap(), "---\nb");
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let text = "title = \"x\"\nwhen = 1979-05-27\n\n[server]\nport = 8080\n";
+        let values = decode_all(Format::Toml, text);
+        assert_eq!(
+            values,
+            vec![json!({"title": "x", "when": "1979-05-27", "server": {"port": 8080}})]
+        );
+        let mut encoder = Encoder::new(Format::Toml);
+        assert_eq!(
+            encoder
+                .encode(&json!({"server": {"port": 8080}, "name": "x"}))
+                .unwrap(),
+            "name = \"x\"\n\n[server]\nport = 8080"
+        );
+        assert!(encoder.encode(&json!([1])).is_err());
+        assert!(encoder.encode(&json!({"a": null})).is_err());
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let text = "name,qty\nwidget,3\n\"a, b\",\n";
+        let values = decode_all(Format::Csv, text);
+        assert_eq!(
+            values,
+            vec![json!([{"name": "widget", "qty": "3"}, {"name": "a, b", "qty": ""}])]
+        );
+        let mut encoder = Encoder::new(Format::Csv);
+        assert_eq!(
+            encoder.encode(&values[0]).unwrap(),
+            "name,qty\nwidget,3\n\"a, b\","
+        );
+        // Later rows reuse the header from the first
+        assert_eq!(
+            encoder.encode(&json!({"qty": 1, "name": null})).unwrap(),
+            ",1"
+        );
+        assert_eq!(
+            Encoder::new(Format::Csv)
+                .encode(&json!([[1, "x"], [true, [2]]]))
+                .unwrap(),
+            "1,x\ntrue,[2]"
+        );
+        assert!(Encoder::new(Format::Csv).encode(&json!(1)).is_err());
+    }
+}
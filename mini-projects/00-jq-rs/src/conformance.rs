@@ -0,0 +1,391 @@
+//! Runs test cases written in the format of jq's own `tests/jq.test`, so that
+//! compatibility with jq can be measured rather than guessed.
+//!
+//! Cases are separated by blank lines. A case is a program, an input, and then one
+//! line per expected output:
+//!
+//! ```text
+//! .[0]
+//! [1,2]
+//! 1
+//! ```
+//!
+//! A case headed by `%%FAIL` (or `%%FAIL IGNORE MSG`) holds a program that jq refuses
+//! to compile, followed by jq's error message. Lines starting with `#` are comments;
+//! the last one seen names the section, which is how results are grouped in the report.
+
+use std::fmt;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::error::JqError;
+use crate::eval::{eval, Env};
+use crate::parser;
+use crate::value::Value;
+
+/// How long a case may run before it is counted as a failure
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    /// The line of the test file the program is on
+    pub line: usize,
+    pub section: String,
+    pub program: String,
+    pub expect: Expect,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expect {
+    /// Running the program on `input` yields exactly `outputs`, each one JSON text
+    Outputs { input: String, outputs: Vec<String> },
+    /// The program does not compile. jq's message is kept but not compared, as
+    /// jq-rs words its errors differently
+    Fail { message: Option<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Pass,
+    /// The program ran but did something other than jq
+    Fail(String),
+    /// The program uses syntax or a builtin that jq-rs does not have
+    Unsupported(String),
+}
+
+/// Split a test file into cases
+pub fn parse(text: &str) -> Vec<Case> {
+    let mut cases = Vec::new();
+    let mut section = String::new();
+    let mut block: Vec<(usize, &str)> = Vec::new();
+    let lines = text.lines().map(Some).chain(std::iter::once(None));
+    for (number, line) in lines.enumerate() {
+        match line {
+            Some(line) if line.trim_start().starts_with('#') => {
+                let comment = line.trim_start().trim_start_matches('#').trim();
+                if !comment.is_empty() {
+                    section = comment.to_string();
+                }
+            }
+            Some(line) if !line.trim().is_empty() => block.push((number + 1, line)),
+            _ => {
+                if let Some(case) = case(&block, &section) {
+                    cases.push(case);
+                }
+                block.clear();
+            }
+        }
+    }
+    cases
+}
+
+fn case(block: &[(usize, &str)], section: &str) -> Option<Case> {
+    let (&(mut line, first), rest) = block.split_first()?;
+    let (program, expect) = if first.starts_with("%%FAIL") {
+        let (&(program_line, program), message) = rest.split_first()?;
+        line = program_line;
+        let message = match first.contains("IGNORE MSG") {
+            true => None,
+            false => Some(lines(message).join("\n")),
+        };
+        (program, Expect::Fail { message })
+    } else {
+        let (&(_, input), outputs) = rest.split_first()?;
+        let expect = Expect::Outputs {
+            input: input.to_string(),
+            outputs: lines(outputs).into_iter().map(String::from).collect(),
+        };
+        (first, expect)
+    };
+    Some(Case {
+        line,
+        section: section.to_string(),
+        program: program.to_string(),
+        expect,
+    })
+}
+
+fn lines<'t>(block: &[(usize, &'t str)]) -> Vec<&'t str> {
+    block.iter().map(|(_, line)| *line).collect()
+}
+
+/// Run one case on its own thread, so that a panic or a program that never finishes
+/// is reported instead of stopping the whole run
+pub fn run(case: &Case) -> Outcome {
+    let (sender, receiver) = mpsc::channel();
+    let (program, expect) = (case.program.clone(), case.expect.clone());
+    thread::spawn(move || {
+        let _ = sender.send(check(&program, &expect));
+    });
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(outcome) => outcome,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            Outcome::Fail(format!("did not finish within {}s", TIMEOUT.as_secs()))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => Outcome::Fail("panicked".into()),
+    }
+}
+
+fn check(program: &str, expect: &Expect) -> Outcome {
+    let filter = parser::parse(program);
+    match expect {
+        // jq reports unknown functions when compiling, jq-rs when they are called
+        Expect::Fail { .. } => match filter {
+            Err(_) => Outcome::Pass,
+            Ok(filter) => {
+                match eval(&filter, &Env::root(), Rc::new(Value::Null)).find_map(Result::err) {
+                    Some(JqError::Undefined(_)) => Outcome::Pass,
+                    _ => Outcome::Fail("the program was accepted".into()),
+                }
+            }
+        },
+        Expect::Outputs { input, outputs } => {
+            let filter = match filter {
+                Ok(filter) => filter,
+                Err(e) => return Outcome::Unsupported(e.to_string()),
+            };
+            let input: Value = match serde_json::from_str(input) {
+                Ok(input) => input,
+                Err(e) => return Outcome::Unsupported(format!("input {}: {}", input, e)),
+            };
+            let mut expected = Vec::new();
+            for output in outputs {
+                match serde_json::from_str::<Value>(output) {
+                    Ok(output) => expected.push(output),
+                    Err(e) => return Outcome::Unsupported(format!("output {}: {}", output, e)),
+                }
+            }
+            // One more than expected is enough to notice extra outputs
+            let mut actual = eval(&filter, &Env::root(), Rc::new(input)).take(expected.len() + 1);
+            for (i, want) in expected.iter().enumerate() {
+                match actual.next() {
+                    Some(Ok(got)) if *got == *want => {}
+                    Some(Ok(got)) => {
+                        return Outcome::Fail(format!(
+                            "output {}: expected {}, got {}",
+                            i + 1,
+                            want,
+                            got
+                        ))
+                    }
+                    Some(Err(e @ JqError::Undefined(_))) => {
+                        return Outcome::Unsupported(e.to_string())
+                    }
+                    Some(Err(e)) => return Outcome::Fail(format!("error: {}", e)),
+                    None => {
+                        return Outcome::Fail(format!(
+                            "expected {} outputs, got {}",
+                            expected.len(),
+                            i
+                        ))
+                    }
+                }
+            }
+            match actual.next() {
+                None => Outcome::Pass,
+                Some(Ok(extra)) => Outcome::Fail(format!("unexpected output {}", extra)),
+                Some(Err(e @ JqError::Undefined(_))) => Outcome::Unsupported(e.to_string()),
+                Some(Err(e)) => Outcome::Fail(format!("error after the outputs: {}", e)),
+            }
+        }
+    }
+}
+
+/// Every case and how it went
+pub struct Report {
+    pub results: Vec<(Case, Outcome)>,
+}
+
+impl Report {
+    pub fn run(cases: Vec<Case>) -> Report {
+        let results = cases
+            .into_iter()
+            .map(|case| {
+                let outcome = run(&case);
+                (case, outcome)
+            })
+            .collect();
+        Report { results }
+    }
+
+    pub fn passed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Pass))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Fail(_)))
+    }
+
+    pub fn unsupported(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Unsupported(_)))
+    }
+
+    /// The percentage of all cases that pass
+    pub fn compatibility(&self) -> f64 {
+        match self.results.len() {
+            0 => 100.0,
+            total => self.passed() as f64 * 100.0 / total as f64,
+        }
+    }
+
+    fn count(&self, f: impl Fn(&Outcome) -> bool) -> usize {
+        self.results
+            .iter()
+            .filter(|(_, outcome)| f(outcome))
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    /// Every case that did not pass, a pass/fail/unsupported line per section, and
+    /// the totals
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (case, outcome) in &self.results {
+            let (label, reason) = match outcome {
+                Outcome::Pass => continue,
+                Outcome::Fail(reason) => ("FAIL", reason),
+                Outcome::Unsupported(reason) => ("UNSUPPORTED", reason),
+            };
+            writeln!(f, "{} line {}: {}", label, case.line, case.program)?;
+            writeln!(f, "    {}", reason)?;
+        }
+
+        let mut sections: Vec<(&str, [usize; 3])> = Vec::new();
+        for (case, outcome) in &self.results {
+            if sections
+                .last()
+                .is_none_or(|(name, _)| *name != case.section)
+            {
+                sections.push((&case.section, [0; 3]));
+            }
+            let tally = &mut sections.last_mut().unwrap().1;
+            match outcome {
+                Outcome::Pass => tally[0] += 1,
+                Outcome::Fail(_) => tally[1] += 1,
+                Outcome::Unsupported(_) => tally[2] += 1,
+            }
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>6} {:>6} {:>12}  section",
+            "pass", "fail", "unsupported"
+        )?;
+        for (name, [pass, fail, unsupported]) in sections {
+            writeln!(f, "{:>6} {:>6} {:>12}  {}", pass, fail, unsupported, name)?;
+        }
+        writeln!(
+            f,
+            "\n{} passed, {} failed, {} unsupported: {:.1}% compatible",
+            self.passed(),
+            self.failed(),
+            self.unsupported(),
+            self.compatibility()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "# Tests are groups of lines: program, input, outputs
+#
+# Simple values
+#
+
+true
+null
+true
+
+.[]
+[1,2]
+1
+2
+
+# Errors
+
+%%FAIL
+{(0):1}
+jq: error: Object keys must be strings at <top-level>, line 1:
+
+%%FAIL IGNORE MSG
+.[
+";
+
+    #[test]
+    fn test_parse_cases() {
+        let cases = parse(SAMPLE);
+        assert_eq!(cases.len(), 4);
+        assert_eq!(
+            cases[1],
+            Case {
+                line: 10,
+                section: "Simple values".into(),
+                program: ".[]".into(),
+                expect: Expect::Outputs {
+                    input: "[1,2]".into(),
+                    outputs: vec!["1".into(), "2".into()],
+                },
+            }
+        );
+        assert_eq!(cases[2].section, "Errors");
+        assert_eq!(cases[2].line, 18);
+        assert_eq!(
+            cases[2].expect,
+            Expect::Fail {
+                message: Some(
+                    "jq: error: Object keys must be strings at <top-level>, line 1:".into()
+                )
+            }
+        );
+        assert_eq!(cases[3].program, ".[");
+        assert_eq!(cases[3].expect, Expect::Fail { message: None });
+    }
+
+    fn outcome(program: &str, input: &str, outputs: &[&str]) -> Outcome {
+        let expect = Expect::Outputs {
+            input: input.into(),
+            outputs: outputs.iter().map(|output| output.to_string()).collect(),
+        };
+        check(program, &expect)
+    }
+
+    #[test]
+    fn test_outcomes() {
+        assert_eq!(outcome(".[]", "[1,2.0]", &["1.0", "2"]), Outcome::Pass);
+        assert_eq!(
+            outcome(".[]", "[1,2]", &["1"]),
+            Outcome::Fail("unexpected output 2".into())
+        );
+        assert_eq!(
+            outcome(".[]", "[1]", &["1", "2"]),
+            Outcome::Fail("expected 2 outputs, got 1".into())
+        );
+        assert_eq!(
+            outcome("nope", "null", &["1"]),
+            Outcome::Unsupported("nope/0 is not defined".into())
+        );
+        assert!(matches!(
+            outcome(".[", "null", &[]),
+            Outcome::Unsupported(_)
+        ));
+        assert_eq!(check(".[", &Expect::Fail { message: None }), Outcome::Pass);
+        assert_eq!(
+            check(".", &Expect::Fail { message: None }),
+            Outcome::Fail("the program was accepted".into())
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        let report = Report::run(parse(SAMPLE));
+        assert_eq!((report.passed(), report.failed()), (3, 1));
+        assert_eq!(report.compatibility(), 75.0);
+        let text = report.to_string();
+        assert!(text.contains("FAIL line 18: {(0):1}"));
+        assert!(text.ends_with("3 passed, 1 failed, 0 unsupported: 75.0% compatible\n"));
+    }
+}
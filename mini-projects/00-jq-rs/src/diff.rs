@@ -0,0 +1,718 @@
+//! Structural differences between two JSON documents, written as RFC 6902 JSON
+//! Patches or RFC 7386 merge patches, and applying either kind of patch.
+//!
+//! Paths use the same model as `path(f)`: a list of object keys and array indices.
+//! Paths read from a patch document hold only strings, which are taken as indices
+//! when they meet an array.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::JqError;
+use crate::value::{json, Map, Value};
+
+/// One step of a JSON Patch
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Add {
+        path: Vec<Value>,
+        value: Value,
+    },
+    Remove {
+        path: Vec<Value>,
+    },
+    Replace {
+        path: Vec<Value>,
+        value: Value,
+    },
+    Move {
+        from: Vec<Value>,
+        path: Vec<Value>,
+    },
+    Copy {
+        from: Vec<Value>,
+        path: Vec<Value>,
+    },
+    /// Fails the whole patch unless the value at `path` equals `value`
+    Test {
+        path: Vec<Value>,
+        value: Value,
+    },
+}
+
+/// Array lengths beyond which elements are compared position by position, as
+/// aligning them takes time proportional to the product of their lengths
+const ALIGN_LIMIT: usize = 4096;
+
+/// The operations that turn `a` into `b`. Objects and arrays are compared member by
+/// member, so a change deep inside a document is a single `replace` of that value;
+/// array elements are aligned so that an insertion is one `add`, not a cascade of
+/// replacements
+pub fn diff(a: &Value, b: &Value) -> Vec<Operation> {
+    let mut ops = Vec::new();
+    diff_into(a, b, &mut Vec::new(), &mut ops);
+    ops
+}
+
+fn diff_into(a: &Value, b: &Value, path: &mut Vec<Value>, ops: &mut Vec<Operation>) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            for (key, old) in a.iter() {
+                path.push(Value::String(key.clone()));
+                match b.get(key) {
+                    Some(new) => diff_into(old, new, path, ops),
+                    None => ops.push(Operation::Remove { path: path.clone() }),
+                }
+                path.pop();
+            }
+            for (key, new) in b.iter().filter(|(key, _)| !a.contains_key(*key)) {
+                let mut path = path.clone();
+                path.push(Value::String(key.clone()));
+                ops.push(Operation::Add {
+                    path,
+                    value: new.clone(),
+                });
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => diff_arrays(a, b, path, ops),
+        _ if a == b => {}
+        _ => ops.push(Operation::Replace {
+            path: path.clone(),
+            value: b.clone(),
+        }),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Edit {
+    Keep,
+    Delete(usize),
+    Insert(usize),
+}
+
+fn diff_arrays(a: &[Value], b: &[Value], path: &mut Vec<Value>, ops: &mut Vec<Operation>) {
+    let edits = align(a, b);
+    // Where the array being patched is up to, as earlier operations shift it
+    let mut at = 0;
+    let mut i = 0;
+    while i < edits.len() {
+        if let Edit::Keep = edits[i] {
+            at += 1;
+            i += 1;
+            continue;
+        }
+        // Deleted elements facing inserted ones are changed in place instead
+        let run = edits[i..]
+            .iter()
+            .take_while(|edit| !matches!(edit, Edit::Keep))
+            .count();
+        let deleted: Vec<usize> = edits[i..i + run]
+            .iter()
+            .filter_map(|edit| match edit {
+                Edit::Delete(index) => Some(*index),
+                _ => None,
+            })
+            .collect();
+        let inserted: Vec<usize> = edits[i..i + run]
+            .iter()
+            .filter_map(|edit| match edit {
+                Edit::Insert(index) => Some(*index),
+                _ => None,
+            })
+            .collect();
+        for (&old, &new) in deleted.iter().zip(&inserted) {
+            path.push(json!(at));
+            diff_into(&a[old], &b[new], path, ops);
+            path.pop();
+            at += 1;
+        }
+        for _ in inserted.len()..deleted.len() {
+            let mut path = path.clone();
+            path.push(json!(at));
+            ops.push(Operation::Remove { path });
+        }
+        for &new in inserted.iter().skip(deleted.len()) {
+            let mut path = path.clone();
+            path.push(json!(at));
+            ops.push(Operation::Add {
+                path,
+                value: b[new].clone(),
+            });
+            at += 1;
+        }
+        i += run;
+    }
+}
+
+/// An edit script from `a` to `b` that keeps their longest common subsequence
+fn align(a: &[Value], b: &[Value]) -> Vec<Edit> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a_rest, b_rest) = (&a[prefix..], &b[prefix..]);
+    let suffix = a_rest
+        .iter()
+        .rev()
+        .zip(b_rest.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (n, m) = (a_rest.len() - suffix, b_rest.len() - suffix);
+
+    let mut middle = Vec::new();
+    if n.max(m) > ALIGN_LIMIT {
+        for k in 0..n.max(m) {
+            if k < n {
+                middle.push(Edit::Delete(prefix + k));
+            }
+            if k < m {
+                middle.push(Edit::Insert(prefix + k));
+            }
+        }
+    } else {
+        // lengths[i][j] is the common subsequence length of a_rest[i..n] and b_rest[j..m]
+        let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i][j] = if a_rest[i] == b_rest[j] {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && a_rest[i] == b_rest[j] {
+                middle.push(Edit::Keep);
+                i += 1;
+                j += 1;
+            } else if j < m && (i == n || lengths[i][j + 1] >= lengths[i + 1][j]) {
+                middle.push(Edit::Insert(prefix + j));
+                j += 1;
+            } else {
+                middle.push(Edit::Delete(prefix + i));
+                i += 1;
+            }
+        }
+    }
+
+    let mut edits = vec![Edit::Keep; prefix];
+    edits.extend(middle);
+    edits.extend(vec![Edit::Keep; suffix]);
+    edits
+}
+
+/// The RFC 7386 merge patch that turns `a` into `b`. Merge patches cannot set a
+/// member to `null`, as that is how they remove one
+pub fn merge_diff(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut patch = Map::new();
+            for key in a.keys().filter(|key| !b.contains_key(*key)) {
+                patch.insert(key.clone(), Value::Null);
+            }
+            for (key, new) in b.iter() {
+                match a.get(key) {
+                    Some(old) if old == new => {}
+                    Some(old) => {
+                        patch.insert(key.clone(), merge_diff(old, new));
+                    }
+                    None => {
+                        patch.insert(key.clone(), new.clone());
+                    }
+                }
+            }
+            Value::from(patch)
+        }
+        _ => b.clone(),
+    }
+}
+
+/// Apply an RFC 7386 merge patch: objects are merged member by member, `null`
+/// removes a member and anything else replaces the target outright
+pub fn merge_patch(target: Value, patch: &Value) -> Value {
+    let Value::Object(patch) = patch else {
+        return patch.clone();
+    };
+    let mut target = match target {
+        Value::Object(target) => Arc::unwrap_or_clone(target),
+        _ => Map::new(),
+    };
+    for (key, value) in patch.iter() {
+        if value.is_null() {
+            target.shift_remove(key);
+        } else if let Some(old) = target.get_mut(key) {
+            *old = merge_patch(old.take(), value);
+        } else {
+            target.insert(key.clone(), merge_patch(Value::Null, value));
+        }
+    }
+    Value::from(target)
+}
+
+impl Operation {
+    /// Read one operation of a JSON Patch document
+    pub fn from_json(op: &Value) -> Result<Operation, JqError> {
+        let member = |name: &str| {
+            op.get(name).ok_or_else(|| {
+                JqError::Patch(format!("patch operation {} has no \"{}\"", op, name))
+            })
+        };
+        let pointer = |name: &str| {
+            member(name)?
+                .as_str()
+                .ok_or_else(|| JqError::Patch(format!("\"{}\" in {} is not a string", name, op)))
+                .and_then(parse_pointer)
+        };
+        let path = pointer("path")?;
+        match member("op")?.as_str() {
+            Some("add") => Ok(Operation::Add {
+                path,
+                value: member("value")?.clone(),
+            }),
+            Some("remove") => Ok(Operation::Remove { path }),
+            Some("replace") => Ok(Operation::Replace {
+                path,
+                value: member("value")?.clone(),
+            }),
+            Some("move") => Ok(Operation::Move {
+                from: pointer("from")?,
+                path,
+            }),
+            Some("copy") => Ok(Operation::Copy {
+                from: pointer("from")?,
+                path,
+            }),
+            Some("test") => Ok(Operation::Test {
+                path,
+                value: member("value")?.clone(),
+            }),
+            _ => Err(JqError::Patch(format!("unknown patch operation {}", op))),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            Operation::Add { path, value } => {
+                json!({"op": "add", "path": pointer(path), "value": value})
+            }
+            Operation::Remove { path } => json!({"op": "remove", "path": pointer(path)}),
+            Operation::Replace { path, value } => {
+                json!({"op": "replace", "path": pointer(path), "value": value})
+            }
+            Operation::Move { from, path } => {
+                json!({"op": "move", "from": pointer(from), "path": pointer(path)})
+            }
+            Operation::Copy { from, path } => {
+                json!({"op": "copy", "from": pointer(from), "path": pointer(path)})
+            }
+            Operation::Test { path, value } => {
+                json!({"op": "test", "path": pointer(path), "value": value})
+            }
+        }
+    }
+}
+
+/// A JSON Patch document for `ops`
+pub fn to_patch(ops: &[Operation]) -> Value {
+    Value::from(ops.iter().map(Operation::to_json).collect::<Vec<_>>())
+}
+
+/// Read a JSON Patch document, which is an array of operations
+pub fn from_patch(patch: &Value) -> Result<Vec<Operation>, JqError> {
+    match patch {
+        Value::Array(ops) => ops.iter().map(Operation::from_json).collect(),
+        _ => Err(JqError::Patch(
+            "a JSON Patch must be an array of operations".into(),
+        )),
+    }
+}
+
+/// The JSON Pointer (RFC 6901) for `path`, such as `/items/0/a~1b`
+pub fn pointer(path: &[Value]) -> String {
+    path.iter()
+        .map(|step| match step {
+            Value::String(key) => format!("/{}", key.replace('~', "~0").replace('/', "~1")),
+            other => format!("/{}", other),
+        })
+        .collect()
+}
+
+/// Split a JSON Pointer into its unescaped reference tokens
+pub fn parse_pointer(pointer: &str) -> Result<Vec<Value>, JqError> {
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let Some(rest) = pointer.strip_prefix('/') else {
+        return Err(JqError::Patch(format!(
+            "JSON pointer \"{}\" must start with /",
+            pointer
+        )));
+    };
+    Ok(rest
+        .split('/')
+        .map(|token| Value::String(token.replace("~1", "/").replace("~0", "~")))
+        .collect())
+}
+
+/// Apply a JSON Patch. The operations apply in order, and if any of them fails the
+/// whole patch fails
+pub fn apply(mut doc: Value, ops: &[Operation]) -> Result<Value, JqError> {
+    for op in ops {
+        doc = match op {
+            Operation::Add { path, value } => add(doc, path, value.clone())?,
+            Operation::Remove { path } => remove(doc, path)?.0,
+            Operation::Replace { path, value } => {
+                *get_mut(&mut doc, path)? = value.clone();
+                doc
+            }
+            Operation::Move { from, path } => {
+                if path.len() > from.len() && path[..from.len()] == from[..] {
+                    return Err(JqError::Patch(format!(
+                        "cannot move {} into itself",
+                        pointer(from)
+                    )));
+                }
+                let (doc, value) = remove(doc, from)?;
+                add(doc, path, value)?
+            }
+            Operation::Copy { from, path } => {
+                let value = get(&doc, from)?.clone();
+                add(doc, path, value)?
+            }
+            Operation::Test { path, value } => {
+                let found = get(&doc, path)?;
+                if found != value {
+                    return Err(JqError::Patch(format!(
+                        "test failed: {} is {}, not {}",
+                        pointer(path),
+                        found,
+                        value
+                    )));
+                }
+                doc
+            }
+        };
+    }
+    Ok(doc)
+}
+
+/// Where `step` points in `array`; `len` allows the position just past the end
+fn array_index(step: &Value, array: &[Value], path: &[Value], len: bool) -> Result<usize, JqError> {
+    let index = match step {
+        Value::String(s) if s == "-" && len => Some(array.len()),
+        // Leading zeros and signs are not allowed
+        Value::String(s)
+            if s == "0" || (!s.starts_with('0') && s.bytes().all(|b| b.is_ascii_digit())) =>
+        {
+            s.parse().ok()
+        }
+        Value::Number(n) => n.as_u64().map(|n| n as usize),
+        _ => None,
+    };
+    match index {
+        Some(index) if index < array.len() || (len && index == array.len()) => Ok(index),
+        _ => Err(missing(path)),
+    }
+}
+
+fn missing(path: &[Value]) -> JqError {
+    JqError::Patch(format!("{} does not exist", pointer(path)))
+}
+
+fn key(step: &Value) -> String {
+    match step {
+        Value::String(key) => key.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn get<'v>(doc: &'v Value, path: &[Value]) -> Result<&'v Value, JqError> {
+    let mut current = doc;
+    for (depth, step) in path.iter().enumerate() {
+        current = match current {
+            Value::Object(map) => map.get(&key(step)),
+            Value::Array(array) => array_index(step, array, &path[..=depth], false)
+                .ok()
+                .map(|index| &array[index]),
+            _ => None,
+        }
+        .ok_or_else(|| missing(&path[..=depth]))?;
+    }
+    Ok(current)
+}
+
+fn get_mut<'v>(doc: &'v mut Value, path: &[Value]) -> Result<&'v mut Value, JqError> {
+    let mut current = doc;
+    for (depth, step) in path.iter().enumerate() {
+        current = match current {
+            Value::Object(map) => Arc::make_mut(map).get_mut(&key(step)),
+            Value::Array(array) => {
+                let index = array_index(step, array, &path[..=depth], false)?;
+                Arc::make_mut(array).get_mut(index)
+            }
+            _ => None,
+        }
+        .ok_or_else(|| missing(&path[..=depth]))?;
+    }
+    Ok(current)
+}
+
+fn add(mut doc: Value, path: &[Value], value: Value) -> Result<Value, JqError> {
+    let Some((last, parent)) = path.split_last() else {
+        return Ok(value);
+    };
+    match get_mut(&mut doc, parent)? {
+        Value::Object(map) => {
+            Arc::make_mut(map).insert(key(last), value);
+        }
+        Value::Array(array) => {
+            let index = array_index(last, array, path, true)?;
+            Arc::make_mut(array).insert(index, value);
+        }
+        _ => return Err(missing(path)),
+    }
+    Ok(doc)
+}
+
+/// Take the value at `path` out of `doc`
+fn remove(mut doc: Value, path: &[Value]) -> Result<(Value, Value), JqError> {
+    let Some((last, parent)) = path.split_last() else {
+        return Ok((Value::Null, doc));
+    };
+    let removed = match get_mut(&mut doc, parent)? {
+        Value::Object(map) => Arc::make_mut(map).shift_remove(&key(last)),
+        Value::Array(array) => {
+            let index = array_index(last, array, path, false)?;
+            Some(Arc::make_mut(array).remove(index))
+        }
+        _ => None,
+    };
+    let removed = removed.ok_or_else(|| missing(path))?;
+    Ok((doc, removed))
+}
+
+/// `.a[0]["b c"]`, the jq syntax for `path`
+pub fn jq_path(path: &[Value]) -> String {
+    if path.is_empty() {
+        return ".".into();
+    }
+    let text: String = path
+        .iter()
+        .map(|step| match step {
+            Value::String(key) if is_identifier(key) => format!(".{}", key),
+            other => format!("[{}]", other),
+        })
+        .collect();
+    match text.starts_with('.') {
+        true => text,
+        false => format!(".{}", text),
+    }
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The operations of a diff as lines a person can read: `+` for added values, `-`
+/// for removed ones and `~` for changed ones, in green, red and yellow with `color`
+pub struct Readable<'a> {
+    pub before: &'a Value,
+    pub ops: &'a [Operation],
+    pub color: bool,
+}
+
+impl fmt::Display for Readable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Earlier operations shift array indices, so old values are looked up in a
+        // copy of the document that is patched along the way
+        let mut doc = self.before.clone();
+        for op in self.ops {
+            let (sign, color, text) = match op {
+                Operation::Add { path, value } => {
+                    ('+', "0;32", format!("{}: {}", jq_path(path), value))
+                }
+                Operation::Remove { path } => {
+                    let old = get(&doc, path).map(Value::to_string).unwrap_or_default();
+                    ('-', "0;31", format!("{}: {}", jq_path(path), old))
+                }
+                Operation::Replace { path, value } => {
+                    let old = get(&doc, path).map(Value::to_string).unwrap_or_default();
+                    (
+                        '~',
+                        "0;33",
+                        format!("{}: {} -> {}", jq_path(path), old, value),
+                    )
+                }
+                other => ('~', "0;33", other.to_json().to_string()),
+            };
+            if self.color {
+                writeln!(f, "\x1b[{}m{} {}\x1b[0m", color, sign, text)?;
+            } else {
+                writeln!(f, "{} {}", sign, text)?;
+            }
+            doc = apply(doc, std::slice::from_ref(op)).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(a: Value, b: Value) -> Vec<Value> {
+        let ops = diff(&a, &b);
+        assert_eq!(apply(a.clone(), &ops), Ok(b.clone()));
+        let patch = to_patch(&ops);
+        assert_eq!(
+            apply(a.clone(), &from_patch(&patch).unwrap()),
+            Ok(b.clone())
+        );
+        assert_eq!(merge_patch(a.clone(), &merge_diff(&a, &b)), b);
+        patch.as_array().unwrap().clone()
+    }
+
+    #[test]
+    fn test_diff_objects() {
+        let patch = roundtrip(
+            json!({"name": "api", "version": 1, "old": true, "nested": {"a": 1, "b": 2}}),
+            json!({"name": "api", "version": 2, "nested": {"a": 1, "b": 3}, "new": [1]}),
+        );
+        assert_eq!(
+            patch,
+            vec![
+                json!({"op": "replace", "path": "/version", "value": 2}),
+                json!({"op": "remove", "path": "/old"}),
+                json!({"op": "replace", "path": "/nested/b", "value": 3}),
+                json!({"op": "add", "path": "/new", "value": [1]}),
+            ]
+        );
+        assert!(diff(&json!({"a": 1}), &json!({"a": 1.0})).is_empty());
+    }
+
+    #[test]
+    fn test_diff_arrays() {
+        assert_eq!(
+            roundtrip(json!([1, 2, 3, 4]), json!([1, 9, 2, 3])),
+            vec![
+                json!({"op": "add", "path": "/1", "value": 9}),
+                json!({"op": "remove", "path": "/4"}),
+            ]
+        );
+        assert_eq!(
+            roundtrip(
+                json!([{"id": 1, "n": "a"}, 5]),
+                json!([{"id": 1, "n": "b"}, 5])
+            ),
+            vec![json!({"op": "replace", "path": "/0/n", "value": "b"})]
+        );
+        roundtrip(json!([1, 2, 3]), json!([]));
+        roundtrip(json!([]), json!([[1], {"a": 2}]));
+        roundtrip(json!({"a": [1]}), json!([1]));
+        roundtrip(json!(null), json!({"a": 1}));
+    }
+
+    #[test]
+    fn test_pointers() {
+        let path = vec![json!("a/b"), json!(0), json!("m~n"), json!("")];
+        assert_eq!(pointer(&path), "/a~1b/0/m~0n/");
+        assert_eq!(
+            parse_pointer("/a~1b/0/m~0n/"),
+            Ok(vec![json!("a/b"), json!("0"), json!("m~n"), json!("")])
+        );
+        assert_eq!(parse_pointer(""), Ok(vec![]));
+        assert!(parse_pointer("a").is_err());
+        assert_eq!(jq_path(&path), ".[\"a/b\"][0][\"m~n\"][\"\"]");
+        assert_eq!(
+            jq_path(&[json!("items"), json!(2), json!("id")]),
+            ".items[2].id"
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        // The example from RFC 6902, appendix A.16
+        let ops = from_patch(&json!([
+            {"op": "add", "path": "/foo/-", "value": ["abc", "def"]},
+            {"op": "test", "path": "/baz", "value": "qux"},
+            {"op": "copy", "from": "/baz", "path": "/copied"},
+            {"op": "move", "from": "/foo/0", "path": "/moved"},
+        ]))
+        .unwrap();
+        assert_eq!(
+            apply(json!({"foo": ["bar"], "baz": "qux"}), &ops),
+            Ok(json!({"foo": [["abc", "def"]], "baz": "qux", "copied": "qux", "moved": "bar"}))
+        );
+
+        let fails = |patch: Value| apply(json!({"a": [1]}), &from_patch(&patch).unwrap());
+        assert_eq!(
+            fails(json!([{"op": "test", "path": "/a/0", "value": 2}])),
+            Err(JqError::Patch("test failed: /a/0 is 1, not 2".into()))
+        );
+        assert_eq!(
+            fails(json!([{"op": "remove", "path": "/a/01"}])),
+            Err(JqError::Patch("/a/01 does not exist".into()))
+        );
+        assert_eq!(
+            fails(json!([{"op": "add", "path": "/b/c", "value": 1}])),
+            Err(JqError::Patch("/b does not exist".into()))
+        );
+        assert!(fails(json!([{"op": "move", "from": "/a", "path": "/a/0"}])).is_err());
+        assert!(from_patch(&json!([{"op": "frobnicate", "path": ""}])).is_err());
+        assert!(from_patch(&json!({"op": "add"})).is_err());
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        // The example from RFC 7386, section 3
+        let target = json!({
+            "title": "Goodbye!",
+            "author": {"givenName": "John", "familyName": "Doe"},
+            "tags": ["example", "sample"],
+            "content": "This will be unchanged"
+        });
+        let patch = json!({
+            "title": "Hello!",
+            "phoneNumber": "+01-123-456-7890",
+            "author": {"familyName": null},
+            "tags": ["example"]
+        });
+        assert_eq!(
+            merge_patch(target, &patch),
+            json!({
+                "title": "Hello!",
+                "author": {"givenName": "John"},
+                "tags": ["example"],
+                "content": "This will be unchanged",
+                "phoneNumber": "+01-123-456-7890"
+            })
+        );
+    }
+
+    #[test]
+    fn test_readable() {
+        let before = json!({"name": "a", "tags": ["x", "y"], "gone": 1});
+        let after = json!({"name": "b", "tags": ["y"], "new": {"k": true}});
+        let ops = diff(&before, &after);
+        let text = Readable {
+            before: &before,
+            ops: &ops,
+            color: false,
+        }
+        .to_string();
+        assert_eq!(
+            text,
+            "~ .name: \"a\" -> \"b\"\n- .tags[0]: \"x\"\n- .gone: 1\n+ .new: {\"k\":true}\n"
+        );
+        let colored = Readable {
+            before: &before,
+            ops: &ops[..1],
+            color: true,
+        }
+        .to_string();
+        assert_eq!(colored, "\x1b[0;33m~ .name: \"a\" -> \"b\"\x1b[0m\n");
+    }
+}
@@ -0,0 +1,44 @@
+use crate::value::Value;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum JqError {
+    /// The filter string could not be parsed
+    #[error("syntax error: {0}")]
+    Syntax(String),
+    /// A variable or function was referenced that is not in scope
+    #[error("{0} is not defined")]
+    Undefined(String),
+    /// A filter was applied to a value of the wrong type
+    #[error("{0}")]
+    Type(String),
+    /// An input value could not be read or parsed
+    #[error("{0}")]
+    Input(String),
+    /// An output value could not be written in the requested format
+    #[error("{0}")]
+    Output(String),
+    /// A JSON patch was malformed or did not fit the document it was applied to
+    #[error("{0}")]
+    Patch(String),
+    /// Raised by the `error` builtin; carries the value it was given
+    #[error("{}", user_message(.0))]
+    User(Value),
+}
+
+impl JqError {
+    /// The value a `try ... catch` handler receives for this error
+    pub fn value(&self) -> Value {
+        match self {
+            JqError::User(value) => value.clone(),
+            other => Value::String(other.to_string()),
+        }
+    }
+}
+
+fn user_message(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => format!("{} (not a string)", other),
+    }
+}
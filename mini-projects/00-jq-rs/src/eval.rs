@@ -0,0 +1,1001 @@
+use std::cell::{Cell, RefCell};
+use std::iter;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::ast::{AssignOp, Filter, FuncDef, Param, StringPart};
+use crate::builtins;
+use crate::error::JqError;
+use crate::filters;
+use crate::value::{Map, Value};
+
+/// A value as it flows between filters. Cloning one only bumps a reference count, so every
+/// sub-filter can be handed the input without the document being copied
+pub type Shared = Rc<Value>;
+
+/// The outputs of a filter. Streams are lazy: nothing is evaluated until it is pulled
+pub type Stream<'a> = Box<dyn Iterator<Item = Result<Shared, JqError>> + 'a>;
+
+/// A location inside the input, as produced by `path(f)`, with the value found there
+pub type Path = (Vec<Value>, Shared);
+
+pub type PathStream<'a> = Box<dyn Iterator<Item = Result<Path, JqError>> + 'a>;
+
+/// The remaining input values, shared with the `input` and `inputs` builtins
+pub type Inputs<'a> = Rc<RefCell<dyn Iterator<Item = Result<Value, JqError>> + 'a>>;
+
+pub fn single<'a, T: 'a>(
+    result: Result<T, JqError>,
+) -> Box<dyn Iterator<Item = Result<T, JqError>> + 'a> {
+    Box::new(iter::once(result))
+}
+
+/// Build a stream only once its first output is requested
+pub fn defer<'a, T: 'a>(
+    f: impl FnOnce() -> Box<dyn Iterator<Item = Result<T, JqError>> + 'a> + 'a,
+) -> Box<dyn Iterator<Item = Result<T, JqError>> + 'a> {
+    Box::new(iter::once_with(f).flatten())
+}
+
+/// Feed every successful output of `stream` through `f`, passing errors along untouched
+pub fn flat_map_ok<'a, T: 'a, U: 'a>(
+    stream: impl Iterator<Item = Result<T, JqError>> + 'a,
+    mut f: impl FnMut(T) -> Box<dyn Iterator<Item = Result<U, JqError>> + 'a> + 'a,
+) -> Box<dyn Iterator<Item = Result<U, JqError>> + 'a> {
+    Box::new(stream.flat_map(move |item| match item {
+        Ok(value) => f(value),
+        Err(e) => single(Err(e)),
+    }))
+}
+
+/// Variables and functions in scope, as a persistent linked list so that extending a
+/// scope for one branch of the evaluation never affects the others
+#[derive(Clone, Default)]
+pub struct Env<'a>(Option<Rc<Scope<'a>>>);
+
+struct Scope<'a> {
+    binding: Binding<'a>,
+    parent: Env<'a>,
+}
+
+enum Binding<'a> {
+    Var(String, Shared),
+    Func(&'a FuncDef),
+    /// A filter argument, evaluated in the scope of the caller
+    Closure(&'a str, &'a Filter, Env<'a>),
+    /// The values `input` and `inputs` read from
+    Inputs(Inputs<'a>),
+}
+
+pub(crate) enum Callable<'a> {
+    Def(&'a FuncDef, Env<'a>),
+    Closure(&'a Filter, Env<'a>),
+}
+
+impl<'a> Env<'a> {
+    /// The top-level scope, containing the builtins defined in jq itself
+    pub fn root() -> Env<'a> {
+        builtins::prelude(Env::default())
+    }
+
+    fn push(&self, binding: Binding<'a>) -> Env<'a> {
+        Env(Some(Rc::new(Scope {
+            binding,
+            parent: self.clone(),
+        })))
+    }
+
+    pub fn bind_var(&self, name: impl Into<String>, value: impl Into<Shared>) -> Env<'a> {
+        self.push(Binding::Var(name.into(), value.into()))
+    }
+
+    pub(crate) fn bind_func(&self, def: &'a FuncDef) -> Env<'a> {
+        self.push(Binding::Func(def))
+    }
+
+    fn bind_closure(&self, name: &'a str, body: &'a Filter, env: Env<'a>) -> Env<'a> {
+        self.push(Binding::Closure(name, body, env))
+    }
+
+    /// Make `inputs` available to the `input` and `inputs` builtins
+    pub fn with_inputs(&self, inputs: Inputs<'a>) -> Env<'a> {
+        self.push(Binding::Inputs(inputs))
+    }
+
+    pub(crate) fn inputs(&self) -> Option<&Inputs<'a>> {
+        let mut scope = self.0.as_ref();
+        while let Some(node) = scope {
+            if let Binding::Inputs(inputs) = &node.binding {
+                return Some(inputs);
+            }
+            scope = node.parent.0.as_ref();
+        }
+        None
+    }
+
+    pub fn var(&self, name: &str) -> Option<&Shared> {
+        let mut scope = self.0.as_ref();
+        while let Some(node) = scope {
+            if let Binding::Var(var, value) = &node.binding {
+                if var == name {
+                    return Some(value);
+                }
+            }
+            scope = node.parent.0.as_ref();
+        }
+        None
+    }
+
+    pub(crate) fn func(&self, name: &str, arity: usize) -> Option<Callable<'a>> {
+        let mut scope = self.0.as_ref();
+        while let Some(node) = scope {
+            match &node.binding {
+                Binding::Func(def) if def.name == name && def.params.len() == arity => {
+                    // The definition's own scope includes itself, so it can recurse
+                    return Some(Callable::Def(def, Env(Some(node.clone()))));
+                }
+                Binding::Closure(closure, body, env) if *closure == name && arity == 0 => {
+                    return Some(Callable::Closure(body, env.clone()));
+                }
+                _ => {}
+            }
+            scope = node.parent.0.as_ref();
+        }
+        None
+    }
+}
+
+/// Run `filter` against `input`
+pub fn eval<'a>(filter: &'a Filter, env: &Env<'a>, input: Shared) -> Stream<'a> {
+    match filter {
+        Filter::Identity => single(Ok(input)),
+        Filter::Literal(value) => single(Ok(Rc::new(value.clone()))),
+        Filter::Interpolate(parts) => interpolate(parts, env.clone(), input),
+        Filter::Index(term, key) => {
+            // `.a.b[0]` borrows its way down to what it finds, and cloning that only
+            // shares its arrays and objects
+            if let Some(found) = lookup(filter, &input) {
+                return single(Ok(Rc::new(found.clone())));
+            }
+            let env = env.clone();
+            if lookup(term, &input).is_some() {
+                return Box::new(eval(key, &env, input.clone()).map(move |key| {
+                    let container = lookup(term, &input).unwrap_or(&Value::Null);
+                    filters::index(container, &*key?).map(Rc::new)
+                }));
+            }
+            flat_map_ok(eval(term, &env, input.clone()), move |value| {
+                Box::new(
+                    eval(key, &env, input.clone())
+                        .map(move |key| filters::index(&value, &*key?).map(Rc::new)),
+                )
+            })
+        }
+        Filter::Slice(term, start, end) => {
+            let env = env.clone();
+            if lookup(term, &input).is_some() {
+                let bounds = slice_bounds(start, end, &env, input.clone());
+                return Box::new(bounds.map(move |key| {
+                    let container = lookup(term, &input).unwrap_or(&Value::Null);
+                    filters::index(container, &key?).map(Rc::new)
+                }));
+            }
+            flat_map_ok(eval(term, &env, input.clone()), move |value| {
+                Box::new(
+                    slice_bounds(start, end, &env, input.clone())
+                        .map(move |key| filters::index(&value, &key?).map(Rc::new)),
+                )
+            })
+        }
+        Filter::Iterate(term) => flat_map_ok(eval(term, env, input), iterate),
+        Filter::Array(None) => single(Ok(Rc::new(Value::from(Vec::new())))),
+        Filter::Array(Some(body)) => {
+            let env = env.clone();
+            defer(move || {
+                single(
+                    eval(body, &env, input)
+                        .map(|item| item.map(Rc::unwrap_or_clone))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(|items| Rc::new(Value::from(items))),
+                )
+            })
+        }
+        Filter::Object(entries) => {
+            let env = env.clone();
+            defer(move || match object(entries, &env, &input) {
+                Ok(objects) => {
+                    Box::new(objects.into_iter().map(|obj| Ok(Rc::new(Value::from(obj)))))
+                }
+                Err(e) => single(Err(e)),
+            })
+        }
+        Filter::Pipe(lhs, rhs) => {
+            let env = env.clone();
+            flat_map_ok(eval(lhs, &env, input), move |value| eval(rhs, &env, value))
+        }
+        Filter::Comma(lhs, rhs) => {
+            let env = env.clone();
+            let first = eval(lhs, &env, input.clone());
+            Box::new(first.chain(defer(move || eval(rhs, &env, input))))
+        }
+        Filter::Neg(term) => Box::new(eval(term, env, input).map(|value| match &*value? {
+            Value::Number(n) => Ok(Rc::new(filters::number(-n.as_f64()))),
+            other => Err(JqError::Type(format!(
+                "{} cannot be negated",
+                filters::describe(other)
+            ))),
+        })),
+        Filter::Binary(op, lhs, rhs) => {
+            let env = env.clone();
+            let op = *op;
+            flat_map_ok(eval(rhs, &env, input.clone()), move |rhs_value| {
+                Box::new(eval(lhs, &env, input.clone()).map(move |lhs_value| {
+                    let lhs_value = Rc::unwrap_or_clone(lhs_value?);
+                    filters::binary_op(op, lhs_value, &rhs_value).map(Rc::new)
+                }))
+            })
+        }
+        Filter::And(lhs, rhs) => logical(lhs, rhs, env.clone(), input, false),
+        Filter::Or(lhs, rhs) => logical(lhs, rhs, env.clone(), input, true),
+        Filter::Alternative(lhs, rhs) => {
+            let found = Rc::new(Cell::new(false));
+            let seen = found.clone();
+            let truthy = eval(lhs, env, input.clone()).filter_map(move |value| match value {
+                Ok(value) if filters::is_truthy(&value) => {
+                    seen.set(true);
+                    Some(Ok(value))
+                }
+                _ => None,
+            });
+            let env = env.clone();
+            Box::new(truthy.chain(defer(move || {
+                if found.get() {
+                    Box::new(iter::empty())
+                } else {
+                    eval(rhs, &env, input)
+                }
+            })))
+        }
+        Filter::Assign(op, lhs, rhs) => assign(*op, lhs, rhs, env.clone(), input),
+        Filter::If(cond, then, otherwise) => {
+            let env = env.clone();
+            flat_map_ok(eval(cond, &env, input.clone()), move |cond| {
+                if filters::is_truthy(&cond) {
+                    eval(then, &env, input.clone())
+                } else if let Some(otherwise) = otherwise {
+                    eval(otherwise, &env, input.clone())
+                } else {
+                    single(Ok(input.clone()))
+                }
+            })
+        }
+        Filter::Try(body, handler) => {
+            let env = env.clone();
+            let body = eval(body, &env, input);
+            catch(body, move |e| match handler {
+                Some(handler) => eval(handler, &env, Rc::new(e.value())),
+                None => Box::new(iter::empty()),
+            })
+        }
+        Filter::Reduce(source, name, init, update) => {
+            let env = env.clone();
+            flat_map_ok(eval(init, &env, input.clone()), move |acc| {
+                single(reduce(source, name, update, &env, input.clone(), acc))
+            })
+        }
+        Filter::Foreach(source, name, init, update, extract) => {
+            let env = env.clone();
+            flat_map_ok(eval(init, &env, input.clone()), move |state| {
+                let state = Rc::new(RefCell::new(state));
+                let env = env.clone();
+                flat_map_ok(eval(source, &env, input.clone()), move |item| {
+                    let env = env.bind_var(name.as_str(), item);
+                    let current = state.borrow().clone();
+                    let updates: Vec<_> = eval(update, &env, current).collect();
+                    if let Some(Ok(last)) = updates.iter().rev().find(|u| u.is_ok()) {
+                        *state.borrow_mut() = last.clone();
+                    }
+                    flat_map_ok(updates.into_iter(), move |updated| match extract {
+                        Some(extract) => eval(extract, &env, updated),
+                        None => single(Ok(updated)),
+                    })
+                })
+            })
+        }
+        Filter::Bind(source, name, body) => {
+            let env = env.clone();
+            flat_map_ok(eval(source, &env, input.clone()), move |value| {
+                eval(body, &env.bind_var(name.as_str(), value), input.clone())
+            })
+        }
+        Filter::Var(name) => single(
+            env.var(name)
+                .cloned()
+                .ok_or_else(|| JqError::Undefined(format!("${}", name))),
+        ),
+        Filter::Call(name, args) => match env.func(name, args.len()) {
+            Some(Callable::Closure(body, closure_env)) => eval(body, &closure_env, input),
+            Some(Callable::Def(def, def_env)) => {
+                let caller = env.clone();
+                flat_map_ok(
+                    bind_params(def, def_env, args, caller, input.clone()),
+                    move |env| eval(&def.body, &env, input.clone()),
+                )
+            }
+            None => builtins::call(name, args, env, input),
+        },
+        Filter::Def(def, rest) => eval(rest, &env.bind_func(def), input),
+    }
+}
+
+/// Run `filter` as a path expression, yielding the location of each output within the
+/// original input rather than the output itself
+pub fn eval_paths<'a>(filter: &'a Filter, env: &Env<'a>, current: Path) -> PathStream<'a> {
+    match filter {
+        Filter::Identity => single(Ok(current)),
+        Filter::Index(term, key) => {
+            if let Some(keys) = static_path(filter) {
+                if let Some(found) = keys
+                    .iter()
+                    .try_fold(&*current.1, |value, key| filters::index_ref(value, key))
+                {
+                    let mut path = current.0;
+                    path.extend(keys.into_iter().cloned());
+                    return single(Ok((path, Rc::new(found.clone()))));
+                }
+            }
+            let env = env.clone();
+            let input = current.1.clone();
+            flat_map_ok(eval_paths(term, &env, current), move |(path, value)| {
+                Box::new(eval(key, &env, input.clone()).map(move |key| {
+                    let key = Rc::unwrap_or_clone(key?);
+                    let child = filters::index(&value, &key)?;
+                    let mut path = path.clone();
+                    path.push(key);
+                    Ok((path, Rc::new(child)))
+                }))
+            })
+        }
+        Filter::Slice(term, start, end) => {
+            let env = env.clone();
+            let input = current.1.clone();
+            flat_map_ok(eval_paths(term, &env, current), move |(path, value)| {
+                Box::new(
+                    slice_bounds(start, end, &env, input.clone()).map(move |key| {
+                        let key = key?;
+                        let child = filters::index(&value, &key)?;
+                        let mut path = path.clone();
+                        path.push(key);
+                        Ok((path, Rc::new(child)))
+                    }),
+                )
+            })
+        }
+        Filter::Iterate(term) => flat_map_ok(eval_paths(term, env, current), |(path, value)| {
+            let children: Vec<(Value, Value)> = match &*value {
+                Value::Array(arr) => arr
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| (Value::from(i), v.clone()))
+                    .collect(),
+                Value::Object(obj) => obj
+                    .iter()
+                    .map(|(k, v)| (Value::from(k.as_str()), v.clone()))
+                    .collect(),
+                other => return single(Err(iterate_error(other))),
+            };
+            Box::new(children.into_iter().map(move |(key, child)| {
+                let mut path = path.clone();
+                path.push(key);
+                Ok((path, Rc::new(child)))
+            }))
+        }),
+        Filter::Pipe(lhs, rhs) => {
+            let env = env.clone();
+            flat_map_ok(eval_paths(lhs, &env, current), move |current| {
+                eval_paths(rhs, &env, current)
+            })
+        }
+        Filter::Comma(lhs, rhs) => {
+            let env = env.clone();
+            let first = eval_paths(lhs, &env, current.clone());
+            Box::new(first.chain(defer(move || eval_paths(rhs, &env, current))))
+        }
+        Filter::Alternative(lhs, rhs) => {
+            let found = Rc::new(Cell::new(false));
+            let seen = found.clone();
+            let truthy =
+                eval_paths(lhs, env, current.clone()).filter_map(move |found| match found {
+                    Ok((path, value)) if filters::is_truthy(&value) => {
+                        seen.set(true);
+                        Some(Ok((path, value)))
+                    }
+                    _ => None,
+                });
+            let env = env.clone();
+            Box::new(truthy.chain(defer(move || {
+                if found.get() {
+                    Box::new(iter::empty())
+                } else {
+                    eval_paths(rhs, &env, current)
+                }
+            })))
+        }
+        Filter::If(cond, then, otherwise) => {
+            let env = env.clone();
+            flat_map_ok(eval(cond, &env, current.1.clone()), move |cond| {
+                if filters::is_truthy(&cond) {
+                    eval_paths(then, &env, current.clone())
+                } else if let Some(otherwise) = otherwise {
+                    eval_paths(otherwise, &env, current.clone())
+                } else {
+                    single(Ok(current.clone()))
+                }
+            })
+        }
+        Filter::Try(body, handler) => {
+            let env = env.clone();
+            let body = eval_paths(body, &env, current);
+            catch(body, move |e| match handler {
+                Some(handler) => invalid_paths(eval(handler, &env, Rc::new(e.value()))),
+                None => Box::new(iter::empty()),
+            })
+        }
+        Filter::Bind(source, name, body) => {
+            let env = env.clone();
+            flat_map_ok(eval(source, &env, current.1.clone()), move |value| {
+                eval_paths(body, &env.bind_var(name.as_str(), value), current.clone())
+            })
+        }
+        Filter::Call(name, args) => match env.func(name, args.len()) {
+            Some(Callable::Closure(body, closure_env)) => eval_paths(body, &closure_env, current),
+            Some(Callable::Def(def, def_env)) => {
+                let caller = env.clone();
+                let input = current.1.clone();
+                flat_map_ok(bind_params(def, def_env, args, caller, input), move |env| {
+                    eval_paths(&def.body, &env, current.clone())
+                })
+            }
+            None => builtins::call_paths(name, args, env, current),
+        },
+        Filter::Def(def, rest) => eval_paths(rest, &env.bind_func(def), current),
+        other => invalid_paths(eval(other, env, current.1)),
+    }
+}
+
+/// Outputs of a filter that cannot be used as a path are reported as errors
+pub fn invalid_paths<'a>(stream: Stream<'a>) -> PathStream<'a> {
+    Box::new(stream.map(|value| {
+        Err(JqError::Type(format!(
+            "Invalid path expression with result {}",
+            value?
+        )))
+    }))
+}
+
+/// The keys of a path made only of constant indexes, such as `.a[0]."b"`
+fn static_path(filter: &Filter) -> Option<Vec<&Value>> {
+    match filter {
+        Filter::Identity => Some(Vec::new()),
+        Filter::Index(term, key) => match key.as_ref() {
+            Filter::Literal(key) => {
+                let mut keys = static_path(term)?;
+                keys.push(key);
+                Some(keys)
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// What a constant path such as `.a[0]` finds in `input`, borrowed rather than copied.
+/// `None` when `filter` is not such a path, or when one of its keys does not apply, which
+/// is left for the general evaluation to report
+fn lookup<'v>(filter: &Filter, input: &'v Value) -> Option<&'v Value> {
+    match filter {
+        Filter::Identity => Some(input),
+        Filter::Index(term, key) => match key.as_ref() {
+            Filter::Literal(key) => filters::index_ref(lookup(term, input)?, key),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Pass outputs through until the first error, then switch to the outputs of `handler`
+fn catch<'a, T: 'a>(
+    mut body: Box<dyn Iterator<Item = Result<T, JqError>> + 'a>,
+    handler: impl FnOnce(JqError) -> Box<dyn Iterator<Item = Result<T, JqError>> + 'a> + 'a,
+) -> Box<dyn Iterator<Item = Result<T, JqError>> + 'a> {
+    let mut handler = Some(handler);
+    let mut recovery: Option<Box<dyn Iterator<Item = Result<T, JqError>> + 'a>> = None;
+    Box::new(iter::from_fn(move || {
+        if let Some(recovery) = recovery.as_mut() {
+            return recovery.next();
+        }
+        match body.next()? {
+            Ok(value) => Some(Ok(value)),
+            Err(e) => {
+                let handler = handler.take()?;
+                recovery.insert(handler(e)).next()
+            }
+        }
+    }))
+}
+
+/// The elements of an array or the values of an object, each shared with the container
+/// rather than copied out of it
+fn iterate<'a>(value: Shared) -> Stream<'a> {
+    match &*value {
+        Value::Array(arr) => {
+            let arr = Arc::clone(arr);
+            Box::new((0..arr.len()).map(move |i| Ok(Rc::new(arr[i].clone()))))
+        }
+        Value::Object(obj) => {
+            let obj = Arc::clone(obj);
+            Box::new((0..obj.len()).map(move |i| Ok(Rc::new(obj[i].clone()))))
+        }
+        other => single(Err(iterate_error(other))),
+    }
+}
+
+fn iterate_error(value: &Value) -> JqError {
+    JqError::Type(format!("Cannot iterate over {}", filters::describe(value)))
+}
+
+/// The path key for each `.[start:end]`, which is also how `getpath` and friends see
+/// slices
+fn slice_bounds<'a>(
+    start: &'a Filter,
+    end: &'a Filter,
+    env: &Env<'a>,
+    input: Shared,
+) -> Box<dyn Iterator<Item = Result<Value, JqError>> + 'a> {
+    let env = env.clone();
+    flat_map_ok(eval(start, &env, input.clone()), move |start| {
+        Box::new(eval(end, &env, input.clone()).map(move |end| {
+            let mut key = Map::new();
+            key.insert("start".into(), (*start).clone());
+            key.insert("end".into(), Rc::unwrap_or_clone(end?));
+            Ok(Value::from(key))
+        }))
+    })
+}
+
+fn interpolate<'a>(parts: &'a [StringPart], env: Env<'a>, input: Shared) -> Stream<'a> {
+    let (last, rest) = match parts.split_last() {
+        Some(split) => split,
+        None => return single(Ok(Rc::new(Value::String(String::new())))),
+    };
+    let suffixes: Stream<'a> = match last {
+        StringPart::Literal(s) => single(Ok(Rc::new(Value::String(s.clone())))),
+        StringPart::Expr(filter) => Box::new(
+            eval(filter, &env, input.clone())
+                .map(|value| value.map(|v| Rc::new(filters::tostring(&v)))),
+        ),
+    };
+    flat_map_ok(suffixes, move |suffix| {
+        let suffix = suffix.as_str().unwrap_or_default().to_string();
+        Box::new(
+            interpolate(rest, env.clone(), input.clone()).map(move |prefix| {
+                let mut prefix = prefix?.as_str().unwrap_or_default().to_string();
+                prefix.push_str(&suffix);
+                Ok(Rc::new(Value::String(prefix)))
+            }),
+        )
+    })
+}
+
+fn object<'a>(
+    entries: &'a [(Filter, Filter)],
+    env: &Env<'a>,
+    input: &Shared,
+) -> Result<Vec<Map<String, Value>>, JqError> {
+    let mut objects = vec![Map::new()];
+    for (key, value) in entries {
+        let mut extended = Vec::new();
+        for obj in &objects {
+            for key in eval(key, env, input.clone()) {
+                let key = match Rc::unwrap_or_clone(key?) {
+                    Value::String(key) => key,
+                    other => {
+                        return Err(JqError::Type(format!(
+                            "Object keys must be strings, not {}",
+                            filters::describe(&other)
+                        )))
+                    }
+                };
+                for value in eval(value, env, input.clone()) {
+                    let mut obj = obj.clone();
+                    obj.insert(key.clone(), Rc::unwrap_or_clone(value?));
+                    extended.push(obj);
+                }
+            }
+        }
+        objects = extended;
+    }
+    Ok(objects)
+}
+
+fn logical<'a>(
+    lhs: &'a Filter,
+    rhs: &'a Filter,
+    env: Env<'a>,
+    input: Shared,
+    is_or: bool,
+) -> Stream<'a> {
+    flat_map_ok(eval(lhs, &env, input.clone()), move |lhs| {
+        // `false and x` and `true or x` are decided without evaluating `x`
+        if filters::is_truthy(&lhs) == is_or {
+            return single(Ok(Rc::new(Value::Bool(is_or))));
+        }
+        Box::new(
+            eval(rhs, &env, input.clone())
+                .map(|rhs| rhs.map(|rhs| Rc::new(Value::Bool(filters::is_truthy(&rhs))))),
+        )
+    })
+}
+
+fn reduce<'a>(
+    source: &'a Filter,
+    name: &'a str,
+    update: &'a Filter,
+    env: &Env<'a>,
+    input: Shared,
+    mut acc: Shared,
+) -> Result<Shared, JqError> {
+    for item in eval(source, env, input) {
+        let env = env.bind_var(name, item?);
+        let mut last = Rc::new(Value::Null);
+        for value in eval(update, &env, acc) {
+            last = value?;
+        }
+        acc = last;
+    }
+    Ok(acc)
+}
+
+/// Bind a user function's parameters for one call. Filter parameters become closures over
+/// the caller's scope; `$name` parameters are bound once per output of their argument, so
+/// the call yields one scope per combination.
+fn bind_params<'a>(
+    def: &'a FuncDef,
+    def_env: Env<'a>,
+    args: &'a [Filter],
+    caller: Env<'a>,
+    input: Shared,
+) -> Box<dyn Iterator<Item = Result<Env<'a>, JqError>> + 'a> {
+    let mut env = def_env;
+    let mut value_params = Vec::new();
+    for (param, arg) in def.params.iter().zip(args) {
+        match param {
+            Param::Filter(name) => env = env.bind_closure(name, arg, caller.clone()),
+            Param::Value(name) => value_params.push((name.as_str(), arg)),
+        }
+    }
+    bind_values(env, value_params, caller, input)
+}
+
+fn bind_values<'a>(
+    env: Env<'a>,
+    params: Vec<(&'a str, &'a Filter)>,
+    caller: Env<'a>,
+    input: Shared,
+) -> Box<dyn Iterator<Item = Result<Env<'a>, JqError>> + 'a> {
+    let mut params = params.into_iter();
+    let (name, arg) = match params.next() {
+        Some(param) => param,
+        None => return single(Ok(env)),
+    };
+    let rest: Vec<_> = params.collect();
+    flat_map_ok(eval(arg, &caller, input.clone()), move |value| {
+        bind_values(
+            env.bind_var(name, value),
+            rest.clone(),
+            caller.clone(),
+            input.clone(),
+        )
+    })
+}
+
+fn assign<'a>(
+    op: AssignOp,
+    lhs: &'a Filter,
+    rhs: &'a Filter,
+    env: Env<'a>,
+    input: Shared,
+) -> Stream<'a> {
+    if op == AssignOp::Update {
+        return defer(move || {
+            single(modify(lhs, &env, input, |old| {
+                eval(rhs, &env, Rc::new(old))
+                    .next()
+                    .transpose()
+                    .map(|new| new.map(Rc::unwrap_or_clone))
+            }))
+        });
+    }
+    flat_map_ok(eval(rhs, &env, input.clone()), move |new| {
+        single(modify(lhs, &env, input.clone(), |old| match op {
+            AssignOp::Arithmetic(op) => filters::binary_op(op, old, &new).map(Some),
+            AssignOp::Alternative if filters::is_truthy(&old) => Ok(Some(old)),
+            _ => Ok(Some((*new).clone())),
+        }))
+    })
+}
+
+/// Replace the value at every path matched by `lhs` with the result of `f`, deleting the
+/// path instead when `f` returns `None`
+fn modify<'a>(
+    lhs: &'a Filter,
+    env: &Env<'a>,
+    input: Shared,
+    mut f: impl FnMut(Value) -> Result<Option<Value>, JqError>,
+) -> Result<Shared, JqError> {
+    let paths = eval_paths(lhs, env, (Vec::new(), input.clone()))
+        .map(|found| found.map(|(path, _)| path))
+        .collect::<Result<Vec<_>, _>>()?;
+    // Once the paths are known this is usually the only reference, so nothing is copied
+    let mut result = Rc::unwrap_or_clone(input);
+    let mut deleted = Vec::new();
+    for path in paths {
+        let old = filters::getpath(&result, &path)?;
+        match f(old)? {
+            Some(new) => result = filters::setpath(result, &path, new)?,
+            None => deleted.push(Value::from(path)),
+        }
+    }
+    filters::delpaths(result, &deleted).map(Rc::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run;
+    use serde_json::json;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_identity() {
+        assert_eq!(run(".", json!({"a": 1})).unwrap(), vec![json!({"a": 1})]);
+    }
+
+    #[test]
+    fn test_object_identifier_index() {
+        let input = json!({"fizz": "buzz", "nested": {"a": [1, 2]}});
+        assert_eq!(run(".fizz", input.clone()).unwrap(), vec![json!("buzz")]);
+        assert_eq!(run(".nested.a[1]", input.clone()).unwrap(), vec![json!(2)]);
+        assert_eq!(run(".missing", input).unwrap(), vec![json!(null)]);
+        assert!(run(".a", json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_array_index_and_slice() {
+        let input = json!(["one", "two", "three"]);
+        assert_eq!(run(".[0]", input.clone()).unwrap(), vec![json!("one")]);
+        assert_eq!(run(".[-1]", input.clone()).unwrap(), vec![json!("three")]);
+        assert_eq!(
+            run(".[0:2]", input.clone()).unwrap(),
+            vec![json!(["one", "two"])]
+        );
+        assert_eq!(
+            run(".[1:10]", input).unwrap(),
+            vec![json!(["two", "three"])]
+        );
+    }
+
+    #[test]
+    fn test_slice_bounds() {
+        let input = json!([0, 1, 2, 3, 4]);
+        assert_eq!(run(".[2:]", input.clone()).unwrap(), vec![json!([2, 3, 4])]);
+        assert_eq!(
+            run(".[-3:]", input.clone()).unwrap(),
+            vec![json!([2, 3, 4])]
+        );
+        assert_eq!(run(".[:2]", input.clone()).unwrap(), vec![json!([0, 1])]);
+        assert_eq!(
+            run(".[1:-1]", input.clone()).unwrap(),
+            vec![json!([1, 2, 3])]
+        );
+        assert_eq!(run(".[3:100]", input.clone()).unwrap(), vec![json!([3, 4])]);
+        assert_eq!(
+            run(".[1.2:2.5]", input.clone()).unwrap(),
+            vec![json!([1, 2])]
+        );
+        assert_eq!(
+            run(r#""abcdef" | .[2:4]"#, json!(null)).unwrap(),
+            vec![json!("cd")]
+        );
+        assert_eq!(run(".[1:]", json!(null)).unwrap(), vec![json!(null)]);
+        assert!(run(".[1:]", json!(1)).is_err());
+        assert!(run(r#".["a":]"#, input.clone()).is_err());
+
+        assert_eq!(
+            run("del(.[:3])", input.clone()).unwrap(),
+            vec![json!([3, 4])]
+        );
+        assert_eq!(
+            run(r#".[1:3] = ["x"]"#, input.clone()).unwrap(),
+            vec![json!([0, "x", 3, 4])]
+        );
+        assert_eq!(
+            run(".[-2:] |= map(. * 10)", input.clone()).unwrap(),
+            vec![json!([0, 1, 2, 30, 40])]
+        );
+        assert_eq!(
+            run("[path(.[1:])]", input).unwrap(),
+            vec![json!([[{"start": 1, "end": null}]])]
+        );
+    }
+
+    #[test]
+    fn test_pipe_and_iterator() {
+        let input = json!([{"name": "Leo"}, {"name": "Sophie"}]);
+        assert_eq!(
+            run(".[] | .name", input).unwrap(),
+            vec![json!("Leo"), json!("Sophie")]
+        );
+        assert!(run(".[]", json!(3)).is_err());
+    }
+
+    #[test]
+    fn test_comma_and_construction() {
+        let input = json!({"a": 1, "b": 2});
+        assert_eq!(
+            run(".a, .b", input.clone()).unwrap(),
+            vec![json!(1), json!(2)]
+        );
+        assert_eq!(run("[.a, .b]", input.clone()).unwrap(), vec![json!([1, 2])]);
+        assert_eq!(
+            run("{x: .a, b, \"c\": 3}", input).unwrap(),
+            vec![json!({"x": 1, "b": 2, "c": 3})]
+        );
+        assert_eq!(
+            run("{a: (1, 2)}", json!(null)).unwrap(),
+            vec![json!({"a": 1}), json!({"a": 2})]
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_and_comparison() {
+        assert_eq!(run("1 + 2 * 3", json!(null)).unwrap(), vec![json!(7)]);
+        assert_eq!(run(".a - 1", json!({"a": 1.5})).unwrap(), vec![json!(0.5)]);
+        assert_eq!(
+            run("[1, 2] == [1, 2]", json!(null)).unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(
+            run("\"a\" < \"b\"", json!(null)).unwrap(),
+            vec![json!(true)]
+        );
+        assert_eq!(run("-.", json!(3)).unwrap(), vec![json!(-3)]);
+        assert!(run("1 / 0", json!(null)).is_err());
+    }
+
+    #[test]
+    fn test_conditionals_and_alternative() {
+        assert_eq!(
+            run(
+                "if . > 1 then \"big\" elif . == 1 then \"one\" else \"small\" end",
+                json!(1)
+            )
+            .unwrap(),
+            vec![json!("one")]
+        );
+        assert_eq!(
+            run(".a // \"default\"", json!({})).unwrap(),
+            vec![json!("default")]
+        );
+        assert_eq!(
+            run("true and (false or true)", json!(null)).unwrap(),
+            vec![json!(true)]
+        );
+    }
+
+    #[test]
+    fn test_try_catch() {
+        assert_eq!(
+            run("try error(\"boom\") catch .", json!(null)).unwrap(),
+            vec![json!("boom")]
+        );
+        assert_eq!(
+            run("[.[] | .a?]", json!([1, {"a": 2}])).unwrap(),
+            vec![json!([2])]
+        );
+        assert_eq!(
+            run("error({\"a\": 1})", json!(null)).unwrap_err().value(),
+            json!({"a": 1}).into()
+        );
+    }
+
+    #[test]
+    fn test_variables_reduce_foreach() {
+        assert_eq!(
+            run(".a as $x | $x + 1", json!({"a": 1})).unwrap(),
+            vec![json!(2)]
+        );
+        assert_eq!(
+            run("reduce .[] as $x (0; . + $x)", json!([1, 2, 3])).unwrap(),
+            vec![json!(6)]
+        );
+        assert_eq!(
+            run("[foreach .[] as $x (0; . + $x)]", json!([1, 2, 3])).unwrap(),
+            vec![json!([1, 3, 6])]
+        );
+        assert!(run("$nope", json!(null)).is_err());
+    }
+
+    #[test]
+    fn test_function_definitions() {
+        assert_eq!(
+            run("def inc(f): f + 1; [.[] | inc(.)]", json!([1, 2])).unwrap(),
+            vec![json!([2, 3])]
+        );
+        assert_eq!(
+            run(
+                "def fac: if . <= 1 then 1 else . * (. - 1 | fac) end; fac",
+                json!(5)
+            )
+            .unwrap(),
+            vec![json!(120)]
+        );
+        assert_eq!(
+            run("def add($a; $b): $a + $b; add(.[0]; .[1])", json!([1, 2])).unwrap(),
+            vec![json!(3)]
+        );
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        assert_eq!(
+            run(
+                "\"\\(.name) is \\(.age)\"",
+                json!({"name": "Amit", "age": 20})
+            )
+            .unwrap(),
+            vec![json!("Amit is 20")]
+        );
+    }
+
+    #[test]
+    fn test_paths_and_assignment() {
+        let input = json!({"a": {"b": 1}, "c": [1, 2, 3]});
+        assert_eq!(
+            run("[path(.a.b, .c[])]", input.clone()).unwrap(),
+            vec![json!([["a", "b"], ["c", 0], ["c", 1], ["c", 2]])]
+        );
+        assert_eq!(
+            run(".a.b |= . + 1", input.clone()).unwrap(),
+            vec![json!({"a": {"b": 2}, "c": [1, 2, 3]})]
+        );
+        assert_eq!(
+            run(".c[] *= 2 | .c", input.clone()).unwrap(),
+            vec![json!([2, 4, 6])]
+        );
+        assert_eq!(run(".d = 1 | .d", input).unwrap(), vec![json!(1)]);
+    }
+
+    #[test]
+    fn test_del() {
+        let input = json!({"fizz": "buzz", "fizzes": [1, 2, 3]});
+        assert_eq!(
+            run("del(.fizzes)", input.clone()).unwrap(),
+            vec![json!({"fizz": "buzz"})]
+        );
+        assert_eq!(
+            run("del(.fizzes[0, 2])", input).unwrap(),
+            vec![json!({"fizz": "buzz", "fizzes": [2]})]
+        );
+    }
+
+    #[test]
+    fn test_evaluation_is_lazy() {
+        use crate::value::json;
+
+        // The error after the first output is never reached
+        let filter = crate::parser::parse("1, error(\"unreachable\")").unwrap();
+        let env = super::Env::root();
+        let first = super::eval(&filter, &env, Rc::new(json!(null))).next();
+        assert_eq!(first, Some(Ok(Rc::new(json!(1)))));
+    }
+}
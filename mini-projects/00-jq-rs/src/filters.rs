@@ -1,129 +1,1064 @@
-use serde_json::Value;
+use std::cmp::Ordering;
+use std::sync::Arc;
 
-pub fn identity_filter(json: &Value) -> Value {
-    json.clone()
+use crate::ast::BinaryOp;
+use crate::error::JqError;
+use crate::value::{Map, Value};
+use regex::{Regex, RegexBuilder};
+
+/// What a missing key or position refers to, so lookups can borrow it
+static NULL: Value = Value::Null;
+
+pub fn identity_filter(json: &Value) -> &Value {
+    json
 }
 
-pub fn object_identifier_index(json: &Value, key: &str) -> Value {
-    json.get(key).cloned().unwrap_or(Value::Null)
+pub fn object_identifier_index<'v>(json: &'v Value, key: &str) -> &'v Value {
+    json.get(key).unwrap_or(&NULL)
 }
 
-pub fn array_index(json: &Value, index: usize) -> Value {
-    json.get(index).cloned().unwrap_or(Value::Null)
+pub fn array_index(json: &Value, index: usize) -> &Value {
+    json.get(index).unwrap_or(&NULL)
 }
 
-pub fn array_slice(json: &Value, start: usize, end: usize) -> Value {
-    if let Some(array) = json.as_array() {
-        Value::Array(array[start..end].to_vec())
-    } else {
-        Value::Null
+/// `.[start:end]` on an array or string. Negative bounds count from the end, a missing
+/// bound means the start or end of the value, and bounds past either end are clamped
+pub fn array_slice(json: &Value, start: Option<i64>, end: Option<i64>) -> Value {
+    match json {
+        Value::Array(array) => {
+            let (start, end) = slice_range(array.len(), start, end);
+            Value::from(array[start..end].to_vec())
+        }
+        Value::String(s) => {
+            let (start, end) = slice_range(s.chars().count(), start, end);
+            Value::String(s.chars().skip(start).take(end - start).collect())
+        }
+        _ => Value::Null,
     }
 }
 
-pub fn pipe(json: &Value, key: &str, index: usize) -> Value {
+/// Resolve slice bounds against a value of length `len`, so that `start <= end <= len`
+fn slice_range(len: usize, start: Option<i64>, end: Option<i64>) -> (usize, usize) {
+    let resolve = |bound: i64| {
+        let position = if bound < 0 { len as i64 + bound } else { bound };
+        position.clamp(0, len as i64) as usize
+    };
+    let end = end.map_or(len, resolve);
+    let start = start.map_or(0, resolve).min(end);
+    (start, end)
+}
+
+/// The bounds of a slice path key such as `{"start": 1, "end": null}`. Fractional bounds
+/// widen the slice, as in jq
+pub fn slice_bounds(key: &Map<String, Value>) -> Result<(Option<i64>, Option<i64>), JqError> {
+    let bound = |name: &str, round: fn(f64) -> f64| match key.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Number(n)) => Ok(Some(round(n.as_f64()) as i64)),
+        Some(_) => Err(JqError::Type(
+            "Start and end indices of an array slice must be numbers".into(),
+        )),
+    };
+    Ok((bound("start", f64::floor)?, bound("end", f64::ceil)?))
+}
+
+pub fn pipe<'v>(json: &'v Value, key: &str, index: usize) -> &'v Value {
     let intermediate = object_identifier_index(json, key);
-    array_index(&intermediate, index)
+    array_index(intermediate, index)
 }
 
 pub fn array_iterator(json: &Value, key: &str) -> Value {
     if let Some(array) = json.as_array() {
         let iterated: Vec<Value> = array
             .iter()
-            .map(|item| object_identifier_index(item, key))
+            .map(|item| object_identifier_index(item, key).clone())
             .collect();
-        Value::Array(iterated)
+        Value::from(iterated)
     } else {
         Value::Null
     }
 }
 
-pub fn add(json: &Value) -> Value {
-    if let Some(array) = json.as_array() {
-        let sum = array
+/// `.[key]` for an object field or array position (negative counts from the end), and
+/// `null` for either on `null`, borrowed from `json`. `None` for any other key
+pub fn index_ref<'v>(json: &'v Value, key: &Value) -> Option<&'v Value> {
+    match (json, key) {
+        (Value::Object(_), Value::String(key)) => Some(object_identifier_index(json, key)),
+        (Value::Array(array), Value::Number(n)) => {
+            let n = n.as_f64().floor();
+            let position = if n < 0.0 { array.len() as f64 + n } else { n };
+            if position < 0.0 || position.is_nan() {
+                Some(&NULL)
+            } else {
+                Some(array_index(json, position as usize))
+            }
+        }
+        (Value::Null, Value::String(_) | Value::Number(_)) => Some(&NULL),
+        _ => None,
+    }
+}
+
+/// `.[key]` for any key: fields, positions, slices and, for an array key, where that
+/// sequence occurs
+pub fn index(json: &Value, key: &Value) -> Result<Value, JqError> {
+    if let Some(found) = index_ref(json, key) {
+        return Ok(found.clone());
+    }
+    match (json, key) {
+        (Value::Array(_) | Value::String(_) | Value::Null, Value::Object(key)) => {
+            let (start, end) = slice_bounds(key)?;
+            Ok(array_slice(json, start, end))
+        }
+        (Value::Array(_), Value::Array(_)) => indices(json, key),
+        (Value::Null, Value::Null) => Ok(Value::Null),
+        _ => Err(JqError::Type(format!(
+            "Cannot index {} with {}",
+            type_name(json),
+            match key {
+                Value::String(s) => format!("\"{}\"", s),
+                other => type_name(other).to_string(),
+            }
+        ))),
+    }
+}
+
+pub fn add(json: &Value) -> Result<Value, JqError> {
+    match json {
+        Value::Array(array) => array
             .iter()
-            .fold(Value::Null, |acc, item| match (acc, item) {
-                (Value::String(mut acc_str), Value::String(item_str)) => {
-                    acc_str.push_str(item_str);
-                    Value::String(acc_str)
-                }
-                (Value::Number(acc_num), Value::Number(item_num)) => {
-                    let acc_f64 = acc_num.as_f64().unwrap_or(0.0);
-                    let item_f64 = item_num.as_f64().unwrap_or(0.0);
-                    Value::Number(serde_json::Number::from_f64(acc_f64 + item_f64).unwrap())
-                }
-                _ => Value::Null,
-            });
-        sum
-    } else {
-        Value::Null
+            .try_fold(Value::Null, |acc, item| binary_op(BinaryOp::Add, acc, item)),
+        Value::Object(obj) => obj
+            .values()
+            .try_fold(Value::Null, |acc, item| binary_op(BinaryOp::Add, acc, item)),
+        Value::Null => Ok(Value::Null),
+        other => Err(JqError::Type(format!(
+            "Cannot iterate over {}",
+            describe(other)
+        ))),
     }
 }
 
-pub fn length(json: &Value) -> Value {
+pub fn length(json: &Value) -> Result<Value, JqError> {
     match json {
-        Value::Array(arr) => Value::Number(arr.len().into()),
-        Value::Object(obj) => Value::Number(obj.len().into()),
-        Value::String(s) => Value::Number(s.len().into()),
-        _ => Value::Null,
+        Value::Array(arr) => Ok(Value::Number(arr.len().into())),
+        Value::Object(obj) => Ok(Value::Number(obj.len().into())),
+        Value::String(s) => Ok(Value::Number(s.chars().count().into())),
+        Value::Number(n) => Ok(number(n.as_f64().abs())),
+        Value::Null => Ok(Value::Number(0.into())),
+        Value::Bool(_) => Err(JqError::Type(format!("{} has no length", describe(json)))),
     }
 }
 
-pub fn del(json: &mut Value, key_or_index: &str) -> Value {
+pub fn del(json: Value, key_or_index: &str) -> Value {
     match json {
-        Value::Object(ref mut obj) => {
-            obj.remove(key_or_index);
-            Value::Object(obj.clone())
+        Value::Object(mut obj) => {
+            Arc::make_mut(&mut obj).shift_remove(key_or_index);
+            Value::Object(obj)
         }
-        Value::Array(ref mut arr) => {
+        Value::Array(mut arr) => {
             if let Ok(index) = key_or_index.parse::<usize>() {
                 if index < arr.len() {
-                    arr.remove(index);
+                    Arc::make_mut(&mut arr).remove(index);
                 }
             }
-            Value::Array(arr.clone())
+            Value::Array(arr)
         }
         _ => Value::Null,
     }
 }
 
+/// Convert the result of a floating point computation back into a number value.
+///
+/// NaN and the infinities are kept as they are, and only become `null` and the largest
+/// finite doubles when printed (see `value`). Integral results are stored as integers so
+/// that `1 + 1` prints as `2` rather than `2.0`.
+pub fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < 9007199254740992.0 {
+        Value::from(n as i64)
+    } else {
+        Value::from(n)
+    }
+}
+
+/// Name of a value's type, as reported by the `type` builtin
+pub fn type_name(json: &Value) -> &'static str {
+    match json {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// A value's type together with a shortened copy of it, for error messages
+pub fn describe(json: &Value) -> String {
+    let text = json.to_string();
+    if text.chars().count() > 11 {
+        let short: String = text.chars().take(10).collect();
+        format!("{} ({}...)", type_name(json), short)
+    } else {
+        format!("{} ({})", type_name(json), text)
+    }
+}
+
+/// jq's notion of truth: everything except `false` and `null`
+pub fn is_truthy(json: &Value) -> bool {
+    !matches!(json, Value::Null | Value::Bool(false))
+}
+
+fn type_rank(json: &Value) -> u8 {
+    match json {
+        Value::Null => 0,
+        Value::Bool(false) => 1,
+        Value::Bool(true) => 2,
+        Value::Number(_) => 3,
+        Value::String(_) => 4,
+        Value::Array(_) => 5,
+        Value::Object(_) => 6,
+    }
+}
+
+/// Total order over JSON values: null < false < true < numbers < strings < arrays <
+/// objects. Objects compare their sorted key sets first, then their values key by key.
+/// As in jq, NaN sorts below every number, itself included, so `nan == nan` is false.
+pub fn compare(lhs: &Value, rhs: &Value) -> Ordering {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(_)) if a.is_nan() => Ordering::Less,
+        (Value::Number(_), Value::Number(b)) if b.is_nan() => Ordering::Greater,
+        (Value::Number(a), Value::Number(b)) => {
+            let a = a.as_f64();
+            let b = b.as_f64();
+            a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+        }
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => {
+            for (x, y) in a.iter().zip(b.iter()) {
+                match compare(x, y) {
+                    Ordering::Equal => continue,
+                    other => return other,
+                }
+            }
+            a.len().cmp(&b.len())
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            let mut a_keys: Vec<&String> = a.keys().collect();
+            let mut b_keys: Vec<&String> = b.keys().collect();
+            a_keys.sort();
+            b_keys.sort();
+            match a_keys.cmp(&b_keys) {
+                Ordering::Equal => {}
+                other => return other,
+            }
+            for key in a_keys {
+                match compare(&a[key], &b[key]) {
+                    Ordering::Equal => continue,
+                    other => return other,
+                }
+            }
+            Ordering::Equal
+        }
+        _ => type_rank(lhs).cmp(&type_rank(rhs)),
+    }
+}
+
+/// Apply an arithmetic or comparison operator to two values
+pub fn binary_op(op: BinaryOp, lhs: Value, rhs: &Value) -> Result<Value, JqError> {
+    match op {
+        BinaryOp::Eq => Ok(Value::Bool(compare(&lhs, rhs) == Ordering::Equal)),
+        BinaryOp::Ne => Ok(Value::Bool(compare(&lhs, rhs) != Ordering::Equal)),
+        BinaryOp::Lt => Ok(Value::Bool(compare(&lhs, rhs) == Ordering::Less)),
+        BinaryOp::Le => Ok(Value::Bool(compare(&lhs, rhs) != Ordering::Greater)),
+        BinaryOp::Gt => Ok(Value::Bool(compare(&lhs, rhs) == Ordering::Greater)),
+        BinaryOp::Ge => Ok(Value::Bool(compare(&lhs, rhs) != Ordering::Less)),
+        BinaryOp::Add => add_values(lhs, rhs),
+        BinaryOp::Sub => subtract_values(lhs, rhs),
+        BinaryOp::Mul => multiply_values(lhs, rhs),
+        BinaryOp::Div => divide_values(lhs, rhs),
+        BinaryOp::Mod => modulo_values(lhs, rhs),
+    }
+}
+
+fn operator_error(lhs: &Value, rhs: &Value, action: &str) -> JqError {
+    JqError::Type(format!(
+        "{} and {} cannot be {}",
+        describe(lhs),
+        describe(rhs),
+        action
+    ))
+}
+
+fn add_values(lhs: Value, rhs: &Value) -> Result<Value, JqError> {
+    match (lhs, rhs) {
+        (Value::Null, rhs) => Ok(rhs.clone()),
+        (lhs, Value::Null) => Ok(lhs),
+        (Value::Number(a), Value::Number(b)) => Ok(number(a.as_f64() + b.as_f64())),
+        (Value::String(mut a), Value::String(b)) => {
+            a.push_str(b);
+            Ok(Value::String(a))
+        }
+        (Value::Array(mut a), Value::Array(b)) => {
+            Arc::make_mut(&mut a).extend(b.iter().cloned());
+            Ok(Value::Array(a))
+        }
+        (Value::Object(mut a), Value::Object(b)) => {
+            Arc::make_mut(&mut a).extend(b.iter().map(|(k, v)| (k.clone(), v.clone())));
+            Ok(Value::Object(a))
+        }
+        (lhs, rhs) => Err(operator_error(&lhs, rhs, "added")),
+    }
+}
+
+fn subtract_values(lhs: Value, rhs: &Value) -> Result<Value, JqError> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => Ok(number(a.as_f64() - b.as_f64())),
+        (Value::Array(a), Value::Array(b)) => Ok(Value::from(
+            a.iter()
+                .filter(|item| !b.iter().any(|other| compare(item, other).is_eq()))
+                .cloned()
+                .collect::<Vec<_>>(),
+        )),
+        (lhs, rhs) => Err(operator_error(&lhs, rhs, "subtracted")),
+    }
+}
+
+fn multiply_values(lhs: Value, rhs: &Value) -> Result<Value, JqError> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => Ok(number(a.as_f64() * b.as_f64())),
+        (Value::String(s), Value::Number(n)) => Ok(repeat_string(&s, n.as_f64())),
+        (Value::Number(n), Value::String(s)) => Ok(repeat_string(s, n.as_f64())),
+        (Value::Object(a), Value::Object(b)) => Ok(Value::Object(deep_merge(a, b))),
+        (lhs, rhs) => Err(operator_error(&lhs, rhs, "multiplied")),
+    }
+}
+
+fn repeat_string(s: &str, times: f64) -> Value {
+    if times <= 0.0 {
+        Value::Null
+    } else {
+        Value::String(s.repeat(times.ceil() as usize))
+    }
+}
+
+fn deep_merge(
+    mut lhs: Arc<Map<String, Value>>,
+    rhs: &Map<String, Value>,
+) -> Arc<Map<String, Value>> {
+    let merging = Arc::make_mut(&mut lhs);
+    for (key, value) in rhs {
+        let merged = match (merging.swap_remove(key), value) {
+            (Some(Value::Object(a)), Value::Object(b)) => Value::Object(deep_merge(a, b)),
+            (_, value) => value.clone(),
+        };
+        merging.insert(key.clone(), merged);
+    }
+    lhs
+}
+
+fn divide_values(lhs: Value, rhs: &Value) -> Result<Value, JqError> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => {
+            let b = b.as_f64();
+            if b == 0.0 {
+                return Err(operator_error(
+                    &Value::Number(a),
+                    rhs,
+                    "divided because the divisor is zero",
+                ));
+            }
+            Ok(number(a.as_f64() / b))
+        }
+        (Value::String(a), Value::String(b)) => Ok(Value::from(if a.is_empty() {
+            Vec::new()
+        } else if b.is_empty() {
+            a.chars().map(|c| Value::String(c.to_string())).collect()
+        } else {
+            a.split(b.as_str())
+                .map(|part| Value::String(part.to_string()))
+                .collect()
+        })),
+        (lhs, rhs) => Err(operator_error(&lhs, rhs, "divided")),
+    }
+}
+
+fn modulo_values(lhs: Value, rhs: &Value) -> Result<Value, JqError> {
+    match (&lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => {
+            let a = a.as_f64() as i64;
+            let b = b.as_f64() as i64;
+            if b == 0 {
+                return Err(operator_error(
+                    &lhs,
+                    rhs,
+                    "divided because the divisor is zero",
+                ));
+            }
+            Ok(Value::from(a.wrapping_rem(b.wrapping_abs())))
+        }
+        _ => Err(operator_error(&lhs, rhs, "divided")),
+    }
+}
+
+/// The value of a number argument, or a type error for anything else
+pub fn number_arg(json: &Value) -> Result<f64, JqError> {
+    match json {
+        Value::Number(n) => Ok(n.as_f64()),
+        other => Err(JqError::Type(format!(
+            "{} number required",
+            describe(other)
+        ))),
+    }
+}
+
+/// Apply a one-argument libm function such as `floor` or `sqrt` to a number
+pub fn math(json: &Value, f: fn(f64) -> f64) -> Result<Value, JqError> {
+    Ok(number(f(number_arg(json)?)))
+}
+
+/// Apply a two-argument libm function such as `pow` or `atan2`
+pub fn math2(a: &Value, b: &Value, f: fn(f64, f64) -> f64) -> Result<Value, JqError> {
+    Ok(number(f(number_arg(a)?, number_arg(b)?)))
+}
+
+/// Apply a three-argument libm function such as `fma`
+pub fn math3(
+    a: &Value,
+    b: &Value,
+    c: &Value,
+    f: fn(f64, f64, f64) -> f64,
+) -> Result<Value, JqError> {
+    Ok(number(f(number_arg(a)?, number_arg(b)?, number_arg(c)?)))
+}
+
+/// `isnan`
+pub fn is_nan(json: &Value) -> Result<Value, JqError> {
+    Ok(Value::Bool(number_arg(json)?.is_nan()))
+}
+
+/// `isinfinite`
+pub fn is_infinite(json: &Value) -> Result<Value, JqError> {
+    Ok(Value::Bool(number_arg(json)?.is_infinite()))
+}
+
+/// `isnormal`
+pub fn is_normal(json: &Value) -> Result<Value, JqError> {
+    Ok(Value::Bool(number_arg(json)?.is_normal()))
+}
+
+/// `tostring`: strings pass through unchanged, everything else is encoded as JSON
+pub fn tostring(json: &Value) -> Value {
+    match json {
+        Value::String(_) => json.clone(),
+        other => Value::String(other.to_string()),
+    }
+}
+
+/// `tojson`
+pub fn tojson(json: &Value) -> Value {
+    Value::String(json.to_string())
+}
+
+/// `fromjson`
+pub fn fromjson(json: &Value) -> Result<Value, JqError> {
+    match json {
+        Value::String(s) => serde_json::from_str(s)
+            .map_err(|e| JqError::Type(format!("{} (while parsing '{}')", e, s))),
+        other => Err(JqError::Type(format!(
+            "{} cannot be parsed as JSON",
+            describe(other)
+        ))),
+    }
+}
+
+/// `tonumber`
+pub fn tonumber(json: &Value) -> Result<Value, JqError> {
+    match json {
+        Value::Number(_) => Ok(json.clone()),
+        Value::String(s) => match serde_json::from_str::<Value>(s.trim()) {
+            Ok(value @ Value::Number(_)) => Ok(value),
+            _ => match s.trim() {
+                "nan" | "NaN" => Ok(number(f64::NAN)),
+                text => text
+                    .parse::<f64>()
+                    .map(number)
+                    .map_err(|_| JqError::Type(format!("Cannot parse '{}' as JSON", s))),
+            },
+        },
+        other => Err(JqError::Type(format!(
+            "{} cannot be parsed as a number",
+            describe(other)
+        ))),
+    }
+}
+
+/// `keys` and `keys_unsorted`
+pub fn keys(json: &Value, sorted: bool) -> Result<Value, JqError> {
+    match json {
+        Value::Object(obj) => {
+            let mut keys: Vec<&String> = obj.keys().collect();
+            if sorted {
+                keys.sort();
+            }
+            Ok(Value::from(
+                keys.into_iter()
+                    .map(|k| Value::String(k.clone()))
+                    .collect::<Vec<_>>(),
+            ))
+        }
+        Value::Array(arr) => Ok(Value::from(
+            (0..arr.len()).map(Value::from).collect::<Vec<_>>(),
+        )),
+        other => Err(JqError::Type(format!("{} has no keys", describe(other)))),
+    }
+}
+
+/// `has(key)`
+pub fn has(json: &Value, key: &Value) -> Result<Value, JqError> {
+    match (json, key) {
+        (Value::Object(obj), Value::String(key)) => Ok(Value::Bool(obj.contains_key(key))),
+        (Value::Array(arr), Value::Number(n)) => {
+            let n = n.as_f64();
+            Ok(Value::Bool(n >= 0.0 && n < arr.len() as f64))
+        }
+        _ => Err(JqError::Type(format!(
+            "Cannot check whether {} has a {} key",
+            type_name(json),
+            type_name(key)
+        ))),
+    }
+}
+
+/// `getpath(path)`
+pub fn getpath(json: &Value, path: &[Value]) -> Result<Value, JqError> {
+    let mut current = json;
+    for (i, key) in path.iter().enumerate() {
+        if current.is_null() {
+            return Ok(Value::Null);
+        }
+        current = match index_ref(current, key) {
+            Some(child) => child,
+            // Slices build a new value, so the rest of the path walks that copy
+            None => return getpath(&index(current, key)?, &path[i + 1..]),
+        };
+    }
+    Ok(current.clone())
+}
+
+/// `setpath(path; value)`, creating intermediate objects and arrays as needed
+pub fn setpath(json: Value, path: &[Value], value: Value) -> Result<Value, JqError> {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Ok(value),
+    };
+    match (json, key) {
+        (Value::Object(mut obj), Value::String(key)) => {
+            // Updated in place, so that the key keeps its position
+            let slot = Arc::make_mut(&mut obj)
+                .entry(key.clone())
+                .or_insert(Value::Null);
+            *slot = setpath(slot.take(), rest, value)?;
+            Ok(Value::Object(obj))
+        }
+        (Value::Null, Value::String(key)) => {
+            let mut obj = Map::new();
+            obj.insert(key.clone(), setpath(Value::Null, rest, value)?);
+            Ok(Value::from(obj))
+        }
+        (json @ (Value::Array(_) | Value::Null), Value::Number(n)) => {
+            let mut arr = match json {
+                Value::Array(arr) => Arc::unwrap_or_clone(arr),
+                _ => Vec::new(),
+            };
+            let n = n.as_f64() as i64;
+            let position = if n < 0 { arr.len() as i64 + n } else { n };
+            if position < 0 {
+                return Err(JqError::Type("Out of bounds negative array index".into()));
+            }
+            let position = position as usize;
+            if position >= arr.len() {
+                arr.resize(position + 1, Value::Null);
+            }
+            let child = std::mem::take(&mut arr[position]);
+            arr[position] = setpath(child, rest, value)?;
+            Ok(Value::from(arr))
+        }
+        (json @ (Value::Array(_) | Value::Null), Value::Object(key)) => {
+            let mut arr = match json {
+                Value::Array(arr) => Arc::unwrap_or_clone(arr),
+                _ => Vec::new(),
+            };
+            let (start, end) = slice_bounds(key)?;
+            let (start, end) = slice_range(arr.len(), start, end);
+            let old = Value::from(arr[start..end].to_vec());
+            match setpath(old, rest, value)? {
+                Value::Array(replacement) => {
+                    arr.splice(start..end, Arc::unwrap_or_clone(replacement));
+                    Ok(Value::from(arr))
+                }
+                _ => Err(JqError::Type(
+                    "A slice of an array can only be assigned another array".into(),
+                )),
+            }
+        }
+        (json, key) => Err(JqError::Type(format!(
+            "Cannot index {} with {}",
+            type_name(&json),
+            type_name(key)
+        ))),
+    }
+}
+
+/// `delpaths(paths)`. Paths are removed longest and last first so that deleting one
+/// array element does not shift the positions of the others
+pub fn delpaths(json: Value, paths: &[Value]) -> Result<Value, JqError> {
+    let mut paths: Vec<&Vec<Value>> = paths
+        .iter()
+        .map(|path| {
+            path.as_array()
+                .ok_or_else(|| JqError::Type("Path must be specified as an array".into()))
+        })
+        .collect::<Result<_, _>>()?;
+    paths.sort_by(|a, b| compare(&Value::from(b.to_vec()), &Value::from(a.to_vec())));
+    paths
+        .into_iter()
+        .try_fold(json, |json, path| delpath(json, path))
+}
+
+fn delpath(json: Value, path: &[Value]) -> Result<Value, JqError> {
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Ok(Value::Null),
+    };
+    match (json, key) {
+        (Value::Null, _) => Ok(Value::Null),
+        (Value::Object(mut obj), Value::String(key)) => {
+            let obj_mut = Arc::make_mut(&mut obj);
+            if rest.is_empty() {
+                obj_mut.shift_remove(key);
+            } else if let Some(child) = obj_mut.get_mut(key) {
+                *child = delpath(std::mem::take(child), rest)?;
+            }
+            Ok(Value::Object(obj))
+        }
+        (Value::Array(arr), Value::Number(n)) => {
+            let mut arr = Arc::unwrap_or_clone(arr);
+            let n = n.as_f64() as i64;
+            let position = if n < 0 { arr.len() as i64 + n } else { n };
+            if position >= 0 && (position as usize) < arr.len() {
+                let position = position as usize;
+                if rest.is_empty() {
+                    arr.remove(position);
+                } else {
+                    arr[position] = delpath(std::mem::take(&mut arr[position]), rest)?;
+                }
+            }
+            Ok(Value::from(arr))
+        }
+        (Value::Array(arr), Value::Object(key)) => {
+            let mut arr = Arc::unwrap_or_clone(arr);
+            let (start, end) = slice_bounds(key)?;
+            let (start, end) = slice_range(arr.len(), start, end);
+            if rest.is_empty() {
+                arr.drain(start..end);
+            } else {
+                let slice = Value::from(arr[start..end].to_vec());
+                if let Value::Array(replacement) = delpath(slice, rest)? {
+                    arr.splice(start..end, Arc::unwrap_or_clone(replacement));
+                }
+            }
+            Ok(Value::from(arr))
+        }
+        (json, key) => Err(JqError::Type(format!(
+            "Cannot delete field at index {} of {}",
+            key,
+            type_name(&json)
+        ))),
+    }
+}
+
+/// `flatten(depth)`: splice nested arrays into their parent, up to `depth` levels deep
+pub fn flatten(json: &Value, depth: &Value) -> Result<Value, JqError> {
+    let depth = number_arg(depth)?;
+    if depth < 0.0 {
+        return Err(JqError::Type("flatten depth must not be negative".into()));
+    }
+    match json {
+        Value::Array(array) => {
+            let mut flat = Vec::new();
+            flatten_into(&mut flat, array, depth);
+            Ok(Value::from(flat))
+        }
+        other => Err(JqError::Type(format!("Cannot flatten {}", describe(other)))),
+    }
+}
+
+fn flatten_into(flat: &mut Vec<Value>, array: &[Value], depth: f64) {
+    for item in array {
+        match item {
+            Value::Array(inner) if depth > 0.0 => flatten_into(flat, inner, depth - 1.0),
+            other => flat.push(other.clone()),
+        }
+    }
+}
+
+/// `transpose`: rows become columns, padding short rows with `null`
+pub fn transpose(json: &Value) -> Result<Value, JqError> {
+    let rows = match json {
+        Value::Array(rows) => rows,
+        other => {
+            return Err(JqError::Type(format!(
+                "Cannot transpose {}",
+                describe(other)
+            )))
+        }
+    };
+    let mut width = 0;
+    for row in rows.iter() {
+        match row {
+            Value::Array(row) => width = width.max(row.len()),
+            other => {
+                return Err(JqError::Type(format!(
+                    "Cannot transpose a row that is {}",
+                    describe(other)
+                )))
+            }
+        }
+    }
+    let columns = (0..width)
+        .map(|i| {
+            Value::from(
+                rows.iter()
+                    .map(|row| array_index(row, i).clone())
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .collect::<Vec<_>>();
+    Ok(Value::from(columns))
+}
+
+/// `indices(i)`: where `i` occurs in `json`. Strings are searched for a substring and
+/// report character offsets; arrays are searched for an element or, when `i` is itself an
+/// array, for a run of elements
+pub fn indices(json: &Value, target: &Value) -> Result<Value, JqError> {
+    let positions: Vec<usize> = match (json, target) {
+        (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+        (Value::String(s), Value::String(needle)) => {
+            if needle.is_empty() {
+                return Ok(Value::Null);
+            }
+            // Overlapping matches count, so "aaa" contains "aa" at 0 and 1
+            s.char_indices()
+                .enumerate()
+                .filter(|(_, (byte, _))| s[*byte..].starts_with(needle.as_str()))
+                .map(|(offset, _)| offset)
+                .collect()
+        }
+        (Value::Array(array), Value::Array(needle)) => {
+            if needle.is_empty() {
+                return Ok(Value::Null);
+            }
+            array
+                .windows(needle.len())
+                .enumerate()
+                .filter(|(_, window)| {
+                    window
+                        .iter()
+                        .zip(needle.iter())
+                        .all(|(a, b)| compare(a, b).is_eq())
+                })
+                .map(|(i, _)| i)
+                .collect()
+        }
+        (Value::Array(array), item) => array
+            .iter()
+            .enumerate()
+            .filter(|(_, element)| compare(element, item).is_eq())
+            .map(|(i, _)| i)
+            .collect(),
+        (json, target) => {
+            return Err(JqError::Type(format!(
+                "Cannot determine the indices of {} in {}",
+                describe(target),
+                describe(json)
+            )))
+        }
+    };
+    Ok(Value::from(
+        positions.into_iter().map(Value::from).collect::<Vec<_>>(),
+    ))
+}
+
+/// `contains(b)`: objects contain `b` when every key of `b` is present with a value that
+/// contains the corresponding value; arrays when every element of `b` is contained in
+/// some element; strings when `b` is a substring. Other values must be equal
+pub fn contains(json: &Value, b: &Value) -> Result<bool, JqError> {
+    if type_name(json) != type_name(b) {
+        return Err(JqError::Type(format!(
+            "{} and {} cannot have their containment checked",
+            describe(json),
+            describe(b)
+        )));
+    }
+    Ok(contains_value(json, b))
+}
+
+/// `contains` below the top level, where values of different types simply do not match
+fn contains_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => b
+            .iter()
+            .all(|(key, b)| a.get(key).is_some_and(|a| contains_value(a, b))),
+        (Value::Array(a), Value::Array(b)) => {
+            b.iter().all(|b| a.iter().any(|a| contains_value(a, b)))
+        }
+        (Value::String(a), Value::String(b)) => a.contains(b.as_str()),
+        (a, b) => compare(a, b).is_eq(),
+    }
+}
+
+/// `split(separator)`: split a string on a literal separator
+pub fn split(json: &Value, separator: &Value) -> Result<Value, JqError> {
+    match (json, separator) {
+        (Value::String(s), Value::String(separator)) => {
+            let parts: Vec<Value> = if s.is_empty() {
+                Vec::new()
+            } else if separator.is_empty() {
+                s.chars().map(|c| Value::String(c.to_string())).collect()
+            } else {
+                s.split(separator.as_str()).map(Value::from).collect()
+            };
+            Ok(Value::from(parts))
+        }
+        _ => Err(JqError::Type(
+            "split input and separator must be strings".into(),
+        )),
+    }
+}
+
+/// `split(re; flags)`: split a string on every match of a regular expression
+pub fn split_regex(json: &Value, re: &Value, flags: &Value) -> Result<Value, JqError> {
+    let s = match json {
+        Value::String(s) => s,
+        other => {
+            return Err(JqError::Type(format!(
+                "{} cannot be matched, as it is not a string",
+                describe(other)
+            )))
+        }
+    };
+    let re = regex(re, flags)?;
+    Ok(Value::from(
+        re.split(s).map(Value::from).collect::<Vec<_>>(),
+    ))
+}
+
+/// Compile `re` with jq's regex flags. `g` is accepted but has no effect, since the
+/// functions that take flags already act on every match
+fn regex(re: &Value, flags: &Value) -> Result<Regex, JqError> {
+    let re = match re {
+        Value::String(re) => re,
+        other => {
+            return Err(JqError::Type(format!(
+                "{} cannot be matched, as it is not a string",
+                describe(other)
+            )))
+        }
+    };
+    let flags = match flags {
+        Value::Null => "",
+        Value::String(flags) => flags.as_str(),
+        other => {
+            return Err(JqError::Type(format!(
+                "{} is not a string",
+                describe(other)
+            )))
+        }
+    };
+    let mut builder = RegexBuilder::new(re);
+    for flag in flags.chars() {
+        match flag {
+            'g' => {}
+            'i' => {
+                builder.case_insensitive(true);
+            }
+            'x' => {
+                builder.ignore_whitespace(true);
+            }
+            's' => {
+                builder.dot_matches_new_line(true);
+            }
+            _ => {
+                return Err(JqError::Type(format!(
+                    "{} is not a valid modifier string",
+                    flags
+                )))
+            }
+        }
+    }
+    builder
+        .build()
+        .map_err(|e| JqError::Type(format!("{} is not a valid regex: {}", re, e)))
+}
+
+/// `bsearch(target)` on a sorted array: the index of `target`, or if it is absent,
+/// `-1 - i` where `i` is the position it would be inserted at
+pub fn bsearch(json: &Value, target: &Value) -> Result<Value, JqError> {
+    match json {
+        Value::Array(array) => Ok(match array.binary_search_by(|item| compare(item, target)) {
+            Ok(i) => Value::from(i),
+            Err(i) => Value::from(-1 - i as i64),
+        }),
+        other => Err(JqError::Type(format!(
+            "{} cannot be searched from",
+            describe(other)
+        ))),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use crate::value::json;
 
     #[test]
     fn test_identity_filter() {
         let input = json!({"key": "value"});
         let output = identity_filter(&input);
-        assert_eq!(output, input);
+        assert_eq!(*output, input);
     }
 
     #[test]
     fn test_object_identifier_index() {
         let input = json!({"key": "value"});
         let output = object_identifier_index(&input, "key");
-        assert_eq!(output, json!("value"));
+        assert_eq!(*output, json!("value"));
     }
 
     #[test]
     fn test_array_index() {
         let input = json!(["value1", "value2"]);
         let output = array_index(&input, 0);
-        assert_eq!(output, json!("value1"));
+        assert_eq!(*output, json!("value1"));
     }
 
     #[test]
     fn test_array_slice() {
         let input = json!(["value1", "value2", "value3"]);
-        let output = array_slice(&input, 0, 2);
+        let output = array_slice(&input, Some(0), Some(2));
         assert_eq!(output, json!(["value1", "value2"]));
     }
 
+    #[test]
+    fn test_array_slice_bounds() {
+        let input = json!([0, 1, 2, 3, 4]);
+        assert_eq!(array_slice(&input, Some(2), None), json!([2, 3, 4]));
+        assert_eq!(array_slice(&input, Some(-3), None), json!([2, 3, 4]));
+        assert_eq!(array_slice(&input, None, Some(-1)), json!([0, 1, 2, 3]));
+        assert_eq!(array_slice(&input, Some(1), Some(100)), json!([1, 2, 3, 4]));
+        assert_eq!(array_slice(&input, Some(4), Some(2)), json!([]));
+        assert_eq!(array_slice(&input, Some(-100), Some(1)), json!([0]));
+        assert_eq!(array_slice(&json!("héllo"), Some(1), Some(3)), json!("él"));
+    }
+
+    #[test]
+    fn test_flatten_and_transpose() {
+        let nested = json!([1, [2, [3, [4]]]]);
+        assert_eq!(
+            flatten(&nested, &json!(1)).unwrap(),
+            json!([1, 2, [3, [4]]])
+        );
+        assert_eq!(flatten(&nested, &json!(1e9)).unwrap(), json!([1, 2, 3, 4]));
+        assert!(flatten(&nested, &json!(-1)).is_err());
+        assert_eq!(
+            transpose(&json!([[1, 2], [3]])).unwrap(),
+            json!([[1, 3], [2, null]])
+        );
+    }
+
+    #[test]
+    fn test_indices() {
+        assert_eq!(
+            indices(&json!("a,b, cd, efg"), &json!(", ")).unwrap(),
+            json!([3, 7])
+        );
+        assert_eq!(indices(&json!("aaa"), &json!("aa")).unwrap(), json!([0, 1]));
+        assert_eq!(indices(&json!("ßa"), &json!("a")).unwrap(), json!([1]));
+        assert_eq!(
+            indices(&json!([0, 1, 2, 1, 3, 1, 2]), &json!([1, 2])).unwrap(),
+            json!([1, 5])
+        );
+        assert_eq!(
+            indices(&json!([0, 1, 1]), &json!(1)).unwrap(),
+            json!([1, 2])
+        );
+        // 1 and 1.0 are the same number
+        assert_eq!(
+            indices(&json!([1.0, 2, 1]), &json!(1)).unwrap(),
+            json!([0, 2])
+        );
+        assert_eq!(
+            indices(&json!([0, 1, 2.0]), &json!([1.0, 2])).unwrap(),
+            json!([1])
+        );
+    }
+
+    #[test]
+    fn test_contains() {
+        let a = json!({"foo": 12, "bar": [1, 2, {"barp": 12, "blip": 13}]});
+        assert!(contains(&a, &json!({"foo": 12, "bar": [{"barp": 12}]})).unwrap());
+        assert!(!contains(&a, &json!({"foo": 12, "bar": [{"barp": 15}]})).unwrap());
+        assert!(contains(&json!(["foobar", "baz"]), &json!(["baz", "bar"])).unwrap());
+        assert!(contains(&json!("foobar"), &json!("bar")).unwrap());
+        assert!(contains(&json!(1), &json!("1")).is_err());
+        assert!(contains(&json!([1.0]), &json!([1])).unwrap());
+        assert!(contains(&json!({"a": 1.0}), &json!({"a": 1})).unwrap());
+        assert!(contains(&json!(2), &json!(2.0)).unwrap());
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(
+            split(&json!("a, b,c"), &json!(", ")).unwrap(),
+            json!(["a", "b,c"])
+        );
+        assert_eq!(split(&json!(""), &json!(",")).unwrap(), json!([]));
+        assert_eq!(
+            split_regex(&json!("a1b22Cd"), &json!("[0-9]+|c"), &json!("i")).unwrap(),
+            json!(["a", "b", "", "d"])
+        );
+        assert!(split_regex(&json!("a"), &json!("("), &json!(null)).is_err());
+    }
+
+    #[test]
+    fn test_bsearch() {
+        let sorted = json!([1, 2, 3]);
+        assert_eq!(bsearch(&sorted, &json!(2)).unwrap(), json!(1));
+        assert_eq!(bsearch(&sorted, &json!(0)).unwrap(), json!(-1));
+        assert_eq!(bsearch(&sorted, &json!(4)).unwrap(), json!(-4));
+    }
+
+    #[test]
+    fn test_slice_paths() {
+        let slice = json!({"start": 1, "end": null});
+        assert_eq!(
+            getpath(&json!([1, 2, 3]), std::slice::from_ref(&slice)).unwrap(),
+            json!([2, 3])
+        );
+        assert_eq!(
+            setpath(json!([1, 2, 3]), std::slice::from_ref(&slice), json!(["x"])).unwrap(),
+            json!([1, "x"])
+        );
+        assert_eq!(
+            delpaths(json!([1, 2, 3]), &[json!([slice])]).unwrap(),
+            json!([1])
+        );
+    }
+
     #[test]
     fn test_pipe() {
         let input = json!({"key": ["value1", "value2"]});
         let output = pipe(&input, "key", 1);
-        assert_eq!(output, json!("value2"));
+        assert_eq!(*output, json!("value2"));
     }
 
     #[test]
@@ -136,21 +1071,99 @@ mod tests {
     #[test]
     fn test_add() {
         let input = json!(["one", "two", "three"]);
-        let output = add(&input);
+        let output = add(&input).unwrap();
         assert_eq!(output, json!("onetwothree"));
     }
 
+    #[test]
+    fn test_add_numbers() {
+        assert_eq!(add(&json!([1, 2, 3.5])).unwrap(), json!(6.5));
+        assert_eq!(add(&json!([])).unwrap(), json!(null));
+        assert!(add(&json!([1, "a"])).is_err());
+    }
+
+    #[test]
+    fn test_add_overflow_does_not_panic() {
+        let output = add(&json!([1.7976931348623157e308, 1.7976931348623157e308])).unwrap();
+        assert_eq!(output, Value::from(f64::INFINITY));
+        assert_eq!(output.to_string(), json!(f64::MAX).to_string());
+    }
+
     #[test]
     fn test_length() {
         let input = json!(["one", "two", "three"]);
-        let output = length(&input);
+        let output = length(&input).unwrap();
         assert_eq!(output, json!(3));
     }
 
     #[test]
     fn test_del() {
-        let mut input = json!({"key": "value", "key2": "value2"});
-        let output = del(&mut input, "key");
+        let input = json!({"key": "value", "key2": "value2"});
+        let output = del(input, "key");
         assert_eq!(output, json!({"key2": "value2"}));
     }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number(2.0), json!(2));
+        assert_eq!(number(2.5), json!(2.5));
+        assert!(matches!(number(f64::NAN), Value::Number(n) if n.is_nan()));
+        assert_eq!(number(f64::INFINITY), Value::from(f64::INFINITY));
+        assert_eq!(number(f64::NEG_INFINITY), Value::from(f64::NEG_INFINITY));
+    }
+
+    #[test]
+    fn test_math() {
+        assert_eq!(math(&json!(3.7), libm::floor).unwrap(), json!(3));
+        assert_eq!(math(&json!(16), libm::sqrt).unwrap(), json!(4));
+        assert_eq!(
+            is_nan(&math(&json!(-1), libm::sqrt).unwrap()),
+            Ok(json!(true))
+        );
+        assert_eq!(
+            math2(&json!(2), &json!(10), libm::pow).unwrap(),
+            json!(1024)
+        );
+        assert!(math(&json!("1"), libm::floor).is_err());
+    }
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(tostring(&json!([1, "a"])), json!("[1,\"a\"]"));
+        assert_eq!(tostring(&json!("a")), json!("a"));
+        assert_eq!(tonumber(&json!("1.5")).unwrap(), json!(1.5));
+        assert!(tonumber(&json!("abc")).is_err());
+        assert_eq!(fromjson(&json!("{\"a\":1}")).unwrap(), json!({"a": 1}));
+        assert_eq!(tojson(&json!({"a": [1]})), json!("{\"a\":[1]}"));
+    }
+
+    #[test]
+    fn test_compare_orders_types() {
+        let ordered = [
+            json!(null),
+            json!(false),
+            json!(true),
+            json!(-1),
+            json!(1.5),
+            json!("a"),
+            json!([1]),
+            json!({"a": 1}),
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(compare(&pair[0], &pair[1]), Ordering::Less);
+        }
+    }
+
+    #[test]
+    fn test_setpath_and_delpaths() {
+        let output = setpath(json!(null), &[json!("a"), json!(1)], json!(true)).unwrap();
+        assert_eq!(output, json!({"a": [null, true]}));
+        let output = setpath(json!({"a": 1, "b": 2}), &[json!("a")], json!(3)).unwrap();
+        assert_eq!(
+            output.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        let output = delpaths(json!([1, 2, 3]), &[json!([0]), json!([2])]).unwrap();
+        assert_eq!(output, json!([2]));
+    }
 }
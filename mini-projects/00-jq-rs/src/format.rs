@@ -1,6 +1,4 @@
-use colored::*;
-use regex::Regex;
-use serde_json::{Map, Value};
+use crate::value::{Map, Value};
 use std::env;
 
 pub fn sort_keys(json: &Value) -> Value {
@@ -12,59 +10,137 @@ pub fn sort_keys(json: &Value) -> Value {
             for key in keys {
                 sorted_map.insert(key.clone(), sort_keys(&map[key]));
             }
-            Value::Object(sorted_map)
+            Value::from(sorted_map)
         }
         Value::Array(arr) => {
             let sorted_arr: Vec<Value> = arr.iter().map(sort_keys).collect();
-            Value::Array(sorted_arr)
+            Value::from(sorted_arr)
         }
         _ => json.clone(),
     }
 }
 
-pub fn pretty_print(json: &Value, indent: u8, color_output: bool) -> String {
-    let mut json_str = serde_json::to_string_pretty(json).unwrap();
-    if color_output {
-        json_str = apply_colors(&json_str);
+/// Default colors for null, false, true, numbers, strings, arrays, objects and object keys
+const DEFAULT_COLORS: [&str; 8] = [
+    "0;90", "0;37", "0;37", "0;37", "0;32", "1;37", "1;37", "1;34",
+];
+
+const NULL: usize = 0;
+const FALSE: usize = 1;
+const TRUE: usize = 2;
+const NUMBER: usize = 3;
+const STRING: usize = 4;
+const ARRAY: usize = 5;
+const OBJECT: usize = 6;
+const KEY: usize = 7;
+
+/// Read `JQ_COLORS`, falling back to the defaults for any entries it leaves out
+fn colors() -> Vec<String> {
+    let custom = env::var("JQ_COLORS").unwrap_or_default();
+    let mut custom = custom.split(':').filter(|c| !c.is_empty());
+    DEFAULT_COLORS
+        .iter()
+        .map(|default| custom.next().unwrap_or(default).to_string())
+        .collect()
+}
+
+struct Printer {
+    /// Spaces per level of nesting, or `None` to print everything on one line
+    indent: Option<usize>,
+    colors: Option<Vec<String>>,
+}
+
+impl Printer {
+    fn paint(&self, out: &mut String, kind: usize, text: &str) {
+        match &self.colors {
+            Some(colors) => {
+                out.push_str(&format!("\x1b[{}m{}\x1b[0m", colors[kind], text));
+            }
+            None => out.push_str(text),
+        }
+    }
+
+    fn newline(&self, out: &mut String, depth: usize) {
+        if let Some(indent) = self.indent {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent * depth));
+        }
+    }
+
+    fn write(&self, out: &mut String, json: &Value, depth: usize) {
+        match json {
+            Value::Null => self.paint(out, NULL, "null"),
+            Value::Bool(false) => self.paint(out, FALSE, "false"),
+            Value::Bool(true) => self.paint(out, TRUE, "true"),
+            Value::Number(n) => self.paint(out, NUMBER, &n.to_string()),
+            Value::String(_) => self.paint(out, STRING, &json.to_string()),
+            Value::Array(arr) if arr.is_empty() => self.paint(out, ARRAY, "[]"),
+            Value::Array(arr) => {
+                self.paint(out, ARRAY, "[");
+                for (i, item) in arr.iter().enumerate() {
+                    if i > 0 {
+                        self.paint(out, ARRAY, ",");
+                    }
+                    self.newline(out, depth + 1);
+                    self.write(out, item, depth + 1);
+                }
+                self.newline(out, depth);
+                self.paint(out, ARRAY, "]");
+            }
+            Value::Object(obj) if obj.is_empty() => self.paint(out, OBJECT, "{}"),
+            Value::Object(obj) => {
+                self.paint(out, OBJECT, "{");
+                for (i, (key, value)) in obj.iter().enumerate() {
+                    if i > 0 {
+                        self.paint(out, OBJECT, ",");
+                    }
+                    self.newline(out, depth + 1);
+                    self.paint(out, KEY, &Value::String(key.clone()).to_string());
+                    self.paint(out, OBJECT, ":");
+                    if self.indent.is_some() {
+                        out.push(' ');
+                    }
+                    self.write(out, value, depth + 1);
+                }
+                self.newline(out, depth);
+                self.paint(out, OBJECT, "}");
+            }
+        }
+    }
+
+    fn print(&self, json: &Value) -> String {
+        let mut out = String::new();
+        self.write(&mut out, json, 0);
+        out
     }
-    json_str.replace("\n", &format!("\n{}", " ".repeat(indent as usize)))
 }
 
-pub fn compact_output(json: &Value) -> String {
-    serde_json::to_string(json).unwrap()
+/// Print with `indent` spaces per level. An indent of 0 prints everything on one line,
+/// like jq does
+pub fn pretty_print(json: &Value, indent: u8, color_output: bool) -> String {
+    let printer = Printer {
+        indent: Some(indent as usize).filter(|&indent| indent > 0),
+        colors: color_output.then(colors),
+    };
+    printer.print(json)
 }
 
-fn apply_colors(json_str: &str) -> String {
-    let jq_colors = env::var("JQ_COLORS")
-        .unwrap_or_else(|_| "0;90:0;37:0;37:0;37:0;32:1;37:1;37:1;34".to_string());
-    let colors: Vec<&str> = jq_colors.split(':').collect();
-    let re = Regex::new(r"\d+").unwrap();
-
-    let json_str = json_str
-        .replace("null", &"null".color(colors[0]).to_string())
-        .replace("false", &"false".color(colors[1]).to_string())
-        .replace("true", &"true".color(colors[2]).to_string())
-        .replace("\"", &"\"".color(colors[4]).to_string())
-        .replace("[", &"[".color(colors[5]).to_string())
-        .replace("]", &"]".color(colors[5]).to_string())
-        .replace("{", &"{".color(colors[6]).to_string())
-        .replace("}", &"}".color(colors[6]).to_string())
-        .replace(":", &":".color(colors[7]).to_string());
-
-    re.replace_all(&json_str, |caps: &regex::Captures| {
-        caps[0].color(colors[3]).to_string()
-    })
-    .to_string()
+pub fn compact_output(json: &Value) -> String {
+    let printer = Printer {
+        indent: None,
+        colors: None,
+    };
+    printer.print(json)
 }
 
 pub fn monochrome_print(json: &Value) -> String {
-    serde_json::to_string_pretty(json).unwrap()
+    pretty_print(json, 2, false)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use crate::value::json;
 
     #[test]
     fn test_sort_keys() {
@@ -92,7 +168,7 @@ mod tests {
         env::set_var("JQ_COLORS", "0;90:0;37:0;37:0;37:0;32:1;37:1;37:1;34");
         let input = json!({"key": "value"});
         let output = pretty_print(&input, 2, true);
-        let expected_output = "{\n  \"key\": \"\u{1b}[0;32mvalue\u{1b}[0m\"\n}";
+        let expected_output = "\u{1b}[1;37m{\u{1b}[0m\n  \u{1b}[1;34m\"key\"\u{1b}[0m\u{1b}[1;37m:\u{1b}[0m \u{1b}[0;32m\"value\"\u{1b}[0m\n\u{1b}[1;37m}\u{1b}[0m";
         assert_eq!(output, expected_output);
     }
 
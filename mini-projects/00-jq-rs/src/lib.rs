@@ -1,2 +1,39 @@
+pub mod ast;
+pub mod builtins;
+pub mod codec;
+pub mod conformance;
+pub mod diff;
+pub mod error;
+pub mod eval;
 pub mod filters;
 pub mod format;
+pub mod parallel;
+pub mod parser;
+pub mod query;
+pub mod schema;
+pub mod value;
+
+pub use error::JqError;
+
+use serde_json::Value;
+use std::rc::Rc;
+
+/// Parse `program` and run it against `input`, collecting every output as JSON, so
+/// NaN comes back as `null` and the infinities as the largest finite doubles
+pub fn run(program: &str, input: Value) -> Result<Vec<Value>, JqError> {
+    let filter = parser::parse(program)?;
+    let env = eval::Env::root();
+    let outputs = eval::eval(&filter, &env, Rc::new(input.into()))
+        .map(|output| output.map(|value| Rc::unwrap_or_clone(value).into()))
+        .collect();
+    outputs
+}
+
+/// Like `run`, for a document that is already a `value::Value`, so nothing is converted
+/// on the way in or out. The outputs may share parts of `input`
+pub fn run_value(program: &str, input: Rc<value::Value>) -> Result<Vec<eval::Shared>, JqError> {
+    let filter = parser::parse(program)?;
+    let env = eval::Env::root();
+    let outputs = eval::eval(&filter, &env, input).collect();
+    outputs
+}
@@ -1,28 +1,141 @@
-mod filters;
-mod format;
-
-use clap::{App, Arg};
-use serde_json::Value;
+use clap::{App, Arg, ArgMatches};
+use jq_rs::ast::Filter;
+use jq_rs::codec::{Encoder, Format};
+use jq_rs::eval::{eval, Env, Inputs};
+use jq_rs::value::{Map, Value};
+use jq_rs::{diff, format, parallel, parser, query, schema, JqError};
+use serde_json::Deserializer;
+use std::cell::RefCell;
 use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
 use std::process;
+use std::rc::Rc;
+use tempfile::NamedTempFile;
+
+mod repl;
 
 fn main() {
     let matches = App::new("JQ Command Line Interface")
         .version("1.0")
         .author("Your Name <your.email@example.com>")
         .about("Processes JSON with various filters and formatting options")
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            App::new("diff")
+                .about("Show how the second document differs from the first; exits 1 if they differ")
+                .arg(Arg::new("before").help("The original document").required(true).index(1))
+                .arg(Arg::new("after").help("The changed document").required(true).index(2))
+                .arg(
+                    Arg::new("json-patch")
+                        .long("json-patch")
+                        .help("Print the changes as an RFC 6902 JSON Patch")
+                        .conflicts_with("merge-patch"),
+                )
+                .arg(
+                    Arg::new("merge-patch")
+                        .long("merge-patch")
+                        .help("Print the changes as an RFC 7386 merge patch"),
+                )
+                .arg(
+                    Arg::new("monochrome-output")
+                        .long("monochrome-output")
+                        .help("Do not color the changes"),
+                ),
+        )
+        .subcommand(
+            App::new("patch")
+                .about("Apply a JSON Patch (an array of operations) or a merge patch (an object)")
+                .arg(Arg::new("document").help("The document to patch").required(true).index(1))
+                .arg(Arg::new("patch").help("The patch to apply").required(true).index(2))
+                .arg(
+                    Arg::new("compact-output")
+                        .long("compact-output")
+                        .help("Enable compact output"),
+                ),
+        )
         .arg(
             Arg::new("filter-string")
-                .help("Filter string for JQ")
+                .help("Filter string for JQ (with --from-file or --interactive, the JSON file instead)")
                 .required(true)
                 .index(1),
         )
         .arg(
             Arg::new("path-to-json")
-                .help("Path to the JSON file")
-                .required(true)
+                .help("Path to the JSON file, or several files read one after another")
+                .required_unless_present_any(["from-file", "interactive"])
+                .multiple_values(true)
                 .index(2),
         )
+        .arg(
+            Arg::new("from-file")
+                .long("from-file")
+                .short('f')
+                .help("Read the filter from a file, which may contain # comments")
+                .takes_value(true)
+                .value_name("file"),
+        )
+        .arg(
+            Arg::new("jsonpath")
+                .long("jsonpath")
+                .help("Read the filter as a JSONPath query such as $.store.book[*].author")
+                .takes_value(false)
+                .conflicts_with("interactive"),
+        )
+        .arg(
+            Arg::new("pointer")
+                .long("pointer")
+                .help("Read the filter as a JSON Pointer such as /store/book/0")
+                .takes_value(false)
+                .conflicts_with_all(&["jsonpath", "interactive"]),
+        )
+        .arg(
+            Arg::new("arg")
+                .long("arg")
+                .help("Bind $name to the string value")
+                .number_of_values(2)
+                .value_names(&["name", "value"])
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("argjson")
+                .long("argjson")
+                .help("Bind $name to the JSON text")
+                .number_of_values(2)
+                .value_names(&["name", "text"])
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("slurpfile")
+                .long("slurpfile")
+                .help("Bind $name to an array of the JSON values in the file")
+                .number_of_values(2)
+                .value_names(&["name", "file"])
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("rawfile")
+                .long("rawfile")
+                .help("Bind $name to the contents of the file as a string")
+                .number_of_values(2)
+                .value_names(&["name", "file"])
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("args")
+                .long("args")
+                .help("Remaining arguments are strings, available in $ARGS.positional")
+                .multiple_values(true)
+                .min_values(0),
+        )
+        .arg(
+            Arg::new("jsonargs")
+                .long("jsonargs")
+                .help("Remaining arguments are JSON texts, available in $ARGS.positional")
+                .multiple_values(true)
+                .min_values(0),
+        )
         .arg(
             Arg::new("color-output")
                 .long("color-output")
@@ -58,12 +171,96 @@ fn main() {
                 .help("Enable compact output")
                 .takes_value(false),
         )
+        .arg(
+            Arg::new("null-input")
+                .long("null-input")
+                .short('n')
+                .help("Run the filter once with null as its input; read the file with input/inputs")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("interactive")
+                .long("interactive")
+                .short('i')
+                .help("Load the JSON file once and read filters from a prompt")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("in-place")
+                .long("in-place")
+                .help("Replace each file with the filter's output for each of its documents, written atomically")
+                .takes_value(false)
+                .conflicts_with_all(&["null-input", "interactive"]),
+        )
+        .arg(
+            Arg::new("jobs")
+                .long("jobs")
+                .help("Filter newline-delimited JSON records on this many threads, keeping their order")
+                .takes_value(true)
+                .value_name("N")
+                .conflicts_with_all(&["null-input", "interactive", "in-place"])
+                .validator(|v| match v.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(()),
+                    _ => Err(String::from("Jobs must be a positive number")),
+                }),
+        )
+        .arg(
+            Arg::new("input-format")
+                .long("input-format")
+                .help("Format of the input files (default: from the file extension, else json)")
+                .takes_value(true)
+                .possible_values(["json", "yaml", "toml", "csv"]),
+        )
+        .arg(
+            Arg::new("output-format")
+                .long("output-format")
+                .help("Format to write the results in")
+                .takes_value(true)
+                .default_value("json")
+                .possible_values(["json", "yaml", "toml", "csv"]),
+        )
+        .subcommand(
+            App::new("schema")
+                .about("Infer a JSON Schema from sample data, or check data against one")
+                .subcommand_required(true)
+                .subcommand(
+                    App::new("infer")
+                        .about("Print a schema that all the sample files satisfy")
+                        .arg(
+                            Arg::new("samples")
+                                .help("Sample documents, whose shapes are merged")
+                                .required(true)
+                                .multiple_values(true),
+                        )
+                        .arg(
+                            Arg::new("strict")
+                                .long("strict")
+                                .help("Reject object properties that no sample has"),
+                        ),
+                )
+                .subcommand(
+                    App::new("validate")
+                        .about("Print every place where the data breaks the schema; exits 1 if any")
+                        .arg(Arg::new("schema").help("The JSON Schema").required(true).index(1))
+                        .arg(Arg::new("data").help("The document to check").required(true).index(2)),
+                ),
+        )
         .get_matches();
 
+    match matches.subcommand() {
+        Some(("diff", matches)) => return diff_command(matches),
+        Some(("patch", matches)) => return patch_command(matches),
+        Some(("schema", matches)) => return schema_command(matches),
+        _ => {}
+    }
+
     let color_output = matches.is_present("color-output");
     let monochrome_output = matches.is_present("monochrome-output");
     let compact_output = matches.is_present("compact-output");
-    let _sort_keys = matches.is_present("sort-keys");
+    let sort_keys = matches.is_present("sort-keys");
+    let null_input = matches.is_present("null-input");
+    let input_format: Option<Format> = matches.value_of("input-format").map(|f| f.parse().unwrap());
+    let output_format: Format = matches.value_of("output-format").unwrap().parse().unwrap();
     let indent = matches.value_of("indent").unwrap().parse::<u8>().unwrap();
 
     // Check for conflicting arguments
@@ -72,60 +269,460 @@ fn main() {
         process::exit(1);
     }
 
-    let filter_string = matches.value_of("filter-string").unwrap();
-    let path_to_json = matches.value_of("path-to-json").unwrap();
+    let mut positional: Vec<&str> = matches.values_of("filter-string").unwrap().collect();
+    positional.extend(matches.values_of("path-to-json").into_iter().flatten());
+    let interactive = matches.is_present("interactive");
+    let program = match matches.value_of("from-file") {
+        Some(path) => read_file(path),
+        // The REPL reads its filters from the prompt
+        None if interactive => String::new(),
+        None => positional.remove(0).to_string(),
+    };
+    if positional.is_empty() {
+        fail(2, "no JSON file given");
+    }
+
+    let texts: Vec<String> = positional.iter().map(|path| read_file(path)).collect();
+    // Variables from the command line, which the REPL binds again for every filter
+    let mut globals: Vec<(String, Value)> = Vec::new();
+    let mut named = Map::new();
+    let mut bind = |name: &str, value: Value| {
+        globals.push((name.to_string(), value.clone()));
+        named.insert(name.to_string(), value);
+    };
+    for (name, value) in pairs(&matches, "arg") {
+        bind(name, Value::from(value));
+    }
+    for (name, text) in pairs(&matches, "argjson") {
+        match serde_json::from_str(text) {
+            Ok(value) => bind(name, value),
+            Err(e) => fail(2, &format!("invalid JSON text passed to --argjson: {}", e)),
+        }
+    }
+    for (name, path) in pairs(&matches, "slurpfile") {
+        let values = parse_all(path, &read_file(path));
+        bind(name, Value::from(values));
+    }
+    for (name, path) in pairs(&matches, "rawfile") {
+        bind(name, Value::from(read_file(path)));
+    }
+    let mut positional_args: Vec<Value> = matches
+        .values_of("args")
+        .into_iter()
+        .flatten()
+        .map(Value::from)
+        .collect();
+    for text in matches.values_of("jsonargs").into_iter().flatten() {
+        match serde_json::from_str(text) {
+            Ok(value) => positional_args.push(value),
+            Err(e) => fail(2, &format!("invalid JSON text passed to --jsonargs: {}", e)),
+        }
+    }
+    let mut args = Map::new();
+    args.insert("positional".into(), Value::from(positional_args));
+    args.insert("named".into(), Value::from(named));
+    globals.push(("ARGS".to_string(), Value::from(args)));
 
-    let json_data = fs::read_to_string(path_to_json).expect("Unable to read file");
-    let parsed_json: Value = serde_json::from_str(&json_data).expect("Unable to parse JSON");
+    let mut output = Output {
+        sort_keys,
+        compact: compact_output,
+        monochrome: monochrome_output,
+        indent,
+        color: color_output,
+        format: output_format,
+        encoder: Encoder::new(output_format),
+    };
 
-    // Split the filter string by the pipe operator and apply each filter sequentially
-    let filters: Vec<&str> = filter_string.split('|').map(|s| s.trim()).collect();
-    let mut result = parsed_json;
+    // The files may hold any number of values. They are decoded lazily so that
+    // `input` and `inputs` can consume them as the filter runs
+    let values = positional.iter().zip(&texts).flat_map(|(path, text)| {
+        input_format
+            .unwrap_or_else(|| Format::from_path(path))
+            .decode(text)
+    });
+    let inputs: Inputs = Rc::new(RefCell::new(values));
 
-    for filter in filters {
-        result = match filter {
-            "." => filters::identity_filter(&result),
-            "add" => filters::add(&result),
-            "length" => filters::length(&result),
-            _ if filter.starts_with("del(") && filter.ends_with(")") => {
-                let key_or_index = &filter[4..filter.len() - 1];
-                filters::del(&mut result, key_or_index)
-            }
-            _ if filter.starts_with("index(") && filter.ends_with(")") => {
-                let index: usize = filter[6..filter.len() - 1].parse().expect("Invalid index");
-                filters::array_index(&result, index)
-            }
-            _ if filter.starts_with("slice(") && filter.ends_with(")") => {
-                let params: Vec<&str> = filter[6..filter.len() - 1].split(',').collect();
-                let start: usize = params[0].parse().expect("Invalid start index");
-                let end: usize = params[1].parse().expect("Invalid end index");
-                filters::array_slice(&result, start, end)
-            }
-            _ if filter.starts_with("pipe(") && filter.ends_with(")") => {
-                let params: Vec<&str> = filter[5..filter.len() - 1].split(',').collect();
-                let key = params[0];
-                let index: usize = params[1].parse().expect("Invalid index");
-                filters::pipe(&result, key, index)
+    if interactive {
+        let document = inputs
+            .borrow_mut()
+            .next()
+            .unwrap_or_else(|| Ok(Value::Null));
+        match document {
+            Ok(document) => repl::run(document, &globals, &mut output),
+            Err(e) => fail(2, &e.to_string()),
+        }
+        return;
+    }
+
+    let filter = if matches.is_present("jsonpath") {
+        query::jsonpath(program.trim())
+    } else if matches.is_present("pointer") {
+        query::pointer(program.trim_end_matches(['\r', '\n']))
+    } else {
+        parser::parse(&program)
+    };
+    let filter = match filter {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("jq: error: {}", e);
+            process::exit(3);
+        }
+    };
+    let jobs: usize = matches.value_of("jobs").map_or(1, |n| n.parse().unwrap());
+    if jobs > 1 {
+        let json_only = positional
+            .iter()
+            .all(|path| input_format.unwrap_or_else(|| Format::from_path(path)) == Format::Json);
+        if !json_only {
+            fail(2, "--jobs only reads newline-delimited JSON");
+        }
+        if parallel::reads_inputs(&filter) {
+            fail(2, "--jobs cannot run filters that call input or inputs");
+        }
+        let lines = texts.iter().flat_map(|text| text.lines());
+        let mut stdout = io::stdout().lock();
+        let started = parallel::run_lines(&filter, &globals, lines, jobs, |result| {
+            match result.and_then(|result| output.render(result)) {
+                Ok(text) => print_result(&mut stdout, &text),
+                Err(JqError::Input(e)) => {
+                    fail(2, &format!("{}; --jobs reads one JSON value per line", e))
+                }
+                Err(e) => fail(5, &e.to_string()),
             }
-            _ if filter.starts_with("iterator(") && filter.ends_with(")") => {
-                let key = &filter[9..filter.len() - 1];
-                filters::array_iterator(&result, key)
+        });
+        if let Err(e) = started {
+            fail(2, &e.to_string());
+        }
+        return;
+    }
+
+    let globals = globals.iter().fold(Env::root(), |env, (name, value)| {
+        env.bind_var(name.clone(), value.clone())
+    });
+    if matches.is_present("in-place") {
+        // Files keep their own format unless another one is asked for
+        let output_format = (matches.occurrences_of("output-format") > 0).then_some(output_format);
+        let files: Vec<(&str, Format)> = positional
+            .iter()
+            .map(|path| {
+                (
+                    *path,
+                    input_format.unwrap_or_else(|| Format::from_path(path)),
+                )
+            })
+            .collect();
+        edit_in_place(&filter, &globals, &files, &texts, output_format, &output);
+        return;
+    }
+    let env = globals.with_inputs(inputs.clone());
+
+    let mut stdout = io::stdout().lock();
+    let mut run = |input: Value| {
+        for result in eval(&filter, &env, Rc::new(input)) {
+            let text = result.and_then(|result| output.render(Rc::unwrap_or_clone(result)));
+            match text {
+                Ok(text) => print_result(&mut stdout, &text),
+                Err(e) => fail(5, &e.to_string()),
             }
-            _ => {
-                eprintln!("Unsupported filter: {}", filter);
-                process::exit(1);
+        }
+    };
+
+    if null_input {
+        run(Value::Null);
+        return;
+    }
+    loop {
+        // Release the borrow before running, as the filter may read more inputs itself
+        let next = inputs.borrow_mut().next();
+        match next {
+            Some(Ok(input)) => run(input),
+            Some(Err(e)) => {
+                eprintln!("jq: error: {}", e);
+                process::exit(2);
             }
+            None => break,
+        }
+    }
+}
+
+/// How results are printed, from the formatting options
+struct Output {
+    sort_keys: bool,
+    compact: bool,
+    monochrome: bool,
+    indent: u8,
+    color: bool,
+    format: Format,
+    encoder: Encoder,
+}
+
+impl Output {
+    /// The text to print for one result
+    fn render(&mut self, result: Value) -> Result<String, JqError> {
+        let result = if self.sort_keys {
+            format::sort_keys(&result)
+        } else {
+            result
+        };
+
+        // Apply formatting options
+        Ok(if self.format != Format::Json {
+            self.encoder.encode(&result)?
+        } else if self.compact {
+            format::compact_output(&result)
+        } else if self.monochrome {
+            format::monochrome_print(&result)
+        } else {
+            format::pretty_print(&result, self.indent, self.color)
+        })
+    }
+}
+
+/// `--in-place`: run the filter on each document of each file and replace the file
+/// with the outputs, one per document. Every file is filtered before any is
+/// written, so a filter that fails or gives the wrong number of outputs for one
+/// document leaves all of the files untouched
+fn edit_in_place<'a>(
+    filter: &'a Filter,
+    globals: &Env<'a>,
+    files: &[(&str, Format)],
+    texts: &'a [String],
+    output_format: Option<Format>,
+    output: &Output,
+) {
+    let mut edits = Vec::new();
+    for (&(path, format), text) in files.iter().zip(texts) {
+        let inputs: Inputs = Rc::new(RefCell::new(format.decode(text)));
+        let env = globals.with_inputs(inputs.clone());
+        let format = output_format.unwrap_or(format);
+        let mut output = Output {
+            color: false,
+            format,
+            encoder: Encoder::new(format),
+            ..*output
         };
+        let mut edited = String::new();
+        let mut documents = 0;
+        // Documents the filter reads with `input` are used up, and don't get an
+        // output of their own
+        loop {
+            let next = inputs.borrow_mut().next();
+            let input = match next {
+                Some(Ok(input)) => input,
+                Some(Err(e)) => fail(2, &format!("{} in {}", e, path)),
+                None if documents == 0 => fail(2, &format!("{} holds no document", path)),
+                None => break,
+            };
+            documents += 1;
+            // A second output is enough to refuse
+            let results: Result<Vec<_>, _> = eval(filter, &env, Rc::new(input)).take(2).collect();
+            let result = match results {
+                Ok(mut results) if results.len() == 1 => results.remove(0),
+                Ok(results) => fail(
+                    5,
+                    &format!(
+                        "--in-place needs exactly one output per document, but the filter gave {} for document {} of {}",
+                        if results.is_empty() {
+                            "none"
+                        } else {
+                            "several"
+                        },
+                        documents,
+                        path
+                    ),
+                ),
+                Err(e) => fail(5, &format!("{} in {}", e, path)),
+            };
+            match output.render(Rc::unwrap_or_clone(result)) {
+                Ok(text) => edited.push_str(&(text + "\n")),
+                Err(e) => fail(5, &format!("{} for {}", e, path)),
+            }
+        }
+        edits.push((path, edited));
+    }
+    for (path, text) in edits {
+        if let Err(e) = write_atomically(path, &text) {
+            fail(2, &format!("Could not write {}: {}", path, e));
+        }
     }
+}
 
-    // Apply formatting options
-    let output = if compact_output {
-        format::compact_output(&result)
-    } else if monochrome_output {
-        format::monochrome_print(&result)
+/// Replace the file at `path` with `text` by writing a temporary file beside it
+/// and renaming it over the original, so that readers never see half a file.
+/// The original's permissions are kept, and a symlink is followed to its target
+fn write_atomically(path: &str, text: &str) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let permissions = fs::metadata(&path)?.permissions();
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let mut file = NamedTempFile::new_in(dir)?;
+    file.write_all(text.as_bytes())?;
+    file.as_file().set_permissions(permissions)?;
+    file.as_file().sync_all()?;
+    file.persist(&path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// `jq-rs diff before.json after.json`
+fn diff_command(matches: &ArgMatches) {
+    let before = read_document(matches.value_of("before").unwrap());
+    let after = read_document(matches.value_of("after").unwrap());
+    let ops = diff::diff(&before, &after);
+    if matches.is_present("json-patch") {
+        println!("{}", format::pretty_print(&diff::to_patch(&ops), 2, false));
+    } else if matches.is_present("merge-patch") {
+        println!(
+            "{}",
+            format::pretty_print(&diff::merge_diff(&before, &after), 2, false)
+        );
     } else {
-        format::pretty_print(&result, indent, color_output)
+        let color = !matches.is_present("monochrome-output") && io::stdout().is_terminal();
+        let readable = diff::Readable {
+            before: &before,
+            ops: &ops,
+            color,
+        };
+        print!("{}", readable);
+    }
+    // Like diff(1), so that scripts can tell whether anything changed
+    if !ops.is_empty() {
+        process::exit(1);
+    }
+}
+
+/// `jq-rs patch doc.json patch.json`
+fn patch_command(matches: &ArgMatches) {
+    let document = read_document(matches.value_of("document").unwrap());
+    let patch = read_document(matches.value_of("patch").unwrap());
+    let patched = match &patch {
+        Value::Array(_) => diff::from_patch(&patch).and_then(|ops| diff::apply(document, &ops)),
+        _ => Ok(diff::merge_patch(document, &patch)),
     };
+    match patched {
+        Ok(patched) if matches.is_present("compact-output") => {
+            println!("{}", format::compact_output(&patched))
+        }
+        Ok(patched) => println!("{}", format::pretty_print(&patched, 2, false)),
+        Err(e) => fail(5, &e.to_string()),
+    }
+}
 
-    println!("{}", output);
+/// `jq-rs schema infer samples...` and `jq-rs schema validate schema.json data.json`
+fn schema_command(matches: &ArgMatches) {
+    match matches.subcommand() {
+        Some(("infer", matches)) => {
+            let samples: Vec<Value> = matches
+                .values_of("samples")
+                .unwrap()
+                .map(read_document)
+                .collect();
+            println!(
+                "{}",
+                format::pretty_print(
+                    &schema::infer(&samples, matches.is_present("strict")),
+                    2,
+                    false
+                )
+            );
+        }
+        Some(("validate", matches)) => {
+            let schema = read_document(matches.value_of("schema").unwrap());
+            let data = read_document(matches.value_of("data").unwrap());
+            match schema::validate(&schema, &data) {
+                Ok(violations) if violations.is_empty() => {}
+                Ok(violations) => {
+                    for violation in violations {
+                        println!("{}", violation);
+                    }
+                    process::exit(1);
+                }
+                Err(e) => fail(2, &e.to_string()),
+            }
+        }
+        _ => unreachable!("clap requires a schema subcommand"),
+    }
+}
+
+/// The one document in the file at `path`, read in the format its extension names
+fn read_document(path: &str) -> Value {
+    let text = read_file(path);
+    let mut values = Format::from_path(path).decode(&text);
+    match (values.next(), values.next()) {
+        (Some(Ok(value)), None) => value,
+        (Some(Err(e)), _) => fail(2, &format!("{} in {}", e, path)),
+        (None, _) => fail(2, &format!("{} holds no document", path)),
+        (Some(Ok(_)), Some(_)) => fail(2, &format!("{} holds more than one document", path)),
+    }
+}
+
+/// Print one result on its own line. When whatever reads the output has gone away,
+/// as `head` does once it has enough, there is nobody left to print for, so this
+/// exits quietly instead of failing
+fn print_result(out: &mut impl Write, text: &str) {
+    match writeln!(out, "{}", text) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => process::exit(0),
+        Err(e) => fail(5, &format!("cannot write output: {}", e)),
+    }
+}
+
+/// Print `message` as a jq error and exit with `code`
+fn fail(code: i32, message: &str) -> ! {
+    eprintln!("jq: error: {}", message);
+    process::exit(code);
+}
+
+fn read_file(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|e| fail(2, &format!("Could not open {}: {}", path, e)))
+}
+
+/// Every JSON value in `text`, which was read from `path`
+fn parse_all(path: &str, text: &str) -> Vec<Value> {
+    Deserializer::from_str(text)
+        .into_iter::<Value>()
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|e| fail(2, &format!("{} in {}", e, path)))
+}
+
+/// The `(name, value)` pairs given to an option such as `--arg name value`
+fn pairs<'m>(matches: &'m ArgMatches, option: &str) -> Vec<(&'m str, &'m str)> {
+    let values: Vec<&str> = matches.values_of(option).into_iter().flatten().collect();
+    values.chunks(2).map(|pair| (pair[0], pair[1])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_in_place_keeps_every_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = dir.path().join("services.yaml");
+        let json = dir.path().join("records.json");
+        fs::write(&yaml, "name: web\nport: 80\n---\nname: db\nport: 5432\n").unwrap();
+        fs::write(&json, "{\"n\": 1}\n{\"n\": 2}\n{\"n\": 3}\n").unwrap();
+        let paths = [yaml.to_str().unwrap(), json.to_str().unwrap()];
+        let files = [(paths[0], Format::Yaml), (paths[1], Format::Json)];
+        let texts: Vec<String> = paths.iter().map(|path| read_file(path)).collect();
+
+        let filter = parser::parse("if has(\"port\") then .port += 1 else .n *= 10 end").unwrap();
+        let output = Output {
+            sort_keys: false,
+            compact: true,
+            monochrome: true,
+            indent: 2,
+            color: false,
+            format: Format::Json,
+            encoder: Encoder::new(Format::Json),
+        };
+        edit_in_place(&filter, &Env::root(), &files, &texts, None, &output);
+
+        assert_eq!(
+            fs::read_to_string(&yaml).unwrap(),
+            "name: web\nport: 81\n---\nname: db\nport: 5433\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&json).unwrap(),
+            "{\"n\":10}\n{\"n\":20}\n{\"n\":30}\n"
+        );
+    }
 }
@@ -0,0 +1,162 @@
+//! Running one filter over many newline-delimited JSON records on several threads.
+//!
+//! Values are shared with `Rc` while a filter runs, so nothing evaluated is sent
+//! between threads: each worker parses its own records, builds its own environment
+//! and hands back plain outputs, which are then emitted in input order.
+
+use std::rc::Rc;
+
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+
+use crate::ast::Filter;
+use crate::error::JqError;
+use crate::eval::{eval, Env};
+use crate::value::Value;
+
+/// How many records a worker takes at a time
+const BATCH: usize = 256;
+
+/// How many batches per thread are in flight at once, which bounds how many
+/// outputs are held back waiting for earlier records to finish
+const BATCHES_PER_THREAD: usize = 4;
+
+/// Whether `filter` reads further inputs with `input` or `inputs`, which makes the
+/// outputs for one record depend on the records after it
+pub fn reads_inputs(filter: &Filter) -> bool {
+    match filter {
+        Filter::Call(name, args) if args.is_empty() && (name == "input" || name == "inputs") => {
+            true
+        }
+        other => other.children().into_iter().any(reads_inputs),
+    }
+}
+
+/// The outputs of one record, and the error that stopped it if there was one
+type Outcome = (Vec<Value>, Option<JqError>);
+
+/// Run `filter` on each line of `lines`, which hold one JSON value apiece, on `jobs`
+/// threads. `emit` sees the outputs in the same order as running the records one
+/// after another would give. The first error, either a record that is not valid
+/// JSON (as `JqError::Input`) or a failure of the filter, is emitted last
+pub fn run_lines<'t>(
+    filter: &Filter,
+    globals: &[(String, Value)],
+    lines: impl Iterator<Item = &'t str>,
+    jobs: usize,
+    mut emit: impl FnMut(Result<Value, JqError>),
+) -> Result<(), JqError> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(|e| JqError::Input(format!("cannot start worker threads: {}", e)))?;
+    let mut lines = lines.filter(|line| !line.trim().is_empty()).peekable();
+    while lines.peek().is_some() {
+        let window: Vec<&str> = lines
+            .by_ref()
+            .take(BATCH * BATCHES_PER_THREAD * jobs)
+            .collect();
+        let outcomes: Vec<Vec<Outcome>> = pool.install(|| {
+            window
+                .par_chunks(BATCH)
+                .map(|batch| run_batch(filter, globals, batch))
+                .collect()
+        });
+        for (outputs, error) in outcomes.into_iter().flatten() {
+            outputs.into_iter().for_each(|output| emit(Ok(output)));
+            if let Some(e) = error {
+                emit(Err(e));
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The outcome of each record in `batch`, up to the first that fails
+fn run_batch(filter: &Filter, globals: &[(String, Value)], batch: &[&str]) -> Vec<Outcome> {
+    let env = globals.iter().fold(Env::root(), |env, (name, value)| {
+        env.bind_var(name.clone(), value.clone())
+    });
+    let mut outcomes = Vec::with_capacity(batch.len());
+    for line in batch {
+        let input = match serde_json::from_str(line) {
+            Ok(input) => input,
+            Err(e) => {
+                outcomes.push((Vec::new(), Some(JqError::Input(e.to_string()))));
+                break;
+            }
+        };
+        let mut outputs = Vec::new();
+        let mut error = None;
+        for result in eval(filter, &env, Rc::new(input)) {
+            match result {
+                Ok(output) => outputs.push(Rc::unwrap_or_clone(output)),
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+        let failed = error.is_some();
+        outcomes.push((outputs, error));
+        if failed {
+            break;
+        }
+    }
+    outcomes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use crate::value::json;
+
+    fn run(program: &str, lines: &[String], jobs: usize) -> Vec<Result<Value, JqError>> {
+        let filter = parser::parse(program).unwrap();
+        let globals = vec![("factor".to_string(), json!(10))];
+        let mut emitted = Vec::new();
+        let lines = lines.iter().map(String::as_str);
+        run_lines(&filter, &globals, lines, jobs, |result| {
+            emitted.push(result)
+        })
+        .unwrap();
+        emitted
+    }
+
+    #[test]
+    fn test_outputs_keep_input_order() {
+        let lines: Vec<String> = (0..5000).map(|n| json!({"n": n}).to_string()).collect();
+        let expected: Vec<_> = (0..5000)
+            .flat_map(|n| [Ok(json!(n)), Ok(json!(n * 10))])
+            .collect();
+        assert_eq!(run(".n, .n * $factor", &lines, 4), expected);
+        assert_eq!(run(".n, .n * $factor", &lines, 1), expected);
+    }
+
+    #[test]
+    fn test_stops_at_the_first_error() {
+        let mut lines: Vec<String> = (0..2000).map(|n| n.to_string()).collect();
+        lines[700] = "\"x\"".into();
+        lines[1500] = "{oops".into();
+        let emitted = run(". + 1", &lines, 4);
+        assert_eq!(emitted.len(), 701);
+        assert_eq!(emitted[699], Ok(json!(700)));
+        assert!(matches!(emitted[700], Err(JqError::Type(_))));
+
+        let emitted = run(".", &lines[701..], 4);
+        assert_eq!(emitted.len(), 1500 - 701 + 1);
+        assert!(matches!(emitted.last(), Some(Err(JqError::Input(_)))));
+    }
+
+    #[test]
+    fn test_reads_inputs() {
+        let reads = |program: &str| reads_inputs(&parser::parse(program).unwrap());
+        assert!(reads("[., input]"));
+        assert!(reads("def f: first(inputs); f"));
+        assert!(reads("{a: (try input catch null)}"));
+        assert!(!reads(".input, input_x, {input: 1}"));
+        assert!(!reads("map(. + 1)"));
+    }
+}
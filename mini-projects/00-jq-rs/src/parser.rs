@@ -0,0 +1,868 @@
+use crate::ast::{AssignOp, BinaryOp, Filter, FuncDef, Param, StringPart};
+use crate::error::JqError;
+use crate::filters;
+use crate::value::Value;
+
+/// Parse a jq program into a `Filter`
+pub fn parse(program: &str) -> Result<Filter, JqError> {
+    let tokens = Lexer::new(program).tokenize()?;
+    let mut parser = Parser { tokens, pos: 0 };
+    let filter = parser.parse_pipe(true)?;
+    match parser.peek() {
+        None => Ok(filter),
+        Some(token) => Err(unexpected(token)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Dot,
+    DotDot,
+    Field(String),
+    Ident(String),
+    Var(String),
+    Num(Value),
+    Str(Vec<StringToken>),
+    Punct(&'static str),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StringToken {
+    Literal(String),
+    Expr(Vec<Token>),
+}
+
+const KEYWORDS: &[&str] = &[
+    "def", "if", "then", "elif", "else", "end", "as", "reduce", "foreach", "try", "catch", "and",
+    "or",
+];
+
+// Longest operators first so that e.g. `|=` is not lexed as `|` followed by `=`
+const PUNCTUATION: &[&str] = &[
+    "//=", "|=", "+=", "-=", "*=", "/=", "%=", "==", "!=", "<=", ">=", "//", "|", ",", "(", ")",
+    "[", "]", "{", "}", ":", ";", "?", "+", "-", "*", "/", "%", "<", ">", "=",
+];
+
+fn unexpected(token: &Token) -> JqError {
+    JqError::Syntax(format!("unexpected token {:?}", token))
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Lexer {
+    fn new(source: &str) -> Self {
+        Lexer {
+            chars: source.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn tokenize(&mut self) -> Result<Vec<Token>, JqError> {
+        let tokens = self.tokens_until(None)?;
+        Ok(tokens)
+    }
+
+    /// Lex tokens until the end of input, or until an unbalanced `close` when lexing the
+    /// contents of a string interpolation
+    fn tokens_until(&mut self, close: Option<char>) -> Result<Vec<Token>, JqError> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            self.skip_whitespace();
+            let c = match self.peek() {
+                Some(c) => c,
+                None if close.is_some() => {
+                    return Err(JqError::Syntax("unterminated string interpolation".into()))
+                }
+                None => return Ok(tokens),
+            };
+            if Some(c) == close && depth == 0 {
+                self.pos += 1;
+                return Ok(tokens);
+            }
+            let token = self.next_token(c)?;
+            match token {
+                Token::Punct("(") => depth += 1,
+                Token::Punct(")") => depth -= 1,
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// Skip whitespace and comments, which run from `#` to the end of the line
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => self.pos += 1,
+                Some('#') => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn next_token(&mut self, c: char) -> Result<Token, JqError> {
+        if c == '.' {
+            self.pos += 1;
+            return Ok(match self.peek() {
+                Some('.') => {
+                    self.pos += 1;
+                    Token::DotDot
+                }
+                Some(c) if is_ident_start(c) => Token::Field(self.ident()),
+                _ => Token::Dot,
+            });
+        }
+        if c == '$' {
+            self.pos += 1;
+            if !self.peek().is_some_and(is_ident_start) {
+                return Err(JqError::Syntax("expected a variable name after `$`".into()));
+            }
+            return Ok(Token::Var(self.ident()));
+        }
+        if c == '"' {
+            self.pos += 1;
+            return self.string();
+        }
+        if c.is_ascii_digit() {
+            return self.number();
+        }
+        if is_ident_start(c) {
+            return Ok(Token::Ident(self.ident()));
+        }
+        for punct in PUNCTUATION {
+            if punct
+                .chars()
+                .enumerate()
+                .all(|(i, p)| self.peek_at(i) == Some(p))
+            {
+                self.pos += punct.len();
+                return Ok(Token::Punct(punct));
+            }
+        }
+        Err(JqError::Syntax(format!("unexpected character '{}'", c)))
+    }
+
+    fn ident(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(is_ident_char) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn number(&mut self) -> Result<Token, JqError> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if self.peek() == Some('.') && self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.pos += 1;
+            }
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
+            let mut end = self.pos + 1;
+            if matches!(self.chars.get(end), Some('+' | '-')) {
+                end += 1;
+            }
+            if self.chars.get(end).is_some_and(|c| c.is_ascii_digit()) {
+                self.pos = end;
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+            }
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        // serde_json keeps the literal's integer or float form; anything it rejects (such as
+        // an exponent too large for an f64) falls back to the nearest double, which may be
+        // infinite
+        let value = match serde_json::from_str(&text) {
+            Ok(value) => value,
+            Err(_) => match text.parse::<f64>() {
+                Ok(n) => filters::number(n),
+                Err(_) => return Err(JqError::Syntax(format!("invalid number {}", text))),
+            },
+        };
+        Ok(Token::Num(value))
+    }
+
+    fn string(&mut self) -> Result<Token, JqError> {
+        let mut parts = Vec::new();
+        let mut current = String::new();
+        loop {
+            let c = self
+                .peek()
+                .ok_or_else(|| JqError::Syntax("unterminated string literal".into()))?;
+            self.pos += 1;
+            match c {
+                '"' => break,
+                '\\' => {
+                    let escape = self
+                        .peek()
+                        .ok_or_else(|| JqError::Syntax("unterminated string literal".into()))?;
+                    self.pos += 1;
+                    match escape {
+                        'n' => current.push('\n'),
+                        't' => current.push('\t'),
+                        'r' => current.push('\r'),
+                        'b' => current.push('\u{8}'),
+                        'f' => current.push('\u{c}'),
+                        '"' | '\\' | '/' => current.push(escape),
+                        'u' => current.push(self.unicode_escape()?),
+                        '(' => {
+                            if !current.is_empty() {
+                                parts.push(StringToken::Literal(std::mem::take(&mut current)));
+                            }
+                            parts.push(StringToken::Expr(self.tokens_until(Some(')'))?));
+                        }
+                        other => {
+                            return Err(JqError::Syntax(format!(
+                                "invalid escape '\\{}' in string literal",
+                                other
+                            )))
+                        }
+                    }
+                }
+                c => current.push(c),
+            }
+        }
+        if !current.is_empty() || parts.is_empty() {
+            parts.push(StringToken::Literal(current));
+        }
+        Ok(Token::Str(parts))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JqError> {
+        let digits: String = self.chars.iter().skip(self.pos).take(4).collect();
+        self.pos += 4;
+        u32::from_str_radix(&digits, 16)
+            .map_err(|_| JqError::Syntax(format!("invalid unicode escape '\\u{}'", digits)))
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, JqError> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high)
+            && self.peek() == Some('\\')
+            && self.peek_at(1) == Some('u')
+        {
+            self.pos += 2;
+            let low = self.hex4()?;
+            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+        } else {
+            high
+        };
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, JqError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| JqError::Syntax("unexpected end of filter".into()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn at_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Some(Token::Punct(p)) if *p == punct)
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(name)) if name == keyword)
+    }
+
+    fn eat_punct(&mut self, punct: &str) -> bool {
+        let found = self.at_punct(punct);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, punct: &str) -> Result<(), JqError> {
+        if self.eat_punct(punct) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("'{}'", punct)))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), JqError> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("'{}'", keyword)))
+        }
+    }
+
+    fn expect_var(&mut self) -> Result<String, JqError> {
+        match self.next()? {
+            Token::Var(name) => Ok(name),
+            token => Err(JqError::Syntax(format!(
+                "expected a variable, found {:?}",
+                token
+            ))),
+        }
+    }
+
+    fn expected(&self, what: &str) -> JqError {
+        match self.peek() {
+            Some(token) => JqError::Syntax(format!("expected {}, found {:?}", what, token)),
+            None => JqError::Syntax(format!("expected {}, found end of filter", what)),
+        }
+    }
+
+    /// `a | b`, the loosest binding operator. `allow_comma` is false inside object values,
+    /// where a comma separates entries instead
+    fn parse_pipe(&mut self, allow_comma: bool) -> Result<Filter, JqError> {
+        if self.at_keyword("def") {
+            let def = self.parse_def()?;
+            let rest = self.parse_pipe(allow_comma)?;
+            return Ok(Filter::Def(Box::new(def), Box::new(rest)));
+        }
+        let lhs = if allow_comma {
+            self.parse_comma()?
+        } else {
+            self.parse_alternative()?
+        };
+        if self.eat_punct("|") {
+            let rhs = self.parse_pipe(allow_comma)?;
+            return Ok(Filter::Pipe(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comma(&mut self) -> Result<Filter, JqError> {
+        let mut lhs = self.parse_alternative()?;
+        while self.eat_punct(",") {
+            let rhs = self.parse_alternative()?;
+            lhs = Filter::Comma(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_alternative(&mut self) -> Result<Filter, JqError> {
+        let lhs = self.parse_assign()?;
+        if self.eat_punct("//") {
+            let rhs = self.parse_alternative()?;
+            return Ok(Filter::Alternative(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_assign(&mut self) -> Result<Filter, JqError> {
+        let lhs = self.parse_or()?;
+        let op = match self.peek() {
+            Some(Token::Punct("=")) => AssignOp::Set,
+            Some(Token::Punct("|=")) => AssignOp::Update,
+            Some(Token::Punct("+=")) => AssignOp::Arithmetic(BinaryOp::Add),
+            Some(Token::Punct("-=")) => AssignOp::Arithmetic(BinaryOp::Sub),
+            Some(Token::Punct("*=")) => AssignOp::Arithmetic(BinaryOp::Mul),
+            Some(Token::Punct("/=")) => AssignOp::Arithmetic(BinaryOp::Div),
+            Some(Token::Punct("%=")) => AssignOp::Arithmetic(BinaryOp::Mod),
+            Some(Token::Punct("//=")) => AssignOp::Alternative,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        let rhs = self.parse_alternative()?;
+        Ok(Filter::Assign(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    fn parse_or(&mut self) -> Result<Filter, JqError> {
+        let mut lhs = self.parse_and()?;
+        while self.eat_keyword("or") {
+            let rhs = self.parse_and()?;
+            lhs = Filter::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter, JqError> {
+        let mut lhs = self.parse_comparison()?;
+        while self.eat_keyword("and") {
+            let rhs = self.parse_comparison()?;
+            lhs = Filter::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Filter, JqError> {
+        let lhs = self.parse_additive()?;
+        let op = match self.peek() {
+            Some(Token::Punct("==")) => BinaryOp::Eq,
+            Some(Token::Punct("!=")) => BinaryOp::Ne,
+            Some(Token::Punct("<")) => BinaryOp::Lt,
+            Some(Token::Punct("<=")) => BinaryOp::Le,
+            Some(Token::Punct(">")) => BinaryOp::Gt,
+            Some(Token::Punct(">=")) => BinaryOp::Ge,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        let rhs = self.parse_additive()?;
+        Ok(Filter::Binary(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    fn parse_additive(&mut self) -> Result<Filter, JqError> {
+        let mut lhs = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct("+")) => BinaryOp::Add,
+                Some(Token::Punct("-")) => BinaryOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.parse_multiplicative()?;
+            lhs = Filter::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Filter, JqError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Punct("*")) => BinaryOp::Mul,
+                Some(Token::Punct("/")) => BinaryOp::Div,
+                Some(Token::Punct("%")) => BinaryOp::Mod,
+                _ => return Ok(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.parse_unary()?;
+            lhs = Filter::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Filter, JqError> {
+        if self.eat_punct("-") {
+            let term = self.parse_postfix(true)?;
+            return Ok(Filter::Neg(Box::new(term)));
+        }
+        self.parse_postfix(true)
+    }
+
+    /// A term followed by any number of `.foo`, `[...]` and `?` suffixes. When `allow_as`
+    /// is set, a trailing `as $name | body` binding is parsed as part of the term
+    fn parse_postfix(&mut self, allow_as: bool) -> Result<Filter, JqError> {
+        let mut term = self.parse_primary()?;
+        loop {
+            match self.peek() {
+                Some(Token::Field(name)) => {
+                    let key = Filter::Literal(Value::String(name.clone()));
+                    self.pos += 1;
+                    term = Filter::Index(Box::new(term), Box::new(key));
+                }
+                Some(Token::Dot) => match self.tokens.get(self.pos + 1) {
+                    Some(Token::Str(parts)) => {
+                        let key = string_filter(parts.clone())?;
+                        self.pos += 2;
+                        term = Filter::Index(Box::new(term), Box::new(key));
+                    }
+                    Some(Token::Punct("[")) => self.pos += 1,
+                    _ => break,
+                },
+                Some(Token::Punct("[")) => {
+                    self.pos += 1;
+                    term = self.parse_brackets(term)?;
+                }
+                Some(Token::Punct("?")) => {
+                    self.pos += 1;
+                    term = Filter::Try(Box::new(term), None);
+                }
+                _ => break,
+            }
+        }
+        if allow_as && self.eat_keyword("as") {
+            let name = self.expect_var()?;
+            self.expect_punct("|")?;
+            let body = self.parse_pipe(true)?;
+            return Ok(Filter::Bind(Box::new(term), name, Box::new(body)));
+        }
+        Ok(term)
+    }
+
+    /// The inside of `term[...]`, after the opening bracket
+    fn parse_brackets(&mut self, term: Filter) -> Result<Filter, JqError> {
+        if self.eat_punct("]") {
+            return Ok(Filter::Iterate(Box::new(term)));
+        }
+        // A missing slice bound is null, which means the start or end of the value
+        let index = if self.at_punct(":") {
+            Filter::Literal(Value::Null)
+        } else {
+            self.parse_pipe(true)?
+        };
+        if self.eat_punct(":") {
+            let end = if self.at_punct("]") {
+                Filter::Literal(Value::Null)
+            } else {
+                self.parse_pipe(true)?
+            };
+            self.expect_punct("]")?;
+            return Ok(Filter::Slice(
+                Box::new(term),
+                Box::new(index),
+                Box::new(end),
+            ));
+        }
+        self.expect_punct("]")?;
+        Ok(Filter::Index(Box::new(term), Box::new(index)))
+    }
+
+    fn parse_primary(&mut self) -> Result<Filter, JqError> {
+        match self.next()? {
+            Token::Dot => match self.peek() {
+                Some(Token::Str(parts)) => {
+                    let key = string_filter(parts.clone())?;
+                    self.pos += 1;
+                    Ok(Filter::Index(Box::new(Filter::Identity), Box::new(key)))
+                }
+                _ => Ok(Filter::Identity),
+            },
+            Token::DotDot => Ok(Filter::Call("recurse".into(), Vec::new())),
+            Token::Field(name) => Ok(Filter::Index(
+                Box::new(Filter::Identity),
+                Box::new(Filter::Literal(Value::String(name))),
+            )),
+            Token::Num(value) => Ok(Filter::Literal(value)),
+            Token::Str(parts) => string_filter(parts),
+            Token::Var(name) => Ok(Filter::Var(name)),
+            Token::Punct("(") => {
+                let inner = self.parse_pipe(true)?;
+                self.expect_punct(")")?;
+                Ok(inner)
+            }
+            Token::Punct("[") => {
+                if self.eat_punct("]") {
+                    return Ok(Filter::Array(None));
+                }
+                let inner = self.parse_pipe(true)?;
+                self.expect_punct("]")?;
+                Ok(Filter::Array(Some(Box::new(inner))))
+            }
+            Token::Punct("{") => self.parse_object(),
+            Token::Ident(name) => self.parse_ident(name),
+            token => Err(unexpected(&token)),
+        }
+    }
+
+    fn parse_ident(&mut self, name: String) -> Result<Filter, JqError> {
+        match name.as_str() {
+            "true" => Ok(Filter::Literal(Value::Bool(true))),
+            "false" => Ok(Filter::Literal(Value::Bool(false))),
+            "null" => Ok(Filter::Literal(Value::Null)),
+            "if" => self.parse_if(),
+            "try" => {
+                let body = self.parse_postfix(false)?;
+                let handler = if self.eat_keyword("catch") {
+                    Some(Box::new(self.parse_postfix(false)?))
+                } else {
+                    None
+                };
+                Ok(Filter::Try(Box::new(body), handler))
+            }
+            "reduce" => {
+                let source = self.parse_postfix(false)?;
+                self.expect_keyword("as")?;
+                let var = self.expect_var()?;
+                self.expect_punct("(")?;
+                let init = self.parse_pipe(true)?;
+                self.expect_punct(";")?;
+                let update = self.parse_pipe(true)?;
+                self.expect_punct(")")?;
+                Ok(Filter::Reduce(
+                    Box::new(source),
+                    var,
+                    Box::new(init),
+                    Box::new(update),
+                ))
+            }
+            "foreach" => {
+                let source = self.parse_postfix(false)?;
+                self.expect_keyword("as")?;
+                let var = self.expect_var()?;
+                self.expect_punct("(")?;
+                let init = self.parse_pipe(true)?;
+                self.expect_punct(";")?;
+                let update = self.parse_pipe(true)?;
+                let extract = if self.eat_punct(";") {
+                    Some(Box::new(self.parse_pipe(true)?))
+                } else {
+                    None
+                };
+                self.expect_punct(")")?;
+                Ok(Filter::Foreach(
+                    Box::new(source),
+                    var,
+                    Box::new(init),
+                    Box::new(update),
+                    extract,
+                ))
+            }
+            keyword if KEYWORDS.contains(&keyword) => {
+                Err(JqError::Syntax(format!("unexpected keyword '{}'", keyword)))
+            }
+            _ => {
+                let mut args = Vec::new();
+                if self.eat_punct("(") {
+                    loop {
+                        args.push(self.parse_pipe(true)?);
+                        if self.eat_punct(")") {
+                            break;
+                        }
+                        self.expect_punct(";")?;
+                    }
+                }
+                Ok(Filter::Call(name, args))
+            }
+        }
+    }
+
+    /// `if` has already been consumed
+    fn parse_if(&mut self) -> Result<Filter, JqError> {
+        let cond = self.parse_pipe(true)?;
+        self.expect_keyword("then")?;
+        let then = self.parse_pipe(true)?;
+        let otherwise = if self.eat_keyword("elif") {
+            Some(Box::new(self.parse_if()?))
+        } else if self.eat_keyword("else") {
+            let otherwise = self.parse_pipe(true)?;
+            self.expect_keyword("end")?;
+            Some(Box::new(otherwise))
+        } else {
+            self.expect_keyword("end")?;
+            None
+        };
+        Ok(Filter::If(Box::new(cond), Box::new(then), otherwise))
+    }
+
+    /// `{` has already been consumed
+    fn parse_object(&mut self) -> Result<Filter, JqError> {
+        let mut entries = Vec::new();
+        if self.eat_punct("}") {
+            return Ok(Filter::Object(entries));
+        }
+        loop {
+            let entry = match self.next()? {
+                Token::Var(name) => (
+                    Filter::Literal(Value::String(name.clone())),
+                    Filter::Var(name),
+                ),
+                Token::Ident(name) => {
+                    let key = Filter::Literal(Value::String(name));
+                    self.parse_object_value(key)?
+                }
+                Token::Str(parts) => {
+                    let key = string_filter(parts)?;
+                    self.parse_object_value(key)?
+                }
+                Token::Num(value) => {
+                    let key = Filter::Literal(Value::String(value.to_string()));
+                    self.parse_object_value(key)?
+                }
+                Token::Punct("(") => {
+                    let key = self.parse_pipe(true)?;
+                    self.expect_punct(")")?;
+                    self.expect_punct(":")?;
+                    (key, self.parse_pipe(false)?)
+                }
+                token => return Err(unexpected(&token)),
+            };
+            entries.push(entry);
+            if self.eat_punct("}") {
+                return Ok(Filter::Object(entries));
+            }
+            self.expect_punct(",")?;
+        }
+    }
+
+    /// The value for a literal object key; `{a}` is shorthand for `{a: .a}`
+    fn parse_object_value(&mut self, key: Filter) -> Result<(Filter, Filter), JqError> {
+        if self.eat_punct(":") {
+            let value = self.parse_pipe(false)?;
+            return Ok((key, value));
+        }
+        let value = Filter::Index(Box::new(Filter::Identity), Box::new(key.clone()));
+        Ok((key, value))
+    }
+
+    /// `def` has not been consumed yet
+    fn parse_def(&mut self) -> Result<FuncDef, JqError> {
+        self.expect_keyword("def")?;
+        let name = match self.next()? {
+            Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => name,
+            token => return Err(unexpected(&token)),
+        };
+        let mut params = Vec::new();
+        if self.eat_punct("(") {
+            loop {
+                params.push(match self.next()? {
+                    Token::Ident(name) => Param::Filter(name),
+                    Token::Var(name) => Param::Value(name),
+                    token => return Err(unexpected(&token)),
+                });
+                if self.eat_punct(")") {
+                    break;
+                }
+                self.expect_punct(";")?;
+            }
+        }
+        self.expect_punct(":")?;
+        let body = self.parse_pipe(true)?;
+        self.expect_punct(";")?;
+        Ok(FuncDef { name, params, body })
+    }
+}
+
+/// Turn the pieces of a string token into a literal, or an interpolation if it has any
+/// `\(...)` parts
+fn string_filter(parts: Vec<StringToken>) -> Result<Filter, JqError> {
+    if let [StringToken::Literal(s)] = parts.as_slice() {
+        return Ok(Filter::Literal(Value::String(s.clone())));
+    }
+    let parts = parts
+        .into_iter()
+        .map(|part| match part {
+            StringToken::Literal(s) => Ok(StringPart::Literal(s)),
+            StringToken::Expr(tokens) => {
+                let mut parser = Parser { tokens, pos: 0 };
+                let filter = parser.parse_pipe(true)?;
+                match parser.peek() {
+                    None => Ok(StringPart::Expr(filter)),
+                    Some(token) => Err(unexpected(token)),
+                }
+            }
+        })
+        .collect::<Result<_, JqError>>()?;
+    Ok(Filter::Interpolate(parts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::json;
+
+    fn field(term: Filter, name: &str) -> Filter {
+        Filter::Index(Box::new(term), Box::new(Filter::Literal(json!(name))))
+    }
+
+    #[test]
+    fn test_parse_identity() {
+        assert_eq!(parse(".").unwrap(), Filter::Identity);
+    }
+
+    #[test]
+    fn test_parse_field_chain() {
+        let expected = field(field(Filter::Identity, "a"), "b");
+        assert_eq!(parse(".a.b").unwrap(), expected);
+        assert_eq!(parse(".a | .b").unwrap(), {
+            Filter::Pipe(
+                Box::new(field(Filter::Identity, "a")),
+                Box::new(field(Filter::Identity, "b")),
+            )
+        });
+    }
+
+    #[test]
+    fn test_parse_index_and_iterate() {
+        let expected = Filter::Iterate(Box::new(Filter::Index(
+            Box::new(Filter::Identity),
+            Box::new(Filter::Literal(json!(0))),
+        )));
+        assert_eq!(parse(".[0][]").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_call_with_args() {
+        let expected = Filter::Call(
+            "pow".into(),
+            vec![Filter::Identity, Filter::Literal(json!(2))],
+        );
+        assert_eq!(parse("pow(.; 2)").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let expected = Filter::Binary(
+            BinaryOp::Add,
+            Box::new(Filter::Literal(json!(1))),
+            Box::new(Filter::Binary(
+                BinaryOp::Mul,
+                Box::new(Filter::Literal(json!(2))),
+                Box::new(Filter::Literal(json!(3))),
+            )),
+        );
+        assert_eq!(parse("1 + 2 * 3").unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_string_interpolation() {
+        let expected = Filter::Interpolate(vec![
+            StringPart::Literal("a".into()),
+            StringPart::Expr(field(Filter::Identity, "b")),
+        ]);
+        assert_eq!(parse(r#""a\(.b)""#).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_comments() {
+        let program = "# leading comment\n.a # trailing comment\n| .b # \"not a string\"";
+        assert_eq!(parse(program).unwrap(), parse(".a | .b").unwrap());
+        assert_eq!(
+            parse("\"a # b\"").unwrap(),
+            Filter::Literal(Value::from("a # b"))
+        );
+        assert_eq!(parse("1 # no newline").unwrap(), parse("1").unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(parse(".[0"), Err(JqError::Syntax(_))));
+        assert!(matches!(parse("if . then 1"), Err(JqError::Syntax(_))));
+        assert!(matches!(parse(". |"), Err(JqError::Syntax(_))));
+        assert!(matches!(parse(")"), Err(JqError::Syntax(_))));
+    }
+}
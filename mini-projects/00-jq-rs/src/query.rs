@@ -0,0 +1,741 @@
+//! JSONPath and JSON Pointer queries, compiled into ordinary jq filters.
+//!
+//! Both syntaxes become an `ast::Filter` built from the same builtins a jq program
+//! would use, so they run through the one evaluator and output pipeline.
+//!
+//! JSONPath follows RFC 9535 without its function extensions: a selector that finds
+//! nothing yields no output instead of `null`, and in a filter expression a query that
+//! finds nothing compares as `null`.
+
+use crate::ast::{BinaryOp, Filter};
+use crate::diff::parse_pointer;
+use crate::error::JqError;
+use crate::value::{Number, Value};
+
+/// The variable holding the document a JSONPath query started from, for `$` inside
+/// filter expressions
+const ROOT: &str = "__jsonpath_root";
+
+/// Compile an RFC 6901 pointer such as `/store/book/0`. A token selects a member of
+/// an object, or an element of an array when it is an index without a leading zero.
+/// A member or element that is missing is an error naming the part of the pointer
+/// that resolved to nothing, since a pointer refers to exactly one value
+pub fn pointer(text: &str) -> Result<Filter, JqError> {
+    let tokens = parse_pointer(text).map_err(|e| JqError::Syntax(e.to_string()))?;
+    Ok((0..tokens.len())
+        .map(|i| {
+            let missing = fail(format!(
+                "JSON pointer {} does not resolve",
+                crate::diff::pointer(&tokens[..=i])
+            ));
+            let token = tokens[i].as_str().unwrap_or_default().to_string();
+            let key = Value::String(token.clone());
+            let member = Filter::If(
+                Box::new(call("has", vec![Filter::Literal(key.clone())])),
+                Box::new(index(key)),
+                Some(Box::new(missing.clone())),
+            );
+            // `-` names the element after the last, which never exists
+            let element = match array_index(&token) {
+                Some(n) => Filter::If(
+                    Box::new(Filter::Binary(
+                        BinaryOp::Gt,
+                        Box::new(call("length", vec![])),
+                        Box::new(Filter::Literal(Value::from(n))),
+                    )),
+                    Box::new(index(Value::from(n))),
+                    Some(Box::new(missing.clone())),
+                ),
+                None => missing.clone(),
+            };
+            Filter::If(
+                Box::new(is_type("object")),
+                Box::new(member),
+                Some(Box::new(Filter::If(
+                    Box::new(is_type("array")),
+                    Box::new(element),
+                    Some(Box::new(missing)),
+                ))),
+            )
+        })
+        .reduce(pipe)
+        .unwrap_or(Filter::Identity))
+}
+
+/// Compile a JSONPath query such as `$.store.book[?@.price < 10].title`
+pub fn jsonpath(text: &str) -> Result<Filter, JqError> {
+    let mut parser = Parser {
+        text,
+        chars: text.char_indices().collect(),
+        pos: 0,
+        uses_root: false,
+    };
+    parser.skip_space();
+    parser.expect('$')?;
+    let query = parser.segments()?;
+    parser.skip_space();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("unexpected character"));
+    }
+    Ok(if parser.uses_root {
+        Filter::Bind(
+            Box::new(Filter::Identity),
+            ROOT.to_string(),
+            Box::new(query),
+        )
+    } else {
+        query
+    })
+}
+
+/// `token` as an array index: digits without a leading zero
+fn array_index(token: &str) -> Option<u64> {
+    let canonical = token == "0" || !token.starts_with('0');
+    if canonical && !token.is_empty() && token.bytes().all(|b| b.is_ascii_digit()) {
+        token.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn pipe(lhs: Filter, rhs: Filter) -> Filter {
+    Filter::Pipe(Box::new(lhs), Box::new(rhs))
+}
+
+fn call(name: &str, args: Vec<Filter>) -> Filter {
+    Filter::Call(name.to_string(), args)
+}
+
+/// `type == "name"`
+fn is_type(name: &str) -> Filter {
+    Filter::Binary(
+        BinaryOp::Eq,
+        Box::new(call("type", vec![])),
+        Box::new(Filter::Literal(Value::from(name))),
+    )
+}
+
+/// `error(message)`
+fn fail(message: String) -> Filter {
+    call("error", vec![Filter::Literal(Value::String(message))])
+}
+
+/// `.[key]`
+fn index(key: Value) -> Filter {
+    Filter::Index(Box::new(Filter::Identity), Box::new(Filter::Literal(key)))
+}
+
+/// `select(cond)`
+fn select(cond: Filter) -> Filter {
+    call("select", vec![cond])
+}
+
+/// `.[]?`, the children of an array or object and nothing for anything else
+fn children() -> Filter {
+    Filter::Try(Box::new(Filter::Iterate(Box::new(Filter::Identity))), None)
+}
+
+/// One selector inside brackets, or after `.` or `..`
+#[derive(Debug)]
+enum Selector {
+    Name(String),
+    Wildcard,
+    Index(i64),
+    Slice(Option<i64>, Option<i64>, Option<i64>),
+    Filter(Filter),
+}
+
+impl Selector {
+    fn compile(self) -> Filter {
+        match self {
+            Selector::Name(name) => {
+                let key = Value::String(name);
+                pipe(
+                    call("objects", vec![]),
+                    pipe(
+                        select(call("has", vec![Filter::Literal(key.clone())])),
+                        index(key),
+                    ),
+                )
+            }
+            Selector::Wildcard => children(),
+            Selector::Index(n) => {
+                let in_range = if n >= 0 {
+                    Filter::Binary(
+                        BinaryOp::Gt,
+                        Box::new(call("length", vec![])),
+                        Box::new(Filter::Literal(Value::from(n))),
+                    )
+                } else {
+                    Filter::Binary(
+                        BinaryOp::Ge,
+                        Box::new(call("length", vec![])),
+                        Box::new(Filter::Literal(Value::from(-n))),
+                    )
+                };
+                pipe(
+                    call("arrays", vec![]),
+                    pipe(select(in_range), index(Value::from(n))),
+                )
+            }
+            Selector::Slice(_, _, Some(0)) => call("empty", vec![]),
+            Selector::Slice(start, end, step) => {
+                let bound =
+                    |n: Option<i64>| Box::new(Filter::Literal(n.map_or(Value::Null, Value::from)));
+                let slice = Filter::Slice(Box::new(Filter::Identity), bound(start), bound(end));
+                let elements = match step {
+                    None | Some(1) => Filter::Iterate(Box::new(slice)),
+                    Some(step) => pipe(
+                        slice,
+                        Filter::Index(
+                            Box::new(Filter::Identity),
+                            Box::new(call(
+                                "range",
+                                vec![
+                                    Filter::Literal(Value::from(0)),
+                                    call("length", vec![]),
+                                    Filter::Literal(Value::from(step)),
+                                ],
+                            )),
+                        ),
+                    ),
+                };
+                pipe(call("arrays", vec![]), elements)
+            }
+            Selector::Filter(cond) => pipe(children(), select(cond)),
+        }
+    }
+}
+
+/// An operand of a comparison in a filter expression
+enum Operand {
+    Literal(Value),
+    Query(Filter),
+}
+
+impl Operand {
+    /// The value compared: a query's first result, or `null` when it finds nothing
+    fn compile(self) -> Filter {
+        match self {
+            Operand::Literal(value) => Filter::Literal(value),
+            Operand::Query(query) => call(
+                "first",
+                vec![Filter::Comma(
+                    Box::new(query),
+                    Box::new(Filter::Literal(Value::Null)),
+                )],
+            ),
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    uses_root: bool,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> JqError {
+        let offset = self.chars.get(self.pos).map_or(self.text.len(), |c| c.0);
+        JqError::Syntax(format!(
+            "{} in JSONPath {:?} at offset {}",
+            message, self.text, offset
+        ))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).map(|c| c.1)
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<char> {
+        self.chars.get(self.pos + ahead).map(|c| c.1)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        let matches = s
+            .chars()
+            .enumerate()
+            .all(|(i, c)| self.peek_at(i) == Some(c));
+        if matches {
+            self.pos += s.chars().count();
+        }
+        matches
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), JqError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", c)))
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            self.pos += 1;
+        }
+    }
+
+    /// The segments after `$` or `@`, piped together
+    fn segments(&mut self) -> Result<Filter, JqError> {
+        let mut query = Filter::Identity;
+        loop {
+            let segment = if self.eat_str("..") {
+                let selectors = match self.peek() {
+                    Some('[') => self.bracket()?,
+                    Some('*') => {
+                        self.pos += 1;
+                        vec![Selector::Wildcard]
+                    }
+                    _ => vec![Selector::Name(self.name()?)],
+                };
+                pipe(call("recurse", vec![]), union(selectors))
+            } else if self.eat('.') {
+                if self.eat('*') {
+                    Selector::Wildcard.compile()
+                } else {
+                    Selector::Name(self.name()?).compile()
+                }
+            } else if self.peek() == Some('[') {
+                union(self.bracket()?)
+            } else {
+                return Ok(query);
+            };
+            query = match query {
+                Filter::Identity => segment,
+                query => pipe(query, segment),
+            };
+        }
+    }
+
+    /// A member name in dot notation
+    fn name(&mut self) -> Result<String, JqError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            let allowed = c.is_alphabetic() || c == '_' || !c.is_ascii() || c.is_ascii_digit();
+            if !allowed || (self.pos == start && c.is_ascii_digit()) {
+                break;
+            }
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return Err(self.error("expected a member name"));
+        }
+        Ok(self.chars[start..self.pos].iter().map(|c| c.1).collect())
+    }
+
+    /// `[selector, ...]`
+    fn bracket(&mut self) -> Result<Vec<Selector>, JqError> {
+        self.expect('[')?;
+        let mut selectors = Vec::new();
+        loop {
+            self.skip_space();
+            selectors.push(self.selector()?);
+            self.skip_space();
+            if self.eat(']') {
+                return Ok(selectors);
+            }
+            self.expect(',')?;
+        }
+    }
+
+    fn selector(&mut self) -> Result<Selector, JqError> {
+        match self.peek() {
+            Some('\'' | '"') => Ok(Selector::Name(self.string()?)),
+            Some('*') => {
+                self.pos += 1;
+                Ok(Selector::Wildcard)
+            }
+            Some('?') => {
+                self.pos += 1;
+                self.skip_space();
+                Ok(Selector::Filter(self.or()?))
+            }
+            _ => {
+                let start = self.integer()?;
+                self.skip_space();
+                if !self.eat(':') {
+                    return match start {
+                        Some(n) => Ok(Selector::Index(n)),
+                        None => Err(self.error("expected a selector")),
+                    };
+                }
+                self.skip_space();
+                let end = self.integer()?;
+                self.skip_space();
+                let step = if self.eat(':') {
+                    self.skip_space();
+                    self.integer()?
+                } else {
+                    None
+                };
+                if step.is_some_and(|step| step < 0) {
+                    return Err(self.error("negative slice steps are not supported"));
+                }
+                Ok(Selector::Slice(start, end, step))
+            }
+        }
+    }
+
+    fn integer(&mut self) -> Result<Option<i64>, JqError> {
+        let start = self.pos;
+        self.eat('-');
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().map(|c| c.1).collect();
+        match digits.as_str() {
+            "" => Ok(None),
+            _ => digits
+                .parse()
+                .map(Some)
+                .map_err(|_| self.error("expected an integer")),
+        }
+    }
+
+    /// A string in single or double quotes, with JSON's escapes plus `\'`
+    fn string(&mut self) -> Result<String, JqError> {
+        let quote = self.peek().unwrap();
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(self.error("unterminated string")),
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some('\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let hex: String = (1..=4).filter_map(|i| self.peek_at(i)).collect();
+                            let code = u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .filter(|_| hex.len() == 4)
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid \\u escape"))?;
+                            self.pos += 4;
+                            code
+                        }
+                        Some(c @ ('\\' | '/' | '\'' | '"')) => c,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    self.pos += 1;
+                    s.push(escaped);
+                }
+                Some(c) => {
+                    self.pos += 1;
+                    s.push(c);
+                }
+            }
+        }
+    }
+
+    /// `a || b`
+    fn or(&mut self) -> Result<Filter, JqError> {
+        let mut lhs = self.and()?;
+        while self.eat_str("||") {
+            self.skip_space();
+            lhs = Filter::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    /// `a && b`
+    fn and(&mut self) -> Result<Filter, JqError> {
+        let mut lhs = self.basic()?;
+        while self.eat_str("&&") {
+            self.skip_space();
+            lhs = Filter::And(Box::new(lhs), Box::new(self.basic()?));
+        }
+        Ok(lhs)
+    }
+
+    /// A negation, parenthesised expression, comparison or existence test
+    fn basic(&mut self) -> Result<Filter, JqError> {
+        let cond = if self.eat('!') {
+            self.skip_space();
+            pipe(self.basic()?, call("not", vec![]))
+        } else if self.eat('(') {
+            self.skip_space();
+            let cond = self.or()?;
+            self.expect(')')?;
+            cond
+        } else {
+            let lhs = self.operand()?;
+            self.skip_space();
+            match self.comparison() {
+                Some(op) => {
+                    self.skip_space();
+                    let rhs = self.operand()?.compile();
+                    compare(op, lhs.compile(), rhs)
+                }
+                None => match lhs {
+                    Operand::Query(query) => {
+                        pipe(call("isempty", vec![query]), call("not", vec![]))
+                    }
+                    Operand::Literal(_) => return Err(self.error("expected a comparison")),
+                },
+            }
+        };
+        self.skip_space();
+        Ok(cond)
+    }
+
+    fn comparison(&mut self) -> Option<BinaryOp> {
+        let ops = [
+            ("==", BinaryOp::Eq),
+            ("!=", BinaryOp::Ne),
+            ("<=", BinaryOp::Le),
+            (">=", BinaryOp::Ge),
+            ("<", BinaryOp::Lt),
+            (">", BinaryOp::Gt),
+        ];
+        ops.into_iter()
+            .find(|(s, _)| self.eat_str(s))
+            .map(|(_, op)| op)
+    }
+
+    fn operand(&mut self) -> Result<Operand, JqError> {
+        if self.eat('@') {
+            return Ok(Operand::Query(self.segments()?));
+        }
+        if self.eat('$') {
+            self.uses_root = true;
+            let query = self.segments()?;
+            return Ok(Operand::Query(pipe(Filter::Var(ROOT.to_string()), query)));
+        }
+        if matches!(self.peek(), Some('\'' | '"')) {
+            return Ok(Operand::Literal(Value::String(self.string()?)));
+        }
+        for (word, value) in [
+            ("true", Value::Bool(true)),
+            ("false", Value::Bool(false)),
+            ("null", Value::Null),
+        ] {
+            if self.eat_str(word) {
+                return Ok(Operand::Literal(value));
+            }
+        }
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            self.pos += 1;
+        }
+        let number: String = self.chars[start..self.pos].iter().map(|c| c.1).collect();
+        match serde_json::from_str::<serde_json::Number>(&number) {
+            Ok(n) => Ok(Operand::Literal(Value::Number(Number::from(&n)))),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error("expected a value or query"))
+            }
+        }
+    }
+}
+
+/// Selectors separated by commas inside one pair of brackets
+fn union(selectors: Vec<Selector>) -> Filter {
+    selectors
+        .into_iter()
+        .map(Selector::compile)
+        .reduce(|lhs, rhs| Filter::Comma(Box::new(lhs), Box::new(rhs)))
+        .unwrap_or_else(|| call("empty", vec![]))
+}
+
+/// JSONPath only orders values of the same type, where jq orders any two values
+fn compare(op: BinaryOp, lhs: Filter, rhs: Filter) -> Filter {
+    let comparison = Filter::Binary(op, Box::new(lhs.clone()), Box::new(rhs.clone()));
+    match op {
+        BinaryOp::Eq | BinaryOp::Ne => comparison,
+        _ => Filter::And(
+            Box::new(Filter::Binary(
+                BinaryOp::Eq,
+                Box::new(pipe(lhs, call("type", vec![]))),
+                Box::new(pipe(rhs, call("type", vec![]))),
+            )),
+            Box::new(comparison),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{eval, Env};
+    use crate::value::json;
+    use std::rc::Rc;
+
+    fn store() -> Value {
+        json!({
+            "store": {
+                "book": [
+                    {"category": "reference", "author": "Nigel Rees", "title": "Sayings of the Century", "price": 8.95},
+                    {"category": "fiction", "author": "Evelyn Waugh", "title": "Sword of Honour", "price": 12.99},
+                    {"category": "fiction", "author": "Herman Melville", "title": "Moby Dick", "isbn": "0-553-21311-3", "price": 8.99},
+                    {"category": "fiction", "author": "J. R. R. Tolkien", "title": "The Lord of the Rings", "isbn": "0-395-19395-8", "price": 22.99}
+                ],
+                "bicycle": {"color": "red", "price": 399}
+            },
+            "limit": 10
+        })
+    }
+
+    fn run(filter: Result<Filter, JqError>, input: Value) -> Vec<Value> {
+        let filter = filter.unwrap();
+        let env = Env::root();
+        let outputs = eval(&filter, &env, Rc::new(input))
+            .map(|output| Rc::unwrap_or_clone(output.unwrap()))
+            .collect();
+        outputs
+    }
+
+    fn path(query: &str) -> Vec<Value> {
+        run(jsonpath(query), store())
+    }
+
+    #[test]
+    fn test_jsonpath_selectors() {
+        assert_eq!(
+            path("$.store.book[*].author"),
+            vec![
+                json!("Nigel Rees"),
+                json!("Evelyn Waugh"),
+                json!("Herman Melville"),
+                json!("J. R. R. Tolkien")
+            ]
+        );
+        assert_eq!(path("$['store']['bicycle'].color"), vec![json!("red")]);
+        assert_eq!(path("$.store.book[-1].price"), vec![json!(22.99)]);
+        assert_eq!(
+            path("$.store.book[0, 2].price"),
+            vec![json!(8.95), json!(8.99)]
+        );
+        assert_eq!(
+            path("$.store.book[1:3].price"),
+            vec![json!(12.99), json!(8.99)]
+        );
+        assert_eq!(
+            path("$.store.book[::2].price"),
+            vec![json!(8.95), json!(8.99)]
+        );
+        assert_eq!(path("$.store.book[4]"), Vec::<Value>::new());
+        assert_eq!(path("$.store.missing"), Vec::<Value>::new());
+        assert_eq!(path("$.limit.deeper"), Vec::<Value>::new());
+        assert_eq!(path("$"), vec![store()]);
+    }
+
+    #[test]
+    fn test_jsonpath_descendants() {
+        assert_eq!(
+            path("$..price"),
+            vec![
+                json!(8.95),
+                json!(12.99),
+                json!(8.99),
+                json!(22.99),
+                json!(399)
+            ]
+        );
+        assert_eq!(path("$..book[2].title"), vec![json!("Moby Dick")]);
+        assert_eq!(path("$.store.*").len(), 2);
+    }
+
+    #[test]
+    fn test_jsonpath_filters() {
+        assert_eq!(
+            path("$..book[?@.isbn].title"),
+            vec![json!("Moby Dick"), json!("The Lord of the Rings")]
+        );
+        assert_eq!(
+            path("$..book[?(@.price < 10 && @.category == 'fiction')].title"),
+            vec![json!("Moby Dick")]
+        );
+        assert_eq!(
+            path("$.store.book[?@.price > $.limit].title"),
+            vec![json!("Sword of Honour"), json!("The Lord of the Rings")]
+        );
+        assert_eq!(path("$.store.book[?!@.isbn]").len(), 2);
+        // Only values of one type are ordered
+        assert_eq!(path("$.store.book[?@.author < 5]").len(), 0);
+    }
+
+    #[test]
+    fn test_jsonpath_errors() {
+        for query in [
+            "store",
+            "$.",
+            "$[",
+            "$[1",
+            "$['a]",
+            "$[?@.a ==]",
+            "$[::-1]",
+            "$.a b",
+        ] {
+            assert!(
+                matches!(jsonpath(query), Err(JqError::Syntax(_))),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_pointer() {
+        assert_eq!(
+            run(pointer("/store/book/0/author"), store()),
+            vec![json!("Nigel Rees")]
+        );
+        assert_eq!(run(pointer(""), store()), vec![store()]);
+        assert_eq!(run(pointer("/0"), json!({"0": "key"})), vec![json!("key")]);
+        assert_eq!(
+            run(pointer("/a~1b/m~0n"), json!({"a/b": {"m~n": 1}})),
+            vec![json!(1)]
+        );
+        assert_eq!(run(pointer("/0"), json!(["x"])), vec![json!("x")]);
+
+        let error = |text: &str, input: Value| {
+            let filter = pointer(text).unwrap();
+            let env = Env::root();
+            let mut outputs = eval(&filter, &env, Rc::new(input));
+            outputs.next().unwrap().unwrap_err().to_string()
+        };
+        assert_eq!(
+            error("/store/missing/x", store()),
+            "JSON pointer /store/missing does not resolve"
+        );
+        assert_eq!(
+            error("/limit/0", store()),
+            "JSON pointer /limit/0 does not resolve"
+        );
+        assert_eq!(error("/5", json!([1])), "JSON pointer /5 does not resolve");
+        assert_eq!(error("/-", json!([1])), "JSON pointer /- does not resolve");
+        assert_eq!(
+            error("/01", json!([1, 2])),
+            "JSON pointer /01 does not resolve"
+        );
+        assert_eq!(
+            error("/a", json!({"b": null})),
+            "JSON pointer /a does not resolve"
+        );
+        assert!(matches!(pointer("store"), Err(JqError::Syntax(_))));
+    }
+}
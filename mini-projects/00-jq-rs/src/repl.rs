@@ -0,0 +1,251 @@
+use jq_rs::eval::{eval, Env};
+use jq_rs::parser;
+use jq_rs::value::Value;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor, Helper, Highlighter, Hinter, Validator};
+use std::rc::Rc;
+
+use crate::Output;
+
+/// How many outputs of the path before the cursor are inspected for keys
+const COMPLETION_SAMPLE: usize = 100;
+
+/// Read filters from a prompt and print their results against `document`, which is only
+/// loaded once and shared by every filter and by key completion. `globals` are the
+/// variables given on the command line
+pub fn run(document: Value, globals: &[(String, Value)], output: &mut Output) {
+    let document = Rc::new(document);
+    let mut editor = match Editor::<KeyCompleter, DefaultHistory>::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("jq: error: cannot start the interactive prompt: {}", e);
+            return;
+        }
+    };
+    editor.set_helper(Some(KeyCompleter {
+        document: Rc::clone(&document),
+        globals: globals.to_vec(),
+    }));
+    println!("Type a filter to run it, Tab to complete keys, or :quit to exit");
+    loop {
+        match editor.readline("jq> ") {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let _ = editor.add_history_entry(line);
+                if line == ":q" || line == ":quit" {
+                    break;
+                }
+                evaluate(line, &document, globals, output);
+            }
+            // Ctrl-C abandons the current line, Ctrl-D leaves
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("jq: error: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+fn environment<'a>(globals: &[(String, Value)]) -> Env<'a> {
+    globals.iter().fold(Env::root(), |env, (name, value)| {
+        env.bind_var(name.clone(), value.clone())
+    })
+}
+
+/// Run one filter, reporting errors without leaving the prompt
+fn evaluate(program: &str, document: &Rc<Value>, globals: &[(String, Value)], output: &mut Output) {
+    let filter = match parser::parse(program) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("jq: error: {}", e);
+            return;
+        }
+    };
+    let env = environment(globals);
+    for result in eval(&filter, &env, Rc::clone(document)) {
+        match result.and_then(|result| output.render(Rc::unwrap_or_clone(result))) {
+            Ok(text) => println!("{}", text),
+            Err(e) => {
+                eprintln!("jq: error: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+#[derive(Helper, Hinter, Highlighter, Validator)]
+struct KeyCompleter {
+    document: Rc<Value>,
+    globals: Vec<(String, Value)>,
+}
+
+impl Completer for KeyCompleter {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, keys) = complete_keys(&self.document, &self.globals, &line[..pos]);
+        let candidates = keys
+            .into_iter()
+            .map(|key| Pair {
+                display: key.clone(),
+                replacement: key,
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+/// Complete the object key being typed at the end of `before`, such as `na` in
+/// `.items[] | .na`. The path leading up to it, and anything piped into that path, is
+/// run against the document to find which keys exist there. Returns where the
+/// replacement starts and the keys, quoted when they are not plain identifiers
+fn complete_keys(
+    document: &Rc<Value>,
+    globals: &[(String, Value)],
+    before: &str,
+) -> (usize, Vec<String>) {
+    let path_start = before
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_alphanumeric() || "_.[]?".contains(*c))
+        .last()
+        .map_or(before.len(), |(i, _)| i);
+    // A path starts with a dot, so `[.a` completes `.a`
+    let path_start = before[path_start..]
+        .find('.')
+        .map_or(before.len(), |dot| path_start + dot);
+    let expr = &before[path_start..];
+    let dot = match expr.rfind('.') {
+        Some(dot) => dot,
+        None => return (before.len(), Vec::new()),
+    };
+    let partial = &expr[dot + 1..];
+    if !partial.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return (before.len(), Vec::new());
+    }
+
+    let path = match &expr[..dot] {
+        "" => ".",
+        path => path,
+    };
+    let context = before[..path_start].trim_end();
+    let program = if context.ends_with('|') {
+        format!("{} {}", context, path)
+    } else {
+        path.to_string()
+    };
+    let filter = match parser::parse(&program).or_else(|_| parser::parse(path)) {
+        Ok(filter) => filter,
+        Err(_) => return (before.len(), Vec::new()),
+    };
+    let env = environment(globals);
+    let mut keys: Vec<String> = Vec::new();
+    for value in eval(&filter, &env, Rc::clone(document))
+        .take(COMPLETION_SAMPLE)
+        .flatten()
+    {
+        if let Value::Object(obj) = &*value {
+            for key in obj.keys() {
+                if key.starts_with(partial) && !keys.contains(key) {
+                    keys.push(key.clone());
+                }
+            }
+        }
+    }
+    keys.sort();
+    let candidates = keys
+        .into_iter()
+        .map(|key| {
+            if is_identifier(&key) {
+                key
+            } else {
+                Value::String(key).to_string()
+            }
+        })
+        .collect();
+    (path_start + dot + 1, candidates)
+}
+
+/// Whether `.key` can be written without quotes
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn complete(document: &serde_json::Value, before: &str) -> (usize, Vec<String>) {
+        complete_keys(&Rc::new(Value::from(document.clone())), &[], before)
+    }
+
+    #[test]
+    fn test_complete_top_level_keys() {
+        let document = json!({"name": "x", "nested": {"a": 1}, "other": 2});
+        assert_eq!(
+            complete(&document, ".n"),
+            (1, vec!["name".to_string(), "nested".to_string()])
+        );
+        assert_eq!(
+            complete(&document, "."),
+            (
+                1,
+                vec![
+                    "name".to_string(),
+                    "nested".to_string(),
+                    "other".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_complete_nested_and_piped_paths() {
+        let document = json!({"items": [{"id": 1, "tag": "a"}, {"id": 2, "size": 3}]});
+        assert_eq!(
+            complete(&document, "[.items[]."),
+            (
+                10,
+                vec!["id".to_string(), "size".to_string(), "tag".to_string()]
+            )
+        );
+        assert_eq!(
+            complete(&document, ".items[] | .s"),
+            (12, vec!["size".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_complete_quotes_unusual_keys() {
+        let document = json!({"a b": 1, "a_c": 2});
+        assert_eq!(
+            complete(&document, ".a"),
+            (1, vec!["\"a b\"".to_string(), "a_c".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_no_completion_outside_paths() {
+        let document = json!({"a": 1});
+        assert_eq!(complete(&document, "length"), (6, Vec::new()));
+        assert_eq!(complete(&document, ".missing."), (9, Vec::new()));
+        assert_eq!(complete(&document, ".a[0]"), (5, Vec::new()));
+    }
+}
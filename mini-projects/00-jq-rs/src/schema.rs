@@ -0,0 +1,614 @@
+//! JSON Schema inference from sample data, and validation of data against a schema.
+//!
+//! Validation covers the keywords that describe the shape of data: `type`, `enum`,
+//! `const`, the numeric, string, array and object bounds, `properties`, `required`,
+//! `additionalProperties`, `items`, `prefixItems`, the `allOf`/`anyOf`/`oneOf`/`not`
+//! combinators and `$ref`s within the same schema. Other keywords, such as `format`,
+//! are ignored.
+
+use std::fmt;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use crate::diff::jq_path;
+use crate::error::JqError;
+use crate::filters;
+use crate::value::{json, Map, Value};
+
+const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// How deeply schemas may nest, counting `$ref`s, before a schema is taken to refer
+/// to itself without end
+const MAX_DEPTH: usize = 256;
+
+/// The types and structure seen across the samples of one value
+#[derive(Debug, Default)]
+struct Shape {
+    null: bool,
+    boolean: bool,
+    integer: bool,
+    number: bool,
+    string: bool,
+    /// The shape of all the elements of all the arrays seen
+    array: Option<Box<Shape>>,
+    object: Option<Fields>,
+}
+
+#[derive(Debug, Default)]
+struct Fields {
+    /// How many objects were seen
+    objects: usize,
+    /// Every property seen, with the number of objects that had it
+    properties: Vec<(String, usize, Shape)>,
+}
+
+impl Shape {
+    fn add(&mut self, value: &Value) {
+        match value {
+            Value::Null => self.null = true,
+            Value::Bool(_) => self.boolean = true,
+            Value::Number(n) if n.as_f64().fract() == 0.0 => self.integer = true,
+            Value::Number(_) => self.number = true,
+            Value::String(_) => self.string = true,
+            Value::Array(elements) => {
+                let items = self.array.get_or_insert_with(Default::default);
+                for element in elements.iter() {
+                    items.add(element);
+                }
+            }
+            Value::Object(map) => {
+                let fields = self.object.get_or_insert_with(Default::default);
+                fields.objects += 1;
+                for (key, value) in map.iter() {
+                    let position = fields.properties.iter().position(|(name, ..)| name == key);
+                    let index = position.unwrap_or_else(|| {
+                        fields.properties.push((key.clone(), 0, Shape::default()));
+                        fields.properties.len() - 1
+                    });
+                    let (_, count, shape) = &mut fields.properties[index];
+                    *count += 1;
+                    shape.add(value);
+                }
+            }
+        }
+    }
+
+    fn to_schema(&self, strict: bool) -> Value {
+        let mut types = Vec::new();
+        let flags = [
+            (self.null, "null"),
+            (self.boolean, "boolean"),
+            // Integers are numbers too
+            (self.integer && !self.number, "integer"),
+            (self.number, "number"),
+            (self.string, "string"),
+            (self.array.is_some(), "array"),
+            (self.object.is_some(), "object"),
+        ];
+        for (seen, name) in flags {
+            if seen {
+                types.push(json!(name));
+            }
+        }
+        let mut schema = Map::new();
+        match types.len() {
+            // Only empty arrays were seen, so their elements could be anything
+            0 => return json!({}),
+            1 => schema.insert("type".into(), types.remove(0)),
+            _ => schema.insert("type".into(), Value::from(types)),
+        };
+        if let Some(items) = &self.array {
+            let items = items.to_schema(strict);
+            if items != json!({}) {
+                schema.insert("items".into(), items);
+            }
+        }
+        if let Some(fields) = &self.object {
+            let mut properties = Map::new();
+            let mut required = Vec::new();
+            for (name, count, shape) in &fields.properties {
+                properties.insert(name.clone(), shape.to_schema(strict));
+                if *count == fields.objects {
+                    required.push(json!(name));
+                }
+            }
+            schema.insert("properties".into(), Value::from(properties));
+            if !required.is_empty() {
+                schema.insert("required".into(), Value::from(required));
+            }
+            if strict {
+                schema.insert("additionalProperties".into(), Value::Bool(false));
+            }
+        }
+        Value::from(schema)
+    }
+}
+
+/// A schema that every one of `samples` satisfies. The elements of an array are
+/// merged into a single `items` schema, and a property is only required if every
+/// object seen in that place has it. A `strict` schema also rejects properties that
+/// were never seen
+pub fn infer<'v>(samples: impl IntoIterator<Item = &'v Value>, strict: bool) -> Value {
+    let mut shape = Shape::default();
+    for sample in samples {
+        shape.add(sample);
+    }
+    let mut schema = Map::new();
+    schema.insert("$schema".into(), json!(DIALECT));
+    if let Value::Object(inferred) = shape.to_schema(strict) {
+        schema.extend(Arc::unwrap_or_clone(inferred));
+    }
+    Value::from(schema)
+}
+
+/// A place where the data does not match the schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub path: Vec<Value>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", jq_path(&self.path), self.message)
+    }
+}
+
+/// Every place where `data` breaks `schema`, rather than only the first. Fails if
+/// the schema itself is malformed
+pub fn validate(schema: &Value, data: &Value) -> Result<Vec<Violation>, JqError> {
+    let mut validator = Validator {
+        root: schema,
+        path: Vec::new(),
+        depth: 0,
+        violations: Vec::new(),
+    };
+    validator.check(schema, data)?;
+    Ok(validator.violations)
+}
+
+struct Validator<'s> {
+    root: &'s Value,
+    path: Vec<Value>,
+    depth: usize,
+    violations: Vec<Violation>,
+}
+
+fn invalid(message: String) -> JqError {
+    JqError::Input(format!("invalid schema: {}", message))
+}
+
+fn has_type(data: &Value, name: &str) -> bool {
+    match name {
+        "integer" => data.as_f64().is_some_and(|n| n.fract() == 0.0),
+        name => filters::type_name(data) == name,
+    }
+}
+
+impl<'s> Validator<'s> {
+    fn report(&mut self, message: String) {
+        self.violations.push(Violation {
+            path: self.path.clone(),
+            message,
+        });
+    }
+
+    /// Whether `data` satisfies `schema`, without reporting anything
+    fn passes(&self, schema: &'s Value, data: &Value) -> Result<bool, JqError> {
+        let mut inner = Validator {
+            root: self.root,
+            path: self.path.clone(),
+            depth: self.depth,
+            violations: Vec::new(),
+        };
+        inner.check(schema, data)?;
+        Ok(inner.violations.is_empty())
+    }
+
+    fn check_at(&mut self, step: Value, schema: &'s Value, data: &Value) -> Result<(), JqError> {
+        self.path.push(step);
+        let result = self.check(schema, data);
+        self.path.pop();
+        result
+    }
+
+    fn check(&mut self, schema: &'s Value, data: &Value) -> Result<(), JqError> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok(()),
+            Value::Bool(false) => {
+                self.report("no value is allowed here".into());
+                return Ok(());
+            }
+            Value::Object(schema) => schema,
+            other => return Err(invalid(format!("{} is not a schema", other))),
+        };
+        if self.depth == MAX_DEPTH {
+            return Err(invalid("it refers to itself without end".into()));
+        }
+        self.depth += 1;
+        let result = self.check_keywords(schema, data);
+        self.depth -= 1;
+        result
+    }
+
+    fn check_keywords(
+        &mut self,
+        schema: &'s Map<String, Value>,
+        data: &Value,
+    ) -> Result<(), JqError> {
+        if let Some(reference) = schema.get("$ref") {
+            let target = self.resolve(reference)?;
+            self.check(target, data)?;
+        }
+        if let Some(types) = schema.get("type") {
+            let names: Vec<&str> = match types {
+                Value::String(name) => vec![name],
+                Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+                other => return Err(invalid(format!("type {} is not a string", other))),
+            };
+            if !names.iter().any(|name| has_type(data, name)) {
+                self.report(format!(
+                    "expected {}, found {}",
+                    names.join(" or "),
+                    filters::describe(data)
+                ));
+                // The other keywords would only repeat the complaint
+                return Ok(());
+            }
+        }
+        if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+            if !allowed.iter().any(|value| value == data) {
+                self.report(format!(
+                    "{} is not one of {}",
+                    data,
+                    Value::from(allowed.clone())
+                ));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != data {
+                self.report(format!("expected {}, found {}", expected, data));
+            }
+        }
+
+        match data {
+            Value::Number(n) => self.check_number(schema, n.as_f64()),
+            Value::String(s) => self.check_string(schema, s)?,
+            Value::Array(elements) => self.check_array(schema, elements)?,
+            Value::Object(map) => self.check_object(schema, map)?,
+            _ => {}
+        }
+
+        if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+            for sub in all {
+                self.check(sub, data)?;
+            }
+        }
+        if let Some(any) = schema.get("anyOf").and_then(Value::as_array) {
+            let mut matched = false;
+            for sub in any {
+                if self.passes(sub, data)? {
+                    matched = true;
+                    break;
+                }
+            }
+            if !matched {
+                self.report("does not match any of the anyOf schemas".into());
+            }
+        }
+        if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+            let mut matches = 0;
+            for sub in one {
+                if self.passes(sub, data)? {
+                    matches += 1;
+                }
+            }
+            if matches != 1 {
+                self.report(format!(
+                    "matches {} of the oneOf schemas, not exactly one",
+                    matches
+                ));
+            }
+        }
+        if let Some(not) = schema.get("not") {
+            if self.passes(not, data)? {
+                self.report("matches the schema it must not match".into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The schema a `$ref` points to; only references within this schema are followed
+    fn resolve(&self, reference: &Value) -> Result<&'s Value, JqError> {
+        let reference = reference
+            .as_str()
+            .ok_or_else(|| invalid(format!("$ref {} is not a string", reference)))?;
+        let pointer = reference
+            .strip_prefix('#')
+            .ok_or_else(|| invalid(format!("$ref {} is not within the schema", reference)))?;
+        self.root
+            .pointer(pointer)
+            .ok_or_else(|| invalid(format!("$ref {} points to nothing", reference)))
+    }
+
+    fn check_number(&mut self, schema: &Map<String, Value>, n: f64) {
+        let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+        let bounds = [
+            ("minimum", "less than"),
+            ("maximum", "greater than"),
+            ("exclusiveMinimum", "not greater than"),
+            ("exclusiveMaximum", "not less than"),
+        ];
+        for (keyword, complaint) in bounds {
+            let Some(b) = bound(keyword) else { continue };
+            let holds = match keyword {
+                "minimum" => n >= b,
+                "maximum" => n <= b,
+                "exclusiveMinimum" => n > b,
+                _ => n < b,
+            };
+            if !holds {
+                self.report(format!(
+                    "{} is {} {}",
+                    filters::number(n),
+                    complaint,
+                    filters::number(b)
+                ));
+            }
+        }
+        if let Some(divisor) = bound("multipleOf").filter(|d| *d > 0.0) {
+            let quotient = n / divisor;
+            if (quotient - quotient.round()).abs() > 1e-9 {
+                self.report(format!(
+                    "{} is not a multiple of {}",
+                    filters::number(n),
+                    filters::number(divisor)
+                ));
+            }
+        }
+    }
+
+    fn check_string(&mut self, schema: &Map<String, Value>, s: &str) -> Result<(), JqError> {
+        let length = s.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+            if length < min {
+                self.report(format!(
+                    "string is {} characters, fewer than {}",
+                    length, min
+                ));
+            }
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+            if length > max {
+                self.report(format!(
+                    "string is {} characters, more than {}",
+                    length, max
+                ));
+            }
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            let re = Regex::new(pattern)
+                .map_err(|e| invalid(format!("pattern {} is not a valid regex: {}", pattern, e)))?;
+            if !re.is_match(s) {
+                self.report(format!("{} does not match /{}/", Value::from(s), pattern));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_array(
+        &mut self,
+        schema: &'s Map<String, Value>,
+        elements: &[Value],
+    ) -> Result<(), JqError> {
+        let length = elements.len() as u64;
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+            if length < min {
+                self.report(format!("array has {} items, fewer than {}", length, min));
+            }
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+            if length > max {
+                self.report(format!("array has {} items, more than {}", length, max));
+            }
+        }
+        if schema.get("uniqueItems") == Some(&Value::Bool(true)) {
+            for (i, element) in elements.iter().enumerate() {
+                if let Some(first) = elements[..i].iter().position(|other| other == element) {
+                    self.report(format!("item {} repeats item {}", i, first));
+                }
+            }
+        }
+        let prefix = match schema.get("prefixItems").and_then(Value::as_array) {
+            Some(prefix) => prefix.as_slice(),
+            None => &[],
+        };
+        for (i, element) in elements.iter().enumerate() {
+            let sub = match prefix.get(i) {
+                Some(sub) => sub,
+                None => match schema.get("items") {
+                    Some(sub) => sub,
+                    None => continue,
+                },
+            };
+            self.check_at(json!(i), sub, element)?;
+        }
+        Ok(())
+    }
+
+    fn check_object(
+        &mut self,
+        schema: &'s Map<String, Value>,
+        map: &Map<String, Value>,
+    ) -> Result<(), JqError> {
+        let count = map.len() as u64;
+        if let Some(min) = schema.get("minProperties").and_then(Value::as_u64) {
+            if count < min {
+                self.report(format!(
+                    "object has {} properties, fewer than {}",
+                    count, min
+                ));
+            }
+        }
+        if let Some(max) = schema.get("maxProperties").and_then(Value::as_u64) {
+            if count > max {
+                self.report(format!(
+                    "object has {} properties, more than {}",
+                    count, max
+                ));
+            }
+        }
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !map.contains_key(name) {
+                    self.report(format!("missing required property {}", Value::from(name)));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (key, value) in map {
+            let sub = match properties.and_then(|properties| properties.get(key)) {
+                Some(sub) => sub,
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        self.path.push(json!(key));
+                        self.report("unexpected property".into());
+                        self.path.pop();
+                        continue;
+                    }
+                    Some(sub) => sub,
+                    None => continue,
+                },
+            };
+            self.check_at(json!(key), sub, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn messages(schema: &Value, data: &Value) -> Vec<String> {
+        validate(schema, data)
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_infer_merges_array_elements() {
+        let data = json!([
+            {"id": 1, "name": "a", "tags": ["x"], "score": 1.5},
+            {"id": 2, "name": null, "tags": [], "extra": {"on": true}}
+        ]);
+        assert_eq!(
+            infer([&data], false),
+            json!({
+                "$schema": DIALECT,
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "integer"},
+                        "name": {"type": ["null", "string"]},
+                        "tags": {"type": "array", "items": {"type": "string"}},
+                        "score": {"type": "number"},
+                        "extra": {
+                            "type": "object",
+                            "properties": {"on": {"type": "boolean"}},
+                            "required": ["on"]
+                        }
+                    },
+                    "required": ["id", "name", "tags"]
+                }
+            })
+        );
+        assert_eq!(
+            infer([&json!([1, 2.5])], false)["items"],
+            json!({"type": "number"})
+        );
+        assert_eq!(
+            infer([&json!([])], false),
+            json!({"$schema": DIALECT, "type": "array"})
+        );
+    }
+
+    #[test]
+    fn test_sample_data_matches_its_own_schema() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/sample_data");
+        for entry in fs::read_dir(dir).unwrap() {
+            let text = fs::read_to_string(entry.unwrap().path()).unwrap();
+            let data: Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(validate(&infer([&data], false), &data), Ok(Vec::new()));
+        }
+    }
+
+    #[test]
+    fn test_validate_reports_every_violation() {
+        let students: Value =
+            serde_json::from_str(include_str!("../sample_data/students.json")).unwrap();
+        let schema = infer([&students], true);
+        let drifted = json!({"metadata": [
+            {"Name": "Steve", "GPA": 4.0, "Age": 23},
+            {"Name": "Bob", "Age": "20", "Email": "bob@example.com"}
+        ]});
+        assert_eq!(
+            messages(&schema, &drifted),
+            vec![
+                ".metadata[0].GPA: expected string, found number (4.0)",
+                ".metadata[1]: missing required property \"GPA\"",
+                ".metadata[1].Age: expected integer, found string (\"20\")",
+                ".metadata[1].Email: unexpected property",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_keywords() {
+        let schema = json!({
+            "$defs": {"positive": {"type": "number", "exclusiveMinimum": 0}},
+            "type": "object",
+            "properties": {
+                "size": {"$ref": "#/$defs/positive"},
+                "name": {"type": "string", "minLength": 2, "pattern": "^[a-z]+$"},
+                "kind": {"enum": ["a", "b"]},
+                "pair": {"type": "array", "prefixItems": [{"type": "string"}], "items": {"type": "integer"}, "maxItems": 3},
+                "either": {"oneOf": [{"type": "string"}, {"const": 1}]},
+                "set": {"uniqueItems": true}
+            },
+            "additionalProperties": false
+        });
+        assert!(messages(&schema, &json!({"size": 1, "name": "ab", "kind": "a", "pair": ["x", 1], "either": 1, "set": [1, 2]})).is_empty());
+        assert_eq!(
+            messages(
+                &schema,
+                &json!({"size": 0, "name": "A", "kind": "c", "pair": [1, 1.5, 2, 3], "either": 2, "set": [1, 1], "more": 1})
+            ),
+            vec![
+                ".size: 0 is not greater than 0",
+                ".name: string is 1 characters, fewer than 2",
+                ".name: \"A\" does not match /^[a-z]+$/",
+                ".kind: \"c\" is not one of [\"a\",\"b\"]",
+                ".pair: array has 4 items, more than 3",
+                ".pair[0]: expected string, found number (1)",
+                ".pair[1]: expected integer, found number (1.5)",
+                ".either: matches 0 of the oneOf schemas, not exactly one",
+                ".set: item 1 repeats item 0",
+                ".more: unexpected property",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_malformed_schemas() {
+        assert!(validate(&json!({"$ref": "#/nowhere"}), &json!(1)).is_err());
+        assert!(validate(&json!({"$ref": "#"}), &json!(1)).is_err());
+        assert!(validate(&json!({"pattern": "("}), &json!("x")).is_err());
+        assert!(validate(&json!(3), &json!(1)).is_err());
+    }
+}
@@ -0,0 +1,519 @@
+//! The values a filter works on.
+//!
+//! These are JSON values, except that a number keeps whatever double arithmetic gave it,
+//! NaN and the infinities included, so that `nan | isnan` and `infinite > 1e308` hold
+//! inside a program. JSON has no way to write those, so, as in jq, they only become
+//! `null` and the largest finite doubles when a value leaves the evaluator: when it is
+//! printed, encoded, or converted back into a `serde_json::Value`.
+
+use std::fmt;
+use std::ops::Index;
+use std::sync::Arc;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+/// Object fields, in insertion order
+pub type Map<K, V> = indexmap::IndexMap<K, V>;
+
+/// What a missing key or position refers to, so lookups can borrow it
+static NULL: Value = Value::Null;
+
+/// A JSON value. Arrays and objects are reference counted, so cloning a value, such as
+/// a field picked out of a large document, never copies its contents; changing a shared
+/// one copies it first (see `Arc::make_mut`)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Arc<Vec<Value>>),
+    Object(Arc<Map<String, Value>>),
+}
+
+/// A number read from the input keeps its exact integer value until something
+/// computes with it; results of arithmetic are doubles and may be NaN or infinite.
+/// Numbers are equal when their values are, so `1` equals `1.0`
+#[derive(Clone, Copy, Debug)]
+pub struct Number(N);
+
+#[derive(Clone, Copy, Debug)]
+enum N {
+    PosInt(u64),
+    NegInt(i64),
+    Float(f64),
+}
+
+impl Number {
+    pub fn as_f64(&self) -> f64 {
+        match self.0 {
+            N::PosInt(n) => n as f64,
+            N::NegInt(n) => n as f64,
+            N::Float(n) => n,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self.0 {
+            N::PosInt(n) => i64::try_from(n).ok(),
+            N::NegInt(n) => Some(n),
+            N::Float(_) => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self.0 {
+            N::PosInt(n) => Some(n),
+            N::NegInt(_) | N::Float(_) => None,
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        self.as_f64().is_nan()
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.0, other.0) {
+            (N::PosInt(a), N::PosInt(b)) => a == b,
+            (N::NegInt(a), N::NegInt(b)) => a == b,
+            _ => self.as_f64() == other.as_f64(),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    /// The number as JSON, so NaN is `null` and the infinities are clamped
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        serde_json::Value::from(*self).fmt(f)
+    }
+}
+
+impl From<i64> for Number {
+    fn from(n: i64) -> Self {
+        if n < 0 {
+            Number(N::NegInt(n))
+        } else {
+            Number(N::PosInt(n as u64))
+        }
+    }
+}
+
+impl From<u64> for Number {
+    fn from(n: u64) -> Self {
+        Number(N::PosInt(n))
+    }
+}
+
+impl From<usize> for Number {
+    fn from(n: usize) -> Self {
+        Number(N::PosInt(n as u64))
+    }
+}
+
+impl From<i32> for Number {
+    fn from(n: i32) -> Self {
+        Number::from(i64::from(n))
+    }
+}
+
+impl From<f64> for Number {
+    fn from(n: f64) -> Self {
+        Number(N::Float(n))
+    }
+}
+
+impl From<&serde_json::Number> for Number {
+    fn from(n: &serde_json::Number) -> Self {
+        if let Some(n) = n.as_u64() {
+            Number(N::PosInt(n))
+        } else if let Some(n) = n.as_i64() {
+            Number(N::NegInt(n))
+        } else {
+            Number(N::Float(n.as_f64().unwrap_or(0.0)))
+        }
+    }
+}
+
+impl From<Number> for serde_json::Value {
+    /// NaN becomes `null` and the infinities the largest finite doubles, as jq prints them
+    fn from(n: Number) -> Self {
+        match n.0 {
+            N::PosInt(n) => serde_json::Value::from(n),
+            N::NegInt(n) => serde_json::Value::from(n),
+            N::Float(n) if n.is_nan() => serde_json::Value::Null,
+            N::Float(n) if n.is_infinite() => serde_json::Value::from(f64::MAX.copysign(n)),
+            N::Float(n) => serde_json::Value::from(n),
+        }
+    }
+}
+
+impl Serialize for Number {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            N::PosInt(n) => serializer.serialize_u64(n),
+            N::NegInt(n) => serializer.serialize_i64(n),
+            N::Float(n) if n.is_nan() => serializer.serialize_unit(),
+            N::Float(n) if n.is_infinite() => serializer.serialize_f64(f64::MAX.copysign(n)),
+            N::Float(n) => serializer.serialize_f64(n),
+        }
+    }
+}
+
+impl Value {
+    /// The field of an object or the element of an array, if there is one
+    pub fn get<I: Key>(&self, key: I) -> Option<&Value> {
+        key.find(self)
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&Map<String, Value>> {
+        match self {
+            Value::Object(obj) => Some(obj),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(n.as_f64()),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) => n.as_u64(),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    pub fn is_array(&self) -> bool {
+        matches!(self, Value::Array(_))
+    }
+
+    pub fn is_object(&self) -> bool {
+        matches!(self, Value::Object(_))
+    }
+
+    /// The value an RFC 6901 JSON Pointer such as `/a/0` refers to, if any
+    pub fn pointer(&self, pointer: &str) -> Option<&Value> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        pointer
+            .strip_prefix('/')?
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .try_fold(self, |target, token| match target {
+                Value::Object(obj) => obj.get(&token),
+                // Array positions are plain decimal, without a sign or leading zeros
+                Value::Array(array) if token == "0" || !token.starts_with(['0', '+']) => {
+                    array.get(token.parse::<usize>().ok()?)
+                }
+                _ => None,
+            })
+    }
+
+    /// Replace the value with `null`, returning what was there
+    pub fn take(&mut self) -> Value {
+        std::mem::take(self)
+    }
+}
+
+/// Something a value can be looked up by: a field name or an array position
+pub trait Key {
+    fn find<'v>(&self, value: &'v Value) -> Option<&'v Value>;
+}
+
+impl Key for usize {
+    fn find<'v>(&self, value: &'v Value) -> Option<&'v Value> {
+        value.as_array()?.get(*self)
+    }
+}
+
+impl Key for str {
+    fn find<'v>(&self, value: &'v Value) -> Option<&'v Value> {
+        value.as_object()?.get(self)
+    }
+}
+
+impl Key for String {
+    fn find<'v>(&self, value: &'v Value) -> Option<&'v Value> {
+        Key::find(self.as_str(), value)
+    }
+}
+
+impl<K: Key + ?Sized> Key for &K {
+    fn find<'v>(&self, value: &'v Value) -> Option<&'v Value> {
+        (**self).find(value)
+    }
+}
+
+impl<I: Key> Index<I> for Value {
+    type Output = Value;
+
+    /// The field or element at `key`, or `null` when there is none
+    fn index(&self, key: I) -> &Value {
+        key.find(self).unwrap_or(&NULL)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Number(n) => n.serialize(serializer),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Array(array) => {
+                let mut seq = serializer.serialize_seq(Some(array.len()))?;
+                for item in array.iter() {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Value::Object(obj) => {
+                let mut map = serializer.serialize_map(Some(obj.len()))?;
+                for (key, value) in obj.iter() {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Value, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Value, E> {
+        Ok(Value::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Value, E> {
+        Ok(Value::from(n))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<Value, E> {
+        Ok(Value::from(n))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Value, E> {
+        Ok(Value::from(n))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Value, E> {
+        Ok(Value::from(s))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Value, E> {
+        Ok(Value::String(s))
+    }
+
+    fn visit_unit<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        Deserialize::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut array = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element()? {
+            array.push(item);
+        }
+        Ok(Value::from(array))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut obj = Map::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((key, value)) = map.next_entry()? {
+            obj.insert(key, value);
+        }
+        Ok(Value::from(obj))
+    }
+}
+
+impl fmt::Display for Value {
+    /// Compact JSON, or indented JSON with `{:#}`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = if f.alternate() {
+            serde_json::to_string_pretty(self)
+        } else {
+            serde_json::to_string(self)
+        };
+        f.write_str(&text.map_err(|_| fmt::Error)?)
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(b) => Value::Bool(b),
+            serde_json::Value::Number(n) => Value::Number(Number::from(&n)),
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(array) => {
+                Value::from(array.into_iter().map(Value::from).collect::<Vec<_>>())
+            }
+            serde_json::Value::Object(obj) => Value::from(
+                obj.into_iter()
+                    .map(|(key, value)| (key, Value::from(value)))
+                    .collect::<Map<_, _>>(),
+            ),
+        }
+    }
+}
+
+impl From<Value> for serde_json::Value {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(b),
+            Value::Number(n) => serde_json::Value::from(n),
+            Value::String(s) => serde_json::Value::String(s),
+            Value::Array(array) => serde_json::Value::Array(
+                Arc::unwrap_or_clone(array)
+                    .into_iter()
+                    .map(serde_json::Value::from)
+                    .collect(),
+            ),
+            Value::Object(obj) => serde_json::Value::Object(
+                Arc::unwrap_or_clone(obj)
+                    .into_iter()
+                    .map(|(key, value)| (key, serde_json::Value::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(array: Vec<Value>) -> Self {
+        Value::Array(Arc::new(array))
+    }
+}
+
+impl From<Map<String, Value>> for Value {
+    fn from(obj: Map<String, Value>) -> Self {
+        Value::Object(Arc::new(obj))
+    }
+}
+
+macro_rules! from_number {
+    ($($t:ty)*) => {$(
+        impl From<$t> for Value {
+            fn from(n: $t) -> Self {
+                Value::Number(Number::from(n))
+            }
+        }
+    )*};
+}
+
+from_number!(i32 i64 u64 usize f64);
+
+/// `serde_json::json!`, producing a `Value`. Interpolated values are serialized, so any NaN
+/// or infinity in them is converted as when printed
+macro_rules! json {
+    ($($json:tt)+) => {
+        $crate::value::Value::from(serde_json::json!($($json)+))
+    };
+}
+
+pub(crate) use json;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_non_finite_numbers_leave_as_json() {
+        let value = Value::from(vec![
+            Value::from(f64::NAN),
+            Value::from(f64::INFINITY),
+            Value::from(f64::NEG_INFINITY),
+            Value::from(u64::MAX),
+        ]);
+        let expected = serde_json::json!([null, f64::MAX, -f64::MAX, u64::MAX]);
+        assert_eq!(value.to_string(), expected.to_string());
+        assert_eq!(serde_json::Value::from(value), expected);
+    }
+
+    #[test]
+    fn test_numbers_are_equal_by_value() {
+        assert_eq!(Value::from(1), Value::from(1.0));
+        assert_eq!(Value::from(-2), Value::from(-2.0));
+        assert_ne!(Value::from(1), Value::from(1.5));
+        assert_ne!(Value::from(-1), Value::from(1u64));
+        assert_ne!(Value::from(f64::NAN), Value::from(f64::NAN));
+        assert_eq!(json!({"a": [1, 2.0]}), json!({"a": [1.0, 2]}));
+    }
+
+    #[test]
+    fn test_parse_and_pointer() {
+        let value: Value = serde_json::from_str(r#"{"a/b": [1, -2, 2.5, {"~": null}]}"#).unwrap();
+        assert_eq!(value, json!({"a/b": [1, -2, 2.5, {"~": null}]}));
+        assert_eq!(value.pointer("/a~1b/1"), Some(&Value::from(-2)));
+        assert_eq!(value.pointer("/a~1b/3/~0"), Some(&Value::Null));
+        assert_eq!(value.pointer("/a~1b/01"), None);
+        assert_eq!(value.pointer(""), Some(&value));
+        assert_eq!(value["a/b"][2], Value::from(2.5));
+        assert_eq!(value["missing"][0], Value::Null);
+    }
+}
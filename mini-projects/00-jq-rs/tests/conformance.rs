@@ -0,0 +1,33 @@
+//! Runs the jq conformance cases and prints how many pass.
+//!
+//! `cargo test --test conformance` runs the cases bundled in `tests/jq.test`. Set
+//! `JQ_TEST` to the path of another file, such as jq's own `tests/jq.test`, to run
+//! that instead.
+
+use jq_rs::conformance::{self, Report};
+use std::{env, fs, process};
+
+/// The run fails if fewer of the bundled cases than this pass, so that a change
+/// which breaks compatibility is noticed
+const MIN_COMPATIBILITY: f64 = 70.0;
+
+fn main() {
+    let custom = env::var("JQ_TEST").ok();
+    let path = custom
+        .clone()
+        .unwrap_or_else(|| format!("{}/tests/jq.test", env!("CARGO_MANIFEST_DIR")));
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("Could not open {}: {}", path, e);
+        process::exit(2);
+    });
+
+    let report = Report::run(conformance::parse(&text));
+    print!("{}", report);
+    if custom.is_none() && report.compatibility() < MIN_COMPATIBILITY {
+        eprintln!(
+            "compatibility fell below {}% of the bundled cases",
+            MIN_COMPATIBILITY
+        );
+        process::exit(1);
+    }
+}